@@ -0,0 +1,218 @@
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::Deserialize;
+
+use crate::error::Result;
+use crate::multi_engine::{EngineEvent, EventError, EventSender};
+use crate::params::{self, ParamError};
+
+/// Optional tuning file, looked up in the working directory.
+pub const CONFIG_FILE: &str = "advanced.toml";
+
+/// How often the watcher checks the file for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Accepted values of each setting. TOML allows `nan` and `inf`, so a file
+/// is checked against these before any of it reaches the DSP code.
+const DRIFT_RATE_RANGE: RangeInclusive<f32> = 0.0..=0.01;
+const DRIFT_DECAY_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+const CHORUS_RATE_RANGE: RangeInclusive<f32> = 0.01..=20.0;
+const STEREO_OFFSET_RANGE: RangeInclusive<f32> = -1.0..=1.0;
+const LATE_DELAY_RANGE: RangeInclusive<f32> = 1.0..=500.0;
+const EARLY_TAP_RANGE: RangeInclusive<f32> = 0.0..=500.0;
+const TAP_DECAY_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+
+/// Tuning constants behind the synth's analog character. Every field is
+/// optional in the file and falls back to the built-in value.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdvancedConfig {
+    pub filter: FilterTuning,
+    pub chorus: ChorusTuning,
+    pub reverb: ReverbTuning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterTuning {
+    /// Largest random step of the cutoff drift per sample, as a fraction of the cutoff
+    pub thermal_drift_rate: f32,
+    /// Per-sample pull of the drift back towards the nominal cutoff (0.0-1.0)
+    pub thermal_drift_decay: f32,
+}
+
+impl Default for FilterTuning {
+    fn default() -> Self {
+        Self {
+            thermal_drift_rate: 0.0001,
+            thermal_drift_decay: 0.9999,
+        }
+    }
+}
+
+/// LFO rates in Hz of each chorus mode's voices.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChorusTuning {
+    pub rates_i: [f32; 1],
+    pub rates_ii: [f32; 1],
+    pub rates_iii: [f32; 2],
+    pub rates_iv: [f32; 4],
+    /// How much faster the right channel's LFO runs than the left, in Hz
+    pub stereo_offset: f32,
+}
+
+impl Default for ChorusTuning {
+    fn default() -> Self {
+        Self {
+            rates_i: [0.513],
+            rates_ii: [0.863],
+            rates_iii: [0.513, 0.863],
+            rates_iv: [0.5, 0.75, 1.0, 1.25],
+            stereo_offset: 0.002,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReverbTuning {
+    /// Lengths of the four late-reflection delay lines
+    pub late_delays_ms: [f32; 4],
+    /// Times of the four early-reflection taps
+    pub early_taps_ms: [f32; 4],
+    /// Gain of each early tap relative to the one before it
+    pub early_tap_decay: f32,
+}
+
+impl Default for ReverbTuning {
+    fn default() -> Self {
+        Self {
+            late_delays_ms: [29.0, 37.0, 43.0, 53.0],
+            early_taps_ms: [7.0, 12.243, 15.4443, 23.405],
+            early_tap_decay: 0.7,
+        }
+    }
+}
+
+impl AdvancedConfig {
+    /// Rejects NaN, infinite and out-of-range settings, naming the first bad one.
+    pub fn validate(&self) -> std::result::Result<(), ParamError> {
+        let FilterTuning { thermal_drift_rate, thermal_drift_decay } = self.filter;
+        params::validate("filter.thermal_drift_rate", DRIFT_RATE_RANGE, thermal_drift_rate)?;
+        params::validate("filter.thermal_drift_decay", DRIFT_DECAY_RANGE, thermal_drift_decay)?;
+
+        let chorus = &self.chorus;
+        let rates: [(&'static str, &[f32]); 4] = [
+            ("chorus.rates_i", &chorus.rates_i),
+            ("chorus.rates_ii", &chorus.rates_ii),
+            ("chorus.rates_iii", &chorus.rates_iii),
+            ("chorus.rates_iv", &chorus.rates_iv),
+        ];
+        for (name, rates) in rates {
+            for &rate in rates {
+                params::validate(name, CHORUS_RATE_RANGE, rate)?;
+            }
+        }
+        params::validate("chorus.stereo_offset", STEREO_OFFSET_RANGE, chorus.stereo_offset)?;
+
+        let reverb = &self.reverb;
+        for &ms in &reverb.late_delays_ms {
+            params::validate("reverb.late_delays_ms", LATE_DELAY_RANGE, ms)?;
+        }
+        for &ms in &reverb.early_taps_ms {
+            params::validate("reverb.early_taps_ms", EARLY_TAP_RANGE, ms)?;
+        }
+        params::validate("reverb.early_tap_decay", TAP_DECAY_RANGE, reverb.early_tap_decay)?;
+        Ok(())
+    }
+}
+
+/// Tracks the tuning file so it is only parsed again after it changes.
+pub struct ConfigWatch {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatch {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            modified: None,
+        }
+    }
+
+    /// Loads the file if it appeared or changed since the last poll. A missing
+    /// file is not an error, and a broken one is reported and skipped so the
+    /// previous tuning stays in effect.
+    pub fn poll(&mut self) -> Option<AdvancedConfig> {
+        let modified = std::fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        match self.load() {
+            Ok(config) => {
+                println!("Loaded tuning from {}", self.path.display());
+                Some(config)
+            }
+            Err(err) => {
+                eprintln!("Ignoring {}: {}", self.path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// Reads and validates the file, so hot reloading and the safe mode
+    /// check both reject settings the DSP code can't use.
+    pub fn load(&self) -> Result<AdvancedConfig> {
+        let text = std::fs::read_to_string(&self.path)?;
+        let config: AdvancedConfig = toml::from_str(&text)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Hot reloading: keeps polling on a background thread and queues every
+    /// new tuning for the audio thread. A tuning the full queue turned away
+    /// is loaded again on the next poll.
+    pub fn spawn(mut self, events: EventSender) {
+        thread::spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            if let Some(config) = self.poll() {
+                match events.try_send(EngineEvent::SetAdvanced(config)) {
+                    Ok(()) => {}
+                    Err(EventError::Stopped) => break,
+                    Err(err) => {
+                        eprintln!("Could not apply {}: {}", self.path.display(), err);
+                        self.modified = None;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        assert_eq!(AdvancedConfig::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_nan_and_out_of_range_values() {
+        let config: AdvancedConfig = toml::from_str("[filter]\nthermal_drift_rate = nan").unwrap();
+        assert!(matches!(config.validate(), Err(ParamError::NotFinite { name: "filter.thermal_drift_rate", .. })));
+
+        let config: AdvancedConfig = toml::from_str("[chorus]\nrates_iv = [0.5, 0.75, inf, 1.25]").unwrap();
+        assert!(matches!(config.validate(), Err(ParamError::NotFinite { name: "chorus.rates_iv", .. })));
+
+        let config: AdvancedConfig = toml::from_str("[reverb]\nlate_delays_ms = [29.0, 37.0, 43.0, 900.0]").unwrap();
+        assert!(matches!(config.validate(), Err(ParamError::OutOfRange { name: "reverb.late_delays_ms", .. })));
+    }
+}
@@ -1,7 +1,10 @@
 use std::f32::consts::PI;
 use rand::Rng;
-use std::sync::Arc;
-use std::sync::Mutex;
+use crate::advanced::ChorusTuning;
+use crate::effects::{ChorusLfo, ChorusMode};
+
+/// Most voices any mode uses (mode IV)
+const MAX_VOICES: usize = 4;
 
 pub struct Chorus {
     buffer_left: Vec<f32>,
@@ -9,27 +12,21 @@ pub struct Chorus {
     index: usize,
     size: usize,
     mode: ChorusMode,
+    lfo: ChorusLfo,
+    tuning: ChorusTuning,
     sample_rate: f32,
     low_pass_filter: LowPassFilter,
     high_pass_filter: HighPassFilter,
-    noise_generator: Arc<Mutex<NoiseGenerator>>,
+    noise_generator: NoiseGenerator,
     saturation: Saturation,
     feedback: f32,
-    voices: Vec<Voice>,
+    /// Fixed slots the modes fill in place, so switching mode on the audio
+    /// thread never allocates. Only the first `voice_count` are in use.
+    voices: [Voice; MAX_VOICES],
+    voice_count: usize,
     rate: f32,
     depth: f32,
     wet_dry_mix: f32,
-    prev_delay_left: Vec<f32>,
-    prev_delay_right: Vec<f32>,
-}
-
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum ChorusMode {
-    Off,
-    I,
-    II,
-    III,
-    IV,
 }
 
 struct LowPassFilter {
@@ -44,9 +41,10 @@ struct HighPassFilter {
     cutoff: f32,
 }
 
+/// Random source for the sample-and-hold LFO
 struct NoiseGenerator {
-    level: f32,
-    prev: f32,
+    /// xorshift32 state, owned so generating noise never locks or touches thread-local RNGs
+    rng: u32,
 }
 
 struct Saturation {
@@ -60,6 +58,12 @@ struct Voice {
     rate_right: f32,
     depth: f32,
     smooth_depth: f32,
+    /// Sample-and-hold targets, picked each time the phase wraps (0.0-1.0)
+    held_left: f32,
+    held_right: f32,
+    /// Glided sample-and-hold values
+    glide_left: f32,
+    glide_right: f32,
 }
 
 impl Chorus {
@@ -72,74 +76,80 @@ impl Chorus {
             index: 0,
             size,
             mode: ChorusMode::Off,
+            lfo: ChorusLfo::Sine,
+            tuning: ChorusTuning::default(),
             sample_rate,
             low_pass_filter: LowPassFilter::new(sample_rate),
             high_pass_filter: HighPassFilter::new(sample_rate),
-            noise_generator: Arc::new(Mutex::new(NoiseGenerator::new())),
+            noise_generator: NoiseGenerator::new(),
             saturation: Saturation::new(),
             feedback: 0.25,
             rate: 0.5,
             depth: 0.5,
-            voices: vec![
+            voices: [
                 Voice::new(0.513, 0.515, 0.7),
                 Voice::new(0.75, 0.753, 0.6),
                 Voice::new(0.95, 0.953, 0.5),
+                Voice::new(1.25, 1.253, 0.4),
             ],
+            voice_count: 3,
             wet_dry_mix: 0.5,
-            prev_delay_left: vec![0.0; 3],
-            prev_delay_right: vec![0.0; 3],
         }
     }
 
+    fn active_voices(&mut self) -> &mut [Voice] {
+        &mut self.voices[..self.voice_count]
+    }
+
     pub fn set_rate(&mut self, rate: f32) {
         self.rate = rate.clamp(0.1, 10.0);
-        for voice in &mut self.voices {
-            voice.rate_left = self.rate * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
-            voice.rate_right = self.rate * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
+        let rate = self.rate;
+        for voice in self.active_voices() {
+            voice.rate_left = rate * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
+            voice.rate_right = rate * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
         }
     }
 
     pub fn set_depth(&mut self, depth: f32) {
         self.depth = depth.clamp(0.0, 1.0);
-        for voice in &mut self.voices {
-            voice.depth = self.depth * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
+        let depth = self.depth;
+        for voice in self.active_voices() {
+            voice.depth = depth * (0.9 + rand::thread_rng().gen::<f32>() * 0.2);
         }
     }
 
+    pub fn set_lfo(&mut self, shape: ChorusLfo) {
+        self.lfo = shape;
+    }
+
+    /// Replaces the voice rates of every mode and rebuilds the current one.
+    pub fn set_tuning(&mut self, tuning: &ChorusTuning) {
+        self.tuning = *tuning;
+        self.set_mode(self.mode);
+    }
+
     pub fn set_mode(&mut self, mode: ChorusMode) {
         self.mode = mode;
-        match mode {
-            ChorusMode::Off => {
-                self.voices.clear();
-                self.wet_dry_mix = 0.0;
-            },
-            ChorusMode::I => {
-                self.voices = vec![Voice::new(0.513, 0.515, 0.00535)];
-                self.wet_dry_mix = 0.5;
-            },
-            ChorusMode::II => {
-                self.voices = vec![Voice::new(0.863, 0.865, 0.00535)];
-                self.wet_dry_mix = 0.8;
-            },
-            ChorusMode::III => {
-                self.voices = vec![
-                    Voice::new(0.513, 0.515, 0.0037),
-                    Voice::new(0.863, 0.865, 0.0037),
-                ];
-                self.wet_dry_mix = 0.5;
-            },
-            ChorusMode::IV => {
-                self.voices = vec![
-                    Voice::new(0.5, 0.502, 0.007),
-                    Voice::new(0.75, 0.752, 0.006),
-                    Voice::new(1.0, 1.002, 0.005),
-                    Voice::new(1.25, 1.252, 0.004),
-                ];
-                self.wet_dry_mix = 0.6;
-            },
+        let tuning = self.tuning;
+        // Rate and depth of each voice the mode uses
+        let (voices, wet_dry_mix): (&[(f32, f32)], f32) = match mode {
+            ChorusMode::Off => (&[], 0.0),
+            ChorusMode::I => (&[(tuning.rates_i[0], 0.00535)], 0.5),
+            ChorusMode::II => (&[(tuning.rates_ii[0], 0.00535)], 0.8),
+            ChorusMode::III => (&[(tuning.rates_iii[0], 0.0037), (tuning.rates_iii[1], 0.0037)], 0.5),
+            ChorusMode::IV => (&[
+                (tuning.rates_iv[0], 0.007),
+                (tuning.rates_iv[1], 0.006),
+                (tuning.rates_iv[2], 0.005),
+                (tuning.rates_iv[3], 0.004),
+            ], 0.6),
+        };
+        for (voice, &(rate, depth)) in self.voices.iter_mut().zip(voices) {
+            let rate = rate.clamp(0.01, 20.0);
+            voice.reset(rate, rate + tuning.stereo_offset, depth, &mut self.noise_generator);
         }
-        self.prev_delay_left = vec![0.0; self.voices.len()];
-        self.prev_delay_right = vec![0.0; self.voices.len()];
+        self.voice_count = voices.len();
+        self.wet_dry_mix = wet_dry_mix;
     }
 
 
@@ -165,10 +175,6 @@ impl Chorus {
 
         let (left_output, right_output) = self.calculate_delay_samples(input_with_feedback_left, input_with_feedback_right);
 
-        let noise = self.noise_generator.lock().unwrap().generate();
-        let left_output = left_output + noise;
-        let right_output = right_output + noise;
-
         let left_output = self.saturation.process(left_output);
         let right_output = self.saturation.process(right_output);
 
@@ -184,18 +190,39 @@ impl Chorus {
         let mut left_output = 0.0;
         let mut right_output = 0.0;
 
-        for voice in &mut self.voices {
+        for voice in &mut self.voices[..self.voice_count] {
             voice.phase_left += voice.rate_left / self.sample_rate;
             voice.phase_right += voice.rate_right / self.sample_rate;
-            if voice.phase_left >= 1.0 { voice.phase_left -= 1.0; }
-            if voice.phase_right >= 1.0 { voice.phase_right -= 1.0; }
+            if voice.phase_left >= 1.0 {
+                voice.phase_left -= 1.0;
+                voice.held_left = self.noise_generator.next_random() * 0.5 + 0.5;
+            }
+            if voice.phase_right >= 1.0 {
+                voice.phase_right -= 1.0;
+                voice.held_right = self.noise_generator.next_random() * 0.5 + 0.5;
+            }
 
             voice.smooth_depth += (voice.depth - voice.smooth_depth) * 0.001;
 
-            let lfo_left = ((2.0 * PI * voice.phase_left).sin() * 0.51 + 0.5) * 0.5 +
-                           ((2.0 * PI * voice.phase_left * 1.101).sin() * 0.5 + 0.5) * 0.5;
-            let lfo_right = ((2.0 * PI * voice.phase_right).sin() * 0.5 + 0.51) * 0.5 +
-                            ((2.0 * PI * voice.phase_right * 1.1).sin() * 0.5 + 0.5) * 0.5;
+            // Every shape is scaled to 0.0-1.0 of the voice's depth
+            let (lfo_left, lfo_right) = match self.lfo {
+                ChorusLfo::Sine => (
+                    ((2.0 * PI * voice.phase_left).sin() * 0.51 + 0.5) * 0.5 +
+                    ((2.0 * PI * voice.phase_left * 1.101).sin() * 0.5 + 0.5) * 0.5,
+                    ((2.0 * PI * voice.phase_right).sin() * 0.5 + 0.51) * 0.5 +
+                    ((2.0 * PI * voice.phase_right * 1.1).sin() * 0.5 + 0.5) * 0.5,
+                ),
+                ChorusLfo::Triangle => (
+                    1.0 - (2.0 * voice.phase_left - 1.0).abs(),
+                    1.0 - (2.0 * voice.phase_right - 1.0).abs(),
+                ),
+                ChorusLfo::Ramp => (voice.phase_left, voice.phase_right),
+                ChorusLfo::SampleAndHold => {
+                    voice.glide_left += (voice.held_left - voice.glide_left) * 0.002;
+                    voice.glide_right += (voice.held_right - voice.glide_right) * 0.002;
+                    (voice.glide_left, voice.glide_right)
+                }
+            };
 
             let delay_left = (voice.smooth_depth * self.sample_rate * lfo_left).min(self.size as f32 - 1.0);
             let delay_right = (voice.smooth_depth * self.sample_rate * lfo_right).min(self.size as f32 - 1.0);
@@ -224,9 +251,9 @@ impl Chorus {
             right_output += sample_right;
         }
 
-        if !self.voices.is_empty() {
-            left_output = left_output / self.voices.len() as f32 + input_left * 0.5;
-            right_output = right_output / self.voices.len() as f32 + input_right * 0.5;
+        if self.voice_count > 0 {
+            left_output = left_output / self.voice_count as f32 + input_left * 0.5;
+            right_output = right_output / self.voice_count as f32 + input_right * 0.5;
         } else {
             left_output = input_left;
             right_output = input_right;
@@ -284,17 +311,16 @@ impl HighPassFilter {
 impl NoiseGenerator {
     fn new() -> Self {
         Self {
-            level: 0.0005,
-            prev: 0.0,
+            rng: rand::thread_rng().gen::<u32>().max(1),
         }
     }
 
-    fn generate(&mut self) -> f32 {
-        let mut rng = rand::thread_rng();
-        let new_noise = rng.gen_range(-self.level..self.level);
-        let output = (self.prev + new_noise) * 0.5;
-        self.prev = new_noise;
-        output
+    fn next_random(&mut self) -> f32 {
+        // xorshift32, uniform in -1.0..1.0
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
     }
 }
 
@@ -319,6 +345,27 @@ impl Voice {
             rate_right,
             depth,
             smooth_depth: depth,
+            held_left: 0.5,
+            held_right: 0.5,
+            glide_left: 0.5,
+            glide_right: 0.5,
         }
     }
+
+    /// Retunes the slot for a new mode, with fresh random phases drawn from
+    /// the chorus's own generator rather than a thread-local RNG.
+    fn reset(&mut self, rate_left: f32, rate_right: f32, depth: f32, noise: &mut NoiseGenerator) {
+        *self = Self {
+            phase_left: noise.next_random() * 0.5 + 0.5,
+            phase_right: noise.next_random() * 0.5 + 0.5,
+            rate_left,
+            rate_right,
+            depth,
+            smooth_depth: depth,
+            held_left: 0.5,
+            held_right: 0.5,
+            glide_left: 0.5,
+            glide_right: 0.5,
+        };
+    }
 }
\ No newline at end of file
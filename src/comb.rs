@@ -0,0 +1,97 @@
+use crate::oscillator::Oscillator;
+
+/// Lowest frequency the comb can be tuned to, which sets its delay line length
+const MIN_FREQUENCY: f32 = 20.0;
+/// Note the tuning is set for when keyboard tracking is on (middle C)
+const TRACKING_CENTER_NOTE: u8 = 60;
+/// Feedback is kept below unity so the comb always decays
+const MAX_FEEDBACK: f32 = 0.98;
+
+/// Feedback comb filter for metallic and flanged tones.
+///
+/// The input is fed back through a delay of one period of the tuned frequency,
+/// putting resonant peaks at every harmonic of it. Negative feedback moves
+/// the peaks to the odd harmonics of half the frequency for a hollower sound.
+pub struct CombFilter {
+    sample_rate: f32,
+    buffer: Vec<f32>,
+    position: usize,
+    /// Tuned frequency in Hz before tracking and modulation
+    frequency: f32,
+    /// Factor applied to the frequency by modulation, 1.0 when unmodulated
+    modulation: f32,
+    /// How far the tuning follows the played note (0.0-1.0)
+    key_tracking: f32,
+    /// Note the tuning follows
+    note: u8,
+    /// Factor applied to the frequency by keyboard tracking
+    tracking_ratio: f32,
+    /// -0.98 to 0.98
+    feedback: f32,
+}
+
+impl CombFilter {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            buffer: vec![0.0; (sample_rate / MIN_FREQUENCY) as usize + 2],
+            position: 0,
+            frequency: 440.0,
+            modulation: 1.0,
+            key_tracking: 0.0,
+            note: TRACKING_CENTER_NOTE,
+            tracking_ratio: 1.0,
+            feedback: 0.5,
+        }
+    }
+
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+    }
+
+    /// Shifts the tuning by `octaves`, e.g. from an LFO.
+    pub fn set_modulation(&mut self, octaves: f32) {
+        self.modulation = 2.0_f32.powf(octaves);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK);
+    }
+
+    pub fn set_key_tracking(&mut self, amount: f32) {
+        self.key_tracking = amount.clamp(0.0, 1.0);
+        self.update_tracking();
+    }
+
+    /// Sets the note the tuning tracks. With full tracking the comb plays the
+    /// tuned frequency at middle C and follows the keyboard from there.
+    pub fn set_note(&mut self, note: u8) {
+        self.note = note;
+        self.update_tracking();
+    }
+
+    fn update_tracking(&mut self) {
+        let ratio = Oscillator::note_to_frequency(self.note) / Oscillator::note_to_frequency(TRACKING_CENTER_NOTE);
+        self.tracking_ratio = ratio.powf(self.key_tracking);
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let max_delay = (self.buffer.len() - 2) as f32;
+        let frequency = (self.frequency * self.modulation * self.tracking_ratio).min(self.sample_rate * 0.5);
+        let delay = (self.sample_rate / frequency).clamp(1.0, max_delay);
+
+        // Linear interpolation between the two samples around the delay
+        let len = self.buffer.len();
+        let read = self.position as f32 + len as f32 - delay;
+        let index = read as usize;
+        let fraction = read - index as f32;
+        let delayed = self.buffer[index % len] * (1.0 - fraction) + self.buffer[(index + 1) % len] * fraction;
+
+        let output = input + self.feedback * delayed;
+        self.buffer[self.position] = output;
+        self.position = (self.position + 1) % len;
+
+        // Scaled so the resonant peaks sit at unity whatever the feedback
+        output * (1.0 - self.feedback.abs())
+    }
+}
@@ -0,0 +1,201 @@
+use std::f32::consts::PI;
+use crate::tempo::NoteDivision;
+
+/// Longest delay time in seconds. Synced times beyond it are shortened to fit.
+pub const MAX_DELAY_TIME: f32 = 2.0;
+/// Seconds a time change takes to settle, gliding the repeats' pitch like a
+/// tape delay instead of clicking
+const TIME_SMOOTHING: f32 = 0.05;
+
+/// One channel's delay line, with the filters its repeats pass through on
+/// the way back into the loop.
+struct DelayLine {
+    buffer: Vec<f32>,
+    write: usize,
+    /// Delay in samples the line is gliding to, and how far it still has to go
+    target: f32,
+    glide: f32,
+    /// One-pole filter states of the loop's low cut and high cut
+    low_cut: f32,
+    high_cut: f32,
+}
+
+impl DelayLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len],
+            write: 0,
+            target: 1.0,
+            glide: 0.0,
+            low_cut: 0.0,
+            high_cut: 0.0,
+        }
+    }
+
+    /// Moves the delay to `target` samples, gliding from where it is now.
+    fn set_target(&mut self, target: f32) {
+        self.glide += self.target - target;
+        self.target = target;
+    }
+
+    /// The repeat due now, read between samples so gliding times stay smooth.
+    /// `decay` shrinks the remaining glide each sample.
+    fn read(&mut self, decay: f32) -> f32 {
+        // Decaying the remainder, rather than stepping the delay towards the
+        // target, never stalls short of it on f32 rounding
+        self.glide *= decay;
+        if self.glide.abs() < 1e-4 {
+            self.glide = 0.0;
+        }
+        let delay = (self.target + self.glide).max(1.0);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let len = self.buffer.len();
+        let newer = self.buffer[(self.write + len - whole) % len];
+        let older = self.buffer[(self.write + len - whole - 1) % len];
+        newer + (older - newer) * fraction
+    }
+
+    /// Runs a repeat through the loop filters, thinning the lows and darkening
+    /// the highs a little more with every pass.
+    fn filter(&mut self, input: f32, low_cut: f32, high_cut: f32) -> f32 {
+        self.low_cut += (input - self.low_cut) * low_cut;
+        self.high_cut += (input - self.low_cut - self.high_cut) * high_cut;
+        self.high_cut
+    }
+
+    fn write(&mut self, input: f32) {
+        self.buffer[self.write] = input;
+        self.write = (self.write + 1) % self.buffer.len();
+    }
+}
+
+/// Stereo feedback delay with independent left and right times, filtering
+/// in the loop and a ping-pong mode that bounces repeats between the sides.
+pub struct Delay {
+    sample_rate: f32,
+    lines: [DelayLine; 2],
+    /// Free times of the left and right repeats in seconds
+    times: [f32; 2],
+    /// Note lengths that replace the free times when set
+    syncs: [Option<NoteDivision>; 2],
+    /// Tempo in BPM the synced times follow
+    tempo: f32,
+    /// Level of each repeat relative to the one before (0.0-0.95)
+    feedback: f32,
+    /// One-pole coefficients of the loop's low cut and high cut
+    low_cut: f32,
+    high_cut: f32,
+    wet: f32,
+    ping_pong: bool,
+    /// Per-sample decay of the time glide
+    glide_decay: f32,
+}
+
+impl Delay {
+    pub fn new(sample_rate: f32) -> Self {
+        let len = (MAX_DELAY_TIME * sample_rate) as usize + 2;
+        let mut delay = Self {
+            sample_rate,
+            lines: [DelayLine::new(len), DelayLine::new(len)],
+            times: [0.375, 0.5],
+            syncs: [None; 2],
+            tempo: 120.0,
+            feedback: 0.35,
+            low_cut: 0.0,
+            high_cut: 1.0,
+            wet: 0.0,
+            ping_pong: false,
+            glide_decay: (-1.0 / (TIME_SMOOTHING * sample_rate)).exp(),
+        };
+        delay.set_low_cut(100.0);
+        delay.set_high_cut(6000.0);
+        delay.update_times();
+        // Start at the set times rather than gliding up to them
+        for line in &mut delay.lines {
+            line.glide = 0.0;
+        }
+        delay
+    }
+
+    /// Sets the free time of one side (0 left, 1 right) in seconds.
+    pub fn set_time(&mut self, channel: usize, seconds: f32) {
+        if let Some(time) = self.times.get_mut(channel) {
+            *time = seconds;
+            self.update_times();
+        }
+    }
+
+    /// Locks one side's time to a note length at the current tempo, or back
+    /// to its free time with `None`.
+    pub fn set_sync(&mut self, channel: usize, sync: Option<NoteDivision>) {
+        if let Some(slot) = self.syncs.get_mut(channel) {
+            *slot = sync;
+            self.update_times();
+        }
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo = bpm.max(1.0);
+        self.update_times();
+    }
+
+    fn update_times(&mut self) {
+        for ((line, &time), sync) in self.lines.iter_mut().zip(&self.times).zip(&self.syncs) {
+            let seconds = match sync {
+                Some(division) => division.beats() * 60.0 / self.tempo,
+                None => time,
+            };
+            line.set_target((seconds.min(MAX_DELAY_TIME) * self.sample_rate).max(1.0));
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0.0, 0.95);
+    }
+
+    /// Corner of the high-pass filter in the feedback loop, in Hz.
+    pub fn set_low_cut(&mut self, frequency: f32) {
+        self.low_cut = self.one_pole(frequency);
+    }
+
+    /// Corner of the low-pass filter in the feedback loop, in Hz.
+    pub fn set_high_cut(&mut self, frequency: f32) {
+        self.high_cut = self.one_pole(frequency);
+    }
+
+    fn one_pole(&self, frequency: f32) -> f32 {
+        let frequency = frequency.clamp(1.0, self.sample_rate * 0.45);
+        1.0 - (-2.0 * PI * frequency / self.sample_rate).exp()
+    }
+
+    pub fn set_wet(&mut self, wet: f32) {
+        self.wet = wet.clamp(0.0, 1.0);
+    }
+
+    pub fn get_wet(&self) -> f32 {
+        self.wet
+    }
+
+    pub fn set_ping_pong(&mut self, enabled: bool) {
+        self.ping_pong = enabled;
+    }
+
+    /// Returns the repeats alone (left, right), for the caller to mix with the input.
+    pub fn process(&mut self, input_left: f32, input_right: f32) -> (f32, f32) {
+        let left = self.lines[0].read(self.glide_decay);
+        let right = self.lines[1].read(self.glide_decay);
+        let feedback_left = self.lines[0].filter(left, self.low_cut, self.high_cut) * self.feedback;
+        let feedback_right = self.lines[1].filter(right, self.low_cut, self.high_cut) * self.feedback;
+
+        if self.ping_pong {
+            // The mono input starts on the left and every repeat crosses over
+            self.lines[0].write((input_left + input_right) * 0.5 + feedback_right);
+            self.lines[1].write(feedback_left);
+        } else {
+            self.lines[0].write(input_left + feedback_left);
+            self.lines[1].write(input_right + feedback_right);
+        }
+        (left, right)
+    }
+}
@@ -1,11 +1,300 @@
+use crate::advanced::{ChorusTuning, ReverbTuning};
+#[cfg(feature = "reverb")]
+use crate::reverb::Reverb;
+#[cfg(feature = "chorus")]
+use crate::chorus::Chorus;
+#[cfg(feature = "delay")]
+use crate::delay::Delay;
+#[cfg(feature = "flanger")]
+use crate::flanger::Flanger;
+use crate::tempo::NoteDivision;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChorusMode {
+    Off,
+    I,
+    II,
+    III,
+    IV,
+}
+
+/// Shape of the chorus delay modulation.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ChorusLfo {
+    /// Two slightly detuned sines, the classic chorus sweep
+    Sine,
+    Triangle,
+    /// Rising sawtooth, for a repeating flanger-style sweep
+    Ramp,
+    /// A new random delay every cycle, glided to avoid zipper noise
+    SampleAndHold,
+}
+
+impl ChorusLfo {
+    pub const ALL: [ChorusLfo; 4] = [ChorusLfo::Sine, ChorusLfo::Triangle, ChorusLfo::Ramp, ChorusLfo::SampleAndHold];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ChorusLfo::Sine => "Sine",
+            ChorusLfo::Triangle => "Triangle",
+            ChorusLfo::Ramp => "Ramp",
+            ChorusLfo::SampleAndHold => "S&H",
+        }
+    }
+}
+
+/// Master effect chain shared by every part: flanger, then delay, then
+/// reverb, then chorus.
+///
+/// Each effect can be compiled out with its cargo feature. A missing effect
+/// passes audio through unchanged and its setters do nothing.
 pub struct Effects {
-    // TODO: Add effects parameters
+    #[cfg(feature = "flanger")]
+    flanger: Flanger,
+    #[cfg(feature = "delay")]
+    delay: Delay,
+    #[cfg(feature = "reverb")]
+    reverb: Reverb,
+    #[cfg(feature = "chorus")]
+    chorus: Chorus,
+    /// Signal between the reverb and the chorus for the last processed sample
+    post_reverb: (f32, f32),
 }
 
 impl Effects {
-    pub fn new() -> Self {
-        Self {}
+    #[cfg_attr(not(any(feature = "reverb", feature = "chorus", feature = "delay", feature = "flanger")), allow(unused_variables))]
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            #[cfg(feature = "flanger")]
+            flanger: Flanger::new(sample_rate),
+            #[cfg(feature = "delay")]
+            delay: Delay::new(sample_rate),
+            #[cfg(feature = "reverb")]
+            reverb: Reverb::new(sample_rate),
+            #[cfg(feature = "chorus")]
+            chorus: Chorus::new(sample_rate),
+            post_reverb: (0.0, 0.0),
+        }
+    }
+
+    pub fn process(&mut self, input_left: f32, input_right: f32) -> (f32, f32) {
+        let (left, right) = (input_left, input_right);
+        #[cfg(feature = "flanger")]
+        let (left, right) = self.flanger.process(left, right);
+        #[cfg(feature = "delay")]
+        let (left, right) = self.apply_delay(left, right);
+        #[cfg(feature = "reverb")]
+        let (left, right) = self.apply_reverb(left, right);
+        self.post_reverb = (left, right);
+        #[cfg(feature = "chorus")]
+        let (left, right) = self.apply_chorus(left, right);
+        (left, right)
+    }
+
+    /// Output of the reverb stage for the last processed sample (left, right)
+    pub fn post_reverb(&self) -> (f32, f32) {
+        self.post_reverb
+    }
+
+    #[cfg(feature = "delay")]
+    fn apply_delay(&mut self, input_left: f32, input_right: f32) -> (f32, f32) {
+        let (delay_left, delay_right) = self.delay.process(input_left, input_right);
+
+        // Mix dry and delayed signals
+        let wet_amount = self.delay.get_wet();
+        let left = input_left * (1.0 - wet_amount) + delay_left * wet_amount;
+        let right = input_right * (1.0 - wet_amount) + delay_right * wet_amount;
+        (left, right)
+    }
+
+    #[cfg(feature = "reverb")]
+    fn apply_reverb(&mut self, input_left: f32, input_right: f32) -> (f32, f32) {
+        let (reverb_left, reverb_right) = self.reverb.process(input_left, input_right);
+
+        // Mix dry and reverb signals
+        let wet_amount = self.reverb.get_wet();
+        let left = input_left * (1.0 - wet_amount) + reverb_left * wet_amount;
+        let right = input_right * (1.0 - wet_amount) + reverb_right * wet_amount;
+        (left, right)
+    }
+
+    #[cfg(feature = "chorus")]
+    fn apply_chorus(&mut self, left: f32, right: f32) -> (f32, f32) {
+        let (chorus_left, chorus_right) = self.chorus.process(left, right);
+
+        // Mix reverb and chorus
+        let chorus_mix = 0.8;
+        let left = left * (1.0 - chorus_mix) + chorus_left * chorus_mix;
+        let right = right * (1.0 - chorus_mix) + chorus_right * chorus_mix;
+        (left, right)
+    }
+
+    #[cfg(feature = "flanger")]
+    pub fn set_flanger_rate(&mut self, rate: f32) {
+        self.flanger.set_rate(rate);
+    }
+
+    #[cfg(feature = "flanger")]
+    pub fn set_flanger_depth(&mut self, depth: f32) {
+        self.flanger.set_depth(depth);
+    }
+
+    #[cfg(feature = "flanger")]
+    pub fn set_flanger_feedback(&mut self, feedback: f32) {
+        self.flanger.set_feedback(feedback);
+    }
+
+    #[cfg(feature = "flanger")]
+    pub fn set_flanger_mix(&mut self, mix: f32) {
+        self.flanger.set_mix(mix);
+    }
+
+    #[cfg(feature = "flanger")]
+    pub fn set_flanger_through_zero(&mut self, enabled: bool) {
+        self.flanger.set_through_zero(enabled);
+    }
+
+    #[cfg(not(feature = "flanger"))]
+    pub fn set_flanger_rate(&mut self, _rate: f32) {}
+
+    #[cfg(not(feature = "flanger"))]
+    pub fn set_flanger_depth(&mut self, _depth: f32) {}
+
+    #[cfg(not(feature = "flanger"))]
+    pub fn set_flanger_feedback(&mut self, _feedback: f32) {}
+
+    #[cfg(not(feature = "flanger"))]
+    pub fn set_flanger_mix(&mut self, _mix: f32) {}
+
+    #[cfg(not(feature = "flanger"))]
+    pub fn set_flanger_through_zero(&mut self, _enabled: bool) {}
+
+    /// Sets the free delay time of one side (0 left, 1 right) in seconds.
+    #[cfg(feature = "delay")]
+    pub fn set_delay_time(&mut self, channel: usize, seconds: f32) {
+        self.delay.set_time(channel, seconds);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_sync(&mut self, channel: usize, sync: Option<NoteDivision>) {
+        self.delay.set_sync(channel, sync);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_tempo(&mut self, bpm: f32) {
+        self.delay.set_tempo(bpm);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_feedback(&mut self, feedback: f32) {
+        self.delay.set_feedback(feedback);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_low_cut(&mut self, frequency: f32) {
+        self.delay.set_low_cut(frequency);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_high_cut(&mut self, frequency: f32) {
+        self.delay.set_high_cut(frequency);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_wet(&mut self, wet: f32) {
+        self.delay.set_wet(wet);
+    }
+
+    #[cfg(feature = "delay")]
+    pub fn set_delay_ping_pong(&mut self, enabled: bool) {
+        self.delay.set_ping_pong(enabled);
+    }
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_time(&mut self, _channel: usize, _seconds: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_sync(&mut self, _channel: usize, _sync: Option<NoteDivision>) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_tempo(&mut self, _bpm: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_feedback(&mut self, _feedback: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_low_cut(&mut self, _frequency: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_high_cut(&mut self, _frequency: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_wet(&mut self, _wet: f32) {}
+
+    #[cfg(not(feature = "delay"))]
+    pub fn set_delay_ping_pong(&mut self, _enabled: bool) {}
+
+    #[cfg(feature = "reverb")]
+    pub fn set_reverb_decay(&mut self, decay: f32) {
+        self.reverb.set_decay(decay.clamp(0.0, 0.99));
     }
 
-    // TODO: Implement effects processing
+    #[cfg(feature = "reverb")]
+    pub fn set_reverb_wet(&mut self, wet: f32) {
+        self.reverb.set_wet(wet.clamp(0.0, 1.0));
+    }
+
+    #[cfg(feature = "reverb")]
+    pub fn set_reverb_tuning(&mut self, tuning: &ReverbTuning) {
+        self.reverb.set_tuning(tuning);
+    }
+
+    #[cfg(not(feature = "reverb"))]
+    pub fn set_reverb_decay(&mut self, _decay: f32) {}
+
+    #[cfg(not(feature = "reverb"))]
+    pub fn set_reverb_wet(&mut self, _wet: f32) {}
+
+    #[cfg(not(feature = "reverb"))]
+    pub fn set_reverb_tuning(&mut self, _tuning: &ReverbTuning) {}
+
+    #[cfg(feature = "chorus")]
+    pub fn set_chorus_mode(&mut self, mode: ChorusMode) {
+        self.chorus.set_mode(mode);
+    }
+
+    #[cfg(feature = "chorus")]
+    pub fn set_chorus_lfo(&mut self, shape: ChorusLfo) {
+        self.chorus.set_lfo(shape);
+    }
+
+    #[cfg(feature = "chorus")]
+    pub fn set_chorus_tuning(&mut self, tuning: &ChorusTuning) {
+        self.chorus.set_tuning(tuning);
+    }
+
+    #[cfg(feature = "chorus")]
+    pub fn set_chorus_rate(&mut self, rate: f32) {
+        self.chorus.set_rate(rate);
+    }
+
+    #[cfg(feature = "chorus")]
+    pub fn set_chorus_depth(&mut self, depth: f32) {
+        self.chorus.set_depth(depth);
+    }
+
+    #[cfg(not(feature = "chorus"))]
+    pub fn set_chorus_mode(&mut self, _mode: ChorusMode) {}
+
+    #[cfg(not(feature = "chorus"))]
+    pub fn set_chorus_lfo(&mut self, _shape: ChorusLfo) {}
+
+    #[cfg(not(feature = "chorus"))]
+    pub fn set_chorus_tuning(&mut self, _tuning: &ChorusTuning) {}
+
+    #[cfg(not(feature = "chorus"))]
+    pub fn set_chorus_rate(&mut self, _rate: f32) {}
+
+    #[cfg(not(feature = "chorus"))]
+    pub fn set_chorus_depth(&mut self, _depth: f32) {}
 }
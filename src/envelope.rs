@@ -2,56 +2,254 @@
 
 use std::sync::atomic::{AtomicU32, Ordering};
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EnvelopeStage {
+    /// Fading out quickly before a reset retrigger starts over from silence
+    Fade,
+    /// Waiting before the attack starts
+    Delay,
     Attack,
+    /// Holding at the peak before the decay starts
+    Hold,
     Decay,
     Sustain,
     Release,
     Idle,
 }
 
+impl EnvelopeStage {
+    pub const ALL: [EnvelopeStage; 8] = [
+        EnvelopeStage::Fade,
+        EnvelopeStage::Delay,
+        EnvelopeStage::Attack,
+        EnvelopeStage::Hold,
+        EnvelopeStage::Decay,
+        EnvelopeStage::Sustain,
+        EnvelopeStage::Release,
+        EnvelopeStage::Idle,
+    ];
+}
+
+/// One stage of a note's path through the envelope, for drawing it.
+#[derive(Debug, Clone, Copy)]
+pub struct EnvelopeSegment {
+    pub stage: EnvelopeStage,
+    /// Seconds the stage lasts
+    pub duration: f32,
+    pub start_level: f32,
+    pub end_level: f32,
+    pub curve: EnvelopeCurve,
+}
+
+/// How a note ends once released, as a patch-level character on top of the
+/// release time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReleaseMode {
+    /// Exponential fade over the full release time, like a string left to ring
+    Natural,
+    /// Exponential fade at under a third of the release time, like a damper
+    /// settling on the string
+    Damped,
+    /// Short straight fade to silence, like an organ key letting go
+    Gated,
+}
+
+impl ReleaseMode {
+    pub const ALL: [ReleaseMode; 3] = [ReleaseMode::Natural, ReleaseMode::Damped, ReleaseMode::Gated];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ReleaseMode::Natural => "Natural",
+            ReleaseMode::Damped => "Damped",
+            ReleaseMode::Gated => "Gated",
+        }
+    }
+
+    /// Multiplier applied to the release time
+    fn time_scale(self) -> f32 {
+        match self {
+            ReleaseMode::Natural => 1.0,
+            ReleaseMode::Damped => 0.3,
+            ReleaseMode::Gated => 0.1,
+        }
+    }
+}
+
+/// What a note-on does to an envelope that is already sounding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetriggerMode {
+    /// Fades to silence and starts the attack from zero every time
+    Reset,
+    /// Starts the attack from wherever the level is, so fast repeats don't dip
+    Current,
+    /// Carries on undisturbed while the previous note is still held, and
+    /// retriggers from the current level once it has been released
+    Legato,
+}
+
+impl RetriggerMode {
+    pub const ALL: [RetriggerMode; 3] = [RetriggerMode::Reset, RetriggerMode::Current, RetriggerMode::Legato];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RetriggerMode::Reset => "Reset",
+            RetriggerMode::Current => "Current",
+            RetriggerMode::Legato => "Legato",
+        }
+    }
+}
+
+/// Shape of an envelope segment between its start and end levels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnvelopeCurve {
+    /// Constant rate of change
+    Linear,
+    /// Fast at first and slowing toward the end, like a capacitor charging:
+    /// snappy attacks and percussive decays
+    Exponential,
+    /// Slow at first and speeding up toward the end: swelling attacks and
+    /// decays that hold before falling away
+    Logarithmic,
+}
+
+impl EnvelopeCurve {
+    pub const ALL: [EnvelopeCurve; 3] = [EnvelopeCurve::Linear, EnvelopeCurve::Exponential, EnvelopeCurve::Logarithmic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            EnvelopeCurve::Linear => "Lin",
+            EnvelopeCurve::Exponential => "Exp",
+            EnvelopeCurve::Logarithmic => "Log",
+        }
+    }
+
+    /// Fraction of the way from the start level to the end level after
+    /// `progress` (0.0-1.0) of the segment's time.
+    pub fn shape(self, progress: f32) -> f32 {
+        match self {
+            EnvelopeCurve::Linear => progress,
+            EnvelopeCurve::Exponential => (1.0 - (-CURVE_STEEPNESS * progress).exp()) / (1.0 - (-CURVE_STEEPNESS).exp()),
+            EnvelopeCurve::Logarithmic => ((CURVE_STEEPNESS * progress).exp() - 1.0) / (CURVE_STEEPNESS.exp() - 1.0),
+        }
+    }
+}
+
+/// How strongly the curved segments bend. At 5.0 an exponential segment
+/// covers 92% of its distance in the first half of its time
+const CURVE_STEEPNESS: f32 = 5.0;
+/// Shortest time any attack, decay or release ramp takes, however short it
+/// is set. Anything faster is heard as a click
+const MIN_RAMP_TIME: f32 = 0.003;
+/// Time a reset retrigger takes to fade the old note out
+const RETRIGGER_FADE_TIME: f32 = 0.003;
+/// Furthest full velocity-to-attack shortens the attack of the hardest hit,
+/// and lengthens that of the softest
+const VELOCITY_ATTACK_RANGE: f32 = 4.0;
+
 pub struct Envelope {
+    /// Delay and hold times are 0.0 when the stage is skipped
+    delay: AtomicU32,
     attack: AtomicU32,
+    hold: AtomicU32,
     decay: AtomicU32,
     sustain: AtomicU32,
     release: AtomicU32,
     stage: EnvelopeStage,
     current_level: f32,
     sample_rate: f32,
-    time_in_stage: f32,
+    /// How far through the current segment the envelope is (0.0-1.0)
+    progress: f32,
+    /// Level the current segment started from
+    stage_level: f32,
+    release_mode: ReleaseMode,
+    retrigger_mode: RetriggerMode,
+    attack_curve: EnvelopeCurve,
+    decay_curve: EnvelopeCurve,
+    release_curve: EnvelopeCurve,
+    /// How far velocity scales the peak level (0.0-1.0), 1.0 being fully
+    /// proportional and 0.0 every note at full level
+    velocity_to_level: f32,
+    /// How far velocity speeds up the attack (0.0-1.0)
+    velocity_to_attack: f32,
+    /// Level the attack rises to for the current note
+    peak: f32,
+    /// Factor on the attack time for the current note
+    attack_scale: f32,
 }
 
 impl Envelope {
     pub fn new(sample_rate: f32) -> Self {
         Self {
+            delay: AtomicU32::new(0.0f32.to_bits()),
             attack: AtomicU32::new(0.1f32.to_bits()),
+            hold: AtomicU32::new(0.0f32.to_bits()),
             decay: AtomicU32::new(0.1f32.to_bits()),
             sustain: AtomicU32::new(0.7f32.to_bits()),
             release: AtomicU32::new(0.2f32.to_bits()),
             stage: EnvelopeStage::Idle,
             current_level: 0.0,
             sample_rate,
-            time_in_stage: 0.0,
+            progress: 0.0,
+            stage_level: 0.0,
+            release_mode: ReleaseMode::Natural,
+            retrigger_mode: RetriggerMode::Current,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Exponential,
+            velocity_to_level: 1.0,
+            velocity_to_attack: 0.0,
+            peak: 1.0,
+            attack_scale: 1.0,
         }
     }
 
     pub fn next_sample(&mut self) -> f32 {
         match self.stage {
+            EnvelopeStage::Fade => {
+                self.advance(RETRIGGER_FADE_TIME);
+                self.current_level = self.stage_level * (1.0 - self.progress);
+                if self.progress >= 1.0 {
+                    self.current_level = 0.0;
+                    self.start();
+                }
+            }
+            EnvelopeStage::Delay => {
+                // The level stays where the note found it until the attack
+                let delay_time = f32::from_bits(self.delay.load(Ordering::Relaxed));
+                self.advance(delay_time);
+                if self.progress >= 1.0 {
+                    self.enter(EnvelopeStage::Attack);
+                }
+            }
             EnvelopeStage::Attack => {
-                let attack_time = f32::from_bits(self.attack.load(Ordering::Relaxed));
-                self.current_level += 1.0 / (attack_time * self.sample_rate);
-                if self.current_level >= 1.0 {
-                    self.current_level = 1.0;
-                    self.stage = EnvelopeStage::Decay;
-                    self.time_in_stage = 0.0;
+                // A retrigger moves from the level it caught the note at to the
+                // new peak, in the share of the attack time that distance takes
+                let distance = ((self.peak - self.stage_level).abs() / self.peak.max(0.001)).min(1.0);
+                let attack_time = f32::from_bits(self.attack.load(Ordering::Relaxed)) * self.attack_scale * distance;
+                self.advance(attack_time.max(MIN_RAMP_TIME));
+                self.current_level = self.stage_level + (self.peak - self.stage_level) * self.attack_curve.shape(self.progress);
+                if self.progress >= 1.0 {
+                    self.current_level = self.peak;
+                    if f32::from_bits(self.hold.load(Ordering::Relaxed)) > 0.0 {
+                        self.enter(EnvelopeStage::Hold);
+                    } else {
+                        self.enter(EnvelopeStage::Decay);
+                    }
+                }
+            }
+            EnvelopeStage::Hold => {
+                let hold_time = f32::from_bits(self.hold.load(Ordering::Relaxed));
+                self.advance(hold_time);
+                if self.progress >= 1.0 {
+                    self.enter(EnvelopeStage::Decay);
                 }
             }
             EnvelopeStage::Decay => {
                 let decay_time = f32::from_bits(self.decay.load(Ordering::Relaxed));
-                let sustain_level = f32::from_bits(self.sustain.load(Ordering::Relaxed));
-                self.current_level -= (1.0 - sustain_level) / (decay_time * self.sample_rate);
-                if self.current_level <= sustain_level {
+                let sustain_level = f32::from_bits(self.sustain.load(Ordering::Relaxed)) * self.peak;
+                self.advance(decay_time.max(MIN_RAMP_TIME));
+                self.current_level = self.peak - (self.peak - sustain_level) * self.decay_curve.shape(self.progress);
+                if self.progress >= 1.0 {
                     self.current_level = sustain_level;
                     self.stage = EnvelopeStage::Sustain;
                 }
@@ -60,9 +258,14 @@ impl Envelope {
                 // Do nothing, maintain the sustain level
             }
             EnvelopeStage::Release => {
-                let release_time = f32::from_bits(self.release.load(Ordering::Relaxed));
-                self.current_level -= self.current_level / (release_time * self.sample_rate);
-                if self.current_level < 0.001 {
+                let release_time = f32::from_bits(self.release.load(Ordering::Relaxed)) * self.release_mode.time_scale();
+                self.advance(release_time.max(MIN_RAMP_TIME));
+                let curve = match self.release_mode {
+                    ReleaseMode::Natural | ReleaseMode::Damped => self.release_curve,
+                    ReleaseMode::Gated => EnvelopeCurve::Linear,
+                };
+                self.current_level = self.stage_level * (1.0 - curve.shape(self.progress));
+                if self.progress >= 1.0 || self.current_level < 0.001 {
                     self.current_level = 0.0;
                     self.stage = EnvelopeStage::Idle;
                 }
@@ -71,24 +274,71 @@ impl Envelope {
                 self.current_level = 0.0;
             }
         }
-        self.time_in_stage += 1.0 / self.sample_rate;
         self.current_level
     }
 
-    pub fn note_on(&mut self) {
-        self.stage = EnvelopeStage::Attack;
-        self.time_in_stage = 0.0;
+    /// Moves one sample through a segment lasting `time` seconds.
+    fn advance(&mut self, time: f32) {
+        let samples = time * self.sample_rate;
+        self.progress = if samples > 1.0 { (self.progress + 1.0 / samples).min(1.0) } else { 1.0 };
+    }
+
+    /// Starts a segment from the current level.
+    fn enter(&mut self, stage: EnvelopeStage) {
+        self.stage = stage;
+        self.progress = 0.0;
+        self.stage_level = self.current_level;
+    }
+
+    /// Starts a note. `velocity` (0.0-1.0) sets the peak level and attack
+    /// time as far as the velocity amounts allow.
+    pub fn note_on(&mut self, velocity: f32) {
+        if self.retrigger_mode == RetriggerMode::Legato && !matches!(self.stage, EnvelopeStage::Release | EnvelopeStage::Idle) {
+            return;
+        }
+        self.peak = self.velocity_gain(velocity);
+        self.attack_scale = VELOCITY_ATTACK_RANGE.powf(self.velocity_to_attack * (1.0 - 2.0 * velocity));
+        if self.retrigger_mode == RetriggerMode::Reset && self.current_level > 0.0 {
+            // Dropping straight to zero would click
+            self.enter(EnvelopeStage::Fade);
+        } else {
+            self.start();
+        }
+    }
+
+    /// Starts the delay, or the attack when there is no delay.
+    fn start(&mut self) {
+        if f32::from_bits(self.delay.load(Ordering::Relaxed)) > 0.0 {
+            self.enter(EnvelopeStage::Delay);
+        } else {
+            self.enter(EnvelopeStage::Attack);
+        }
     }
 
     pub fn note_off(&mut self) {
-        self.stage = EnvelopeStage::Release;
-        self.time_in_stage = 0.0;
+        self.enter(EnvelopeStage::Release);
+    }
+
+    /// Silences the envelope immediately, skipping the release stage.
+    pub fn reset(&mut self) {
+        self.stage = EnvelopeStage::Idle;
+        self.current_level = 0.0;
+        self.progress = 0.0;
+        self.stage_level = 0.0;
+    }
+
+    pub fn set_delay(&self, delay: f32) {
+        self.delay.store(delay.to_bits(), Ordering::Relaxed);
     }
 
     pub fn set_attack(&self, attack: f32) {
         self.attack.store(attack.to_bits(), Ordering::Relaxed);
     }
 
+    pub fn set_hold(&self, hold: f32) {
+        self.hold.store(hold.to_bits(), Ordering::Relaxed);
+    }
+
     pub fn set_decay(&self, decay: f32) {
         self.decay.store(decay.to_bits(), Ordering::Relaxed);
     }
@@ -101,6 +351,77 @@ impl Envelope {
         self.release.store(release.to_bits(), Ordering::Relaxed);
     }
 
+    pub fn set_release_mode(&mut self, mode: ReleaseMode) {
+        self.release_mode = mode;
+    }
+
+    pub fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        self.retrigger_mode = mode;
+    }
+
+    pub fn set_velocity_to_level(&mut self, amount: f32) {
+        self.velocity_to_level = amount.clamp(0.0, 1.0);
+    }
+
+    pub fn set_velocity_to_attack(&mut self, amount: f32) {
+        self.velocity_to_attack = amount.clamp(0.0, 1.0);
+    }
+
+    /// Peak level a note of `velocity` (0.0-1.0) reaches.
+    pub fn velocity_gain(&self, velocity: f32) -> f32 {
+        1.0 - self.velocity_to_level * (1.0 - velocity)
+    }
+
+    /// Sets the curve of the attack, decay or release segment. The other
+    /// stages hold a level and have no curve.
+    pub fn set_curve(&mut self, stage: EnvelopeStage, curve: EnvelopeCurve) {
+        match stage {
+            EnvelopeStage::Attack => self.attack_curve = curve,
+            EnvelopeStage::Decay => self.decay_curve = curve,
+            EnvelopeStage::Release => self.release_curve = curve,
+            EnvelopeStage::Fade | EnvelopeStage::Delay | EnvelopeStage::Hold | EnvelopeStage::Sustain | EnvelopeStage::Idle => {}
+        }
+    }
+
+    /// The stages a full-velocity note goes through with the current
+    /// settings, holding the sustain for `sustain_time` seconds. Skipped
+    /// stages are left out and the times are the ones actually played.
+    pub fn outline(&self, sustain_time: f32) -> Vec<EnvelopeSegment> {
+        let time = |value: &AtomicU32| f32::from_bits(value.load(Ordering::Relaxed));
+        let sustain = time(&self.sustain);
+        let release_curve = match self.release_mode {
+            ReleaseMode::Natural | ReleaseMode::Damped => self.release_curve,
+            ReleaseMode::Gated => EnvelopeCurve::Linear,
+        };
+        let segment = |stage, duration, start_level, end_level, curve| EnvelopeSegment { stage, duration, start_level, end_level, curve };
+        [
+            segment(EnvelopeStage::Delay, time(&self.delay), 0.0, 0.0, EnvelopeCurve::Linear),
+            segment(EnvelopeStage::Attack, time(&self.attack).max(MIN_RAMP_TIME), 0.0, 1.0, self.attack_curve),
+            segment(EnvelopeStage::Hold, time(&self.hold), 1.0, 1.0, EnvelopeCurve::Linear),
+            segment(EnvelopeStage::Decay, time(&self.decay).max(MIN_RAMP_TIME), 1.0, sustain, self.decay_curve),
+            segment(EnvelopeStage::Sustain, sustain_time, sustain, sustain, EnvelopeCurve::Linear),
+            segment(EnvelopeStage::Release, (time(&self.release) * self.release_mode.time_scale()).max(MIN_RAMP_TIME), sustain, 0.0, release_curve),
+        ]
+        .into_iter()
+        .filter(|segment| segment.duration > 0.0)
+        .collect()
+    }
+
+    /// The stage the envelope is in and how far through it (0.0-1.0), or
+    /// `None` when idle. The sustain stage has no end and reports 0.0.
+    pub fn position(&self) -> Option<(EnvelopeStage, f32)> {
+        match self.stage {
+            EnvelopeStage::Idle => None,
+            EnvelopeStage::Sustain => Some((EnvelopeStage::Sustain, 0.0)),
+            stage => Some((stage, self.progress)),
+        }
+    }
+
+    /// Current output level, without advancing the envelope
+    pub fn level(&self) -> f32 {
+        self.current_level
+    }
+
     pub fn is_idle(&self) -> bool {
         self.stage == EnvelopeStage::Idle
     }
@@ -0,0 +1,79 @@
+use thiserror::Error;
+
+use crate::params::ParamError;
+
+/// Every way the engine API can fail, grouped by subsystem so callers can
+/// match on the kind of failure instead of parsing a message.
+#[derive(Debug, Error)]
+pub enum RustWaveError {
+    /// The audio host has no default output device
+    #[error("no audio output device available")]
+    NoOutputDevice,
+    /// None of the device's configurations has a sample format the engine can render
+    #[error("could not find any usable audio configuration")]
+    NoUsableConfig,
+    /// The audio host has no default input device, needed by `--latency-test`
+    #[error("no audio input device available")]
+    NoInputDevice,
+    #[error("could not read the audio device name: {0}")]
+    DeviceName(#[from] cpal::DeviceNameError),
+    #[error("could not query the audio device configurations: {0}")]
+    SupportedConfigs(#[from] cpal::SupportedStreamConfigsError),
+    #[error("could not query the default audio configuration: {0}")]
+    DefaultConfig(#[from] cpal::DefaultStreamConfigError),
+    #[error("could not open the audio stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("could not start the audio stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
+
+    #[cfg(feature = "midi")]
+    #[error("could not initialize MIDI input: {0}")]
+    MidiInit(#[from] midir::InitError),
+    /// The device index is not in the list from the last scan
+    #[cfg(feature = "midi")]
+    #[error("MIDI device {0} does not exist")]
+    NoSuchMidiDevice(usize),
+    #[cfg(feature = "midi")]
+    #[error("could not connect to MIDI device {port}: {kind}")]
+    MidiConnect { port: String, kind: midir::ConnectErrorKind },
+
+    #[cfg(feature = "ui")]
+    #[error("the GUI failed: {0}")]
+    Ui(#[from] eframe::Error),
+
+    /// Reading or writing preset and config files, or the console
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    /// The advanced tuning file is not valid TOML or has unknown keys
+    #[error("invalid tuning file: {0}")]
+    Config(#[from] toml::de::Error),
+    #[error("could not write the tuning file: {0}")]
+    ConfigWrite(#[from] toml::ser::Error),
+    #[error("could not write the probe report: {0}")]
+    Report(#[from] serde_json::Error),
+    /// A key in the key tuning file is not a MIDI note number (0-127)
+    #[error("{0} is not a MIDI note number")]
+    InvalidKey(u8),
+    /// A key's offset in the key tuning file is NaN, infinite or beyond 100 cents
+    #[error("key {note}: {source}")]
+    InvalidKeyOffset { note: u8, source: ParamError },
+
+    #[error("could not read the .wav file: {0}")]
+    Wav(#[from] hound::Error),
+    /// The file holds fewer samples than one wavetable frame
+    #[error("the wavetable has {0} samples, at least one 2048-sample frame is needed")]
+    WavetableTooShort(usize),
+    /// No steady pitch was found in a snippet to extract cycles from
+    #[error("no steady pitch found in {0}")]
+    NoPitch(String),
+    /// The sampler was given a file without audio in it
+    #[error("the sample holds no audio")]
+    EmptySample,
+
+    /// A synth parameter was given an invalid value
+    #[error(transparent)]
+    Param(#[from] ParamError),
+}
+
+pub type Result<T> = std::result::Result<T, RustWaveError>;
@@ -1,15 +1,109 @@
+use crate::advanced::FilterTuning;
+
+/// Half-band lowpass taps at odd offsets 1, 3, 5 and 7 either side of the
+/// centre; the even offsets are zero. A 15-tap Blackman-windowed design,
+/// about 60 dB down in the stopband.
+const HALF_BAND_TAPS: [f32; 4] = [0.298_638_8, -0.058_844, 0.010_952, -0.000_665];
+const HALF_BAND_CENTER_TAP: f32 = 0.499_836_5;
+const HALF_BAND_LEN: usize = 15;
+
+/// Which filter shapes a part's voices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    /// Moog-style 24 dB lowpass
+    Ladder,
+    /// Feedback comb tuned by the cutoff, for metallic and flanged tones
+    Comb,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 2] = [FilterType::Ladder, FilterType::Comb];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterType::Ladder => "Ladder",
+            FilterType::Comb => "Comb",
+        }
+    }
+}
+
+/// How many times faster than the audio rate the ladder runs internally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Oversampling {
+    Off,
+    X2,
+    X4,
+}
+
+impl Oversampling {
+    pub const ALL: [Oversampling; 3] = [Oversampling::Off, Oversampling::X2, Oversampling::X4];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Oversampling::Off => "1x",
+            Oversampling::X2 => "2x",
+            Oversampling::X4 => "4x",
+        }
+    }
+
+    fn factor(self) -> usize {
+        match self {
+            Oversampling::Off => 1,
+            Oversampling::X2 => 2,
+            Oversampling::X4 => 4,
+        }
+    }
+}
+
+/// Decimates by two with a half-band FIR lowpass.
+#[derive(Clone, Copy)]
+struct HalfBand {
+    history: [f32; HALF_BAND_LEN],
+}
+
+impl HalfBand {
+    fn new() -> Self {
+        Self { history: [0.0; HALF_BAND_LEN] }
+    }
+
+    /// Takes two consecutive samples at the higher rate and returns one at half of it.
+    fn decimate(&mut self, first: f32, second: f32) -> f32 {
+        self.history.copy_within(2.., 0);
+        self.history[HALF_BAND_LEN - 2] = first;
+        self.history[HALF_BAND_LEN - 1] = second;
+        let center = HALF_BAND_LEN / 2;
+        let mut output = HALF_BAND_CENTER_TAP * self.history[center];
+        for (k, tap) in HALF_BAND_TAPS.iter().enumerate() {
+            let offset = 2 * k + 1;
+            output += tap * (self.history[center - offset] + self.history[center + offset]);
+        }
+        output
+    }
+}
+
 pub struct LadderFilter {
     sample_rate: f32,
     cutoff: f32,
+    /// Factor applied to the cutoff by modulation, 1.0 when unmodulated
+    cutoff_modulation: f32,
     resonance: f32,
     drive: f32,
     saturation: f32,
+    /// Whether to scale the output to offset level changes from resonance and drive
+    auto_gain: bool,
+    oversampling: Oversampling,
+    /// Last input sample, for interpolating the oversampled input
+    previous_input: f32,
+    /// Decimators from 4x to 2x and from 2x to the audio rate
+    decimators: [HalfBand; 2],
     stage: [f32; 4],
     delay: [f32; 4],
     tanhstage: [f32; 3],
     old_x: f32,
     old_y: f32,
     thermal_drift: f32,
+    thermal_drift_rate: f32,
+    thermal_drift_decay: f32,
     transistor_mismatch: [f32; 4],
     rng: Xoshiro256PlusPlus,
 }
@@ -19,15 +113,22 @@ impl LadderFilter {
         let mut filter = Self {
             sample_rate,
             cutoff: 15000.0,
+            cutoff_modulation: 1.0,
             resonance: 0.0,
             drive: 1.0,
             saturation: 1.0,
+            auto_gain: false,
+            oversampling: Oversampling::Off,
+            previous_input: 0.0,
+            decimators: [HalfBand::new(); 2],
             stage: [0.0; 4],
             delay: [0.0; 4],
             tanhstage: [0.0; 3],
             old_x: 0.0,
             old_y: 0.0,
             thermal_drift: 0.0,
+            thermal_drift_rate: FilterTuning::default().thermal_drift_rate,
+            thermal_drift_decay: FilterTuning::default().thermal_drift_decay,
             transistor_mismatch: [1.0; 4],
             rng: Xoshiro256PlusPlus::seed_from_u64(0),
         };
@@ -39,6 +140,11 @@ impl LadderFilter {
         self.cutoff = cutoff.clamp(20.0, self.sample_rate * 0.49);
     }
 
+    /// Shifts the cutoff by `octaves` from its set value, e.g. from an LFO.
+    pub fn set_cutoff_modulation(&mut self, octaves: f32) {
+        self.cutoff_modulation = 2.0_f32.powf(octaves);
+    }
+
     pub fn set_resonance(&mut self, resonance: f32) {
         self.resonance = resonance.clamp(0.0, 4.0);
     }
@@ -51,6 +157,30 @@ impl LadderFilter {
         self.saturation = saturation.clamp(0.00, 2.00);
     }
 
+    pub fn set_auto_gain(&mut self, enabled: bool) {
+        self.auto_gain = enabled;
+    }
+
+    /// Runs the ladder at 2x or 4x the audio rate, so high cutoffs with strong
+    /// resonance stay stable and don't alias, at two or four times the cost.
+    pub fn set_oversampling(&mut self, oversampling: Oversampling) {
+        if oversampling != self.oversampling {
+            self.oversampling = oversampling;
+            self.decimators = [HalfBand::new(); 2];
+        }
+    }
+
+    pub fn set_tuning(&mut self, tuning: &FilterTuning) {
+        self.thermal_drift_rate = tuning.thermal_drift_rate.clamp(0.0, 0.01);
+        self.thermal_drift_decay = tuning.thermal_drift_decay.clamp(0.0, 1.0);
+    }
+
+    /// Approximate makeup gain for the current settings. Resonance thins out the
+    /// passband, while drive pushes the signal into the saturators and raises it.
+    fn compensation_gain(&self) -> f32 {
+        (1.0 + 0.5 * self.resonance) / self.drive.sqrt()
+    }
+
     fn randomize_transistors(&mut self) {
         for i in 0..4 {
             // Subtle mismatch, within 0.5% of ideal
@@ -60,17 +190,52 @@ impl LadderFilter {
 
     fn update_thermal_drift(&mut self) {
         // Simulate slow thermal drift
-        self.thermal_drift += (self.rng.next_u32() as f32 / u32::MAX as f32 - 0.5) * 0.0001;
-        self.thermal_drift *= 0.9999; // Slow decay towards zero
+        self.thermal_drift += (self.rng.next_u32() as f32 / u32::MAX as f32 - 0.5) * self.thermal_drift_rate;
+        self.thermal_drift *= self.thermal_drift_decay; // Slow decay towards zero
     }
 
     pub fn process(&mut self, input: f32) -> f32 {
         self.update_thermal_drift();
         
-        let fc = (self.cutoff * (1.0 + self.thermal_drift)) / self.sample_rate;
+        let cutoff = (self.cutoff * self.cutoff_modulation).clamp(20.0, self.sample_rate * 0.49);
+        let fc = (cutoff * (1.0 + self.thermal_drift)) / (self.sample_rate * self.oversampling.factor() as f32);
         let f = fc * 1.16;
         let fb = self.resonance * (1.0 - 0.15 * f * f);
 
+        // The input is interpolated linearly up to the internal rate and the
+        // output decimated back down in half-band steps
+        let previous = self.previous_input;
+        self.previous_input = input;
+        let output = match self.oversampling {
+            Oversampling::Off => self.process_ladder(input, f, fb),
+            Oversampling::X2 => {
+                let first = self.process_ladder((previous + input) * 0.5, f, fb);
+                let second = self.process_ladder(input, f, fb);
+                self.decimators[1].decimate(first, second)
+            }
+            Oversampling::X4 => {
+                let mut upsampled = [0.0; 4];
+                for (i, sample) in upsampled.iter_mut().enumerate() {
+                    let x = previous + (input - previous) * (i + 1) as f32 * 0.25;
+                    *sample = self.process_ladder(x, f, fb);
+                }
+                let first = self.decimators[0].decimate(upsampled[0], upsampled[1]);
+                let second = self.decimators[0].decimate(upsampled[2], upsampled[3]);
+                self.decimators[1].decimate(first, second)
+            }
+        };
+
+        if self.auto_gain {
+            // Only the output is scaled, so the feedback path is unchanged
+            output * self.compensation_gain()
+        } else {
+            output
+        }
+    }
+
+    /// One step of the ladder at the internal rate, with the frequency
+    /// coefficient `f` and feedback `fb` for that rate.
+    fn process_ladder(&mut self, input: f32, f: f32, fb: f32) -> f32 {
         let mut input_with_feedback = input * self.drive - self.old_y * fb;
         self.old_x = input_with_feedback;
 
@@ -109,11 +274,11 @@ impl Xoshiro256PlusPlus {
     fn seed_from_u64(seed: u64) -> Self {
         let mut state = [0; 4];
         let mut splitmix64 = seed;
-        for i in 0..4 {
+        for word in &mut state {
             splitmix64 = splitmix64.wrapping_add(0x9e3779b97f4a7c15);
             let z = (splitmix64 ^ (splitmix64 >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
             let z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
-            state[i] = z ^ (z >> 31);
+            *word = z ^ (z >> 31);
         }
         Self { s: state }
     }
@@ -0,0 +1,135 @@
+/// Shortest delay of the normal sweep in seconds
+const MIN_DELAY: f32 = 0.0002;
+/// How far a full-depth sweep moves the delay, in seconds
+const SWEEP: f32 = 0.006;
+/// Fixed delay the dry signal is held back by in through-zero mode, the
+/// middle of the sweep the wet signal crosses it in
+const ZERO_POINT: f32 = SWEEP * 0.5;
+/// Per-sample step depth changes glide by, so moving the knob doesn't click
+const DEPTH_SMOOTHING: f32 = 0.001;
+
+/// A short buffer read at a moving, fractional delay.
+struct FlangerLine {
+    buffer: Vec<f32>,
+    write: usize,
+}
+
+impl FlangerLine {
+    fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.0; len],
+            write: 0,
+        }
+    }
+
+    /// The signal `delay` samples ago, read between samples so the sweep is smooth.
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let delay = delay.clamp(1.0, (len - 2) as f32);
+        let whole = delay as usize;
+        let fraction = delay - whole as f32;
+        let newer = self.buffer[(self.write + len - whole) % len];
+        let older = self.buffer[(self.write + len - whole - 1) % len];
+        newer + (older - newer) * fraction
+    }
+
+    fn write(&mut self, input: f32) {
+        self.buffer[self.write] = input;
+        self.write = (self.write + 1) % self.buffer.len();
+    }
+}
+
+/// Stereo flanger: a single short delay swept by a triangle LFO and fed
+/// back into itself, mixed with the input so the notches comb through the
+/// spectrum. Unlike the chorus it uses one voice, delays of only a few
+/// milliseconds and strong feedback, for the jet-plane sweep.
+///
+/// Through-zero mode holds the dry signal back by the middle of the sweep
+/// and lets the wet signal cross it, like two tape machines with one being
+/// slowed by hand. The wet side is inverted there, so the sound cancels
+/// briefly as the two line up.
+pub struct Flanger {
+    sample_rate: f32,
+    /// Wet lines, fed back into, and the dry lines through-zero mode reads
+    lines: [FlangerLine; 2],
+    dry_lines: [FlangerLine; 2],
+    /// Sweep position of the left side (0.0-1.0). The right side runs a
+    /// quarter cycle ahead, so the notches move across the stereo field.
+    phase: f32,
+    rate: f32,
+    depth: f32,
+    smooth_depth: f32,
+    /// Share of the delayed signal fed back (-0.95 to 0.95). Negative
+    /// feedback gives a hollower, more metallic sweep.
+    feedback: f32,
+    /// Wet share of the output (0.0-1.0)
+    mix: f32,
+    through_zero: bool,
+}
+
+impl Flanger {
+    pub fn new(sample_rate: f32) -> Self {
+        let len = ((MIN_DELAY + SWEEP) * sample_rate) as usize + 4;
+        Self {
+            sample_rate,
+            lines: [FlangerLine::new(len), FlangerLine::new(len)],
+            dry_lines: [FlangerLine::new(len), FlangerLine::new(len)],
+            phase: 0.0,
+            rate: 0.2,
+            depth: 0.7,
+            smooth_depth: 0.7,
+            feedback: 0.5,
+            mix: 0.0,
+            through_zero: false,
+        }
+    }
+
+    /// Sweeps per second.
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.clamp(0.01, 10.0);
+    }
+
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-0.95, 0.95);
+    }
+
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_through_zero(&mut self, enabled: bool) {
+        self.through_zero = enabled;
+    }
+
+    /// Returns the flanged signal, already mixed with the input.
+    pub fn process(&mut self, input_left: f32, input_right: f32) -> (f32, f32) {
+        self.phase = (self.phase + self.rate / self.sample_rate).fract();
+        self.smooth_depth += (self.depth - self.smooth_depth) * DEPTH_SMOOTHING;
+
+        let mut output = [input_left, input_right];
+        for (side, input) in [input_left, input_right].into_iter().enumerate() {
+            let phase = (self.phase + side as f32 * 0.25).fract();
+            // Triangle from 0.0 up to 1.0 and back over each cycle
+            let sweep = 1.0 - (2.0 * phase - 1.0).abs();
+
+            let (delay, dry, polarity) = if self.through_zero {
+                let delay = ZERO_POINT * (1.0 + self.smooth_depth * (2.0 * sweep - 1.0));
+                (delay, self.dry_lines[side].read(ZERO_POINT * self.sample_rate), -1.0)
+            } else {
+                (MIN_DELAY + SWEEP * self.smooth_depth * sweep, input, 1.0)
+            };
+            let wet = self.lines[side].read(delay * self.sample_rate);
+
+            // Soft-limit what goes round the loop so high feedback rings
+            // instead of boosting resonant notes many times over
+            self.lines[side].write(input + (wet * self.feedback).tanh());
+            self.dry_lines[side].write(input);
+            output[side] = dry * (1.0 - self.mix) + wet * polarity * self.mix;
+        }
+        (output[0], output[1])
+    }
+}
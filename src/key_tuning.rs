@@ -0,0 +1,134 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, RustWaveError};
+use crate::params;
+
+/// Per-key offsets, looked up in the working directory.
+pub const KEY_TUNING_FILE: &str = "tuning.toml";
+
+/// Largest offset a key can be nudged by, in cents either way
+pub const MAX_KEY_OFFSET: f32 = 100.0;
+
+/// Number of MIDI notes
+const NUM_KEYS: usize = 128;
+
+/// On-disk form: a `[[key]]` entry for each retuned key.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct KeyTuningFile {
+    key: Vec<KeyOffset>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyOffset {
+    /// MIDI note number
+    note: u8,
+    cents: f64,
+}
+
+/// Offset of every MIDI key from equal temperament, in cents. Applied on top
+/// of a part's octave, semitone and fine tuning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyTuning {
+    cents: [f32; NUM_KEYS],
+}
+
+impl KeyTuning {
+    /// Equal temperament, every offset zero.
+    pub fn new() -> Self {
+        Self { cents: [0.0; NUM_KEYS] }
+    }
+
+    pub fn offset(&self, note: u8) -> f32 {
+        self.cents.get(note as usize).copied().unwrap_or(0.0)
+    }
+
+    pub fn set_offset(&mut self, note: u8, cents: f32) {
+        if let Some(offset) = self.cents.get_mut(note as usize) {
+            *offset = cents.clamp(-MAX_KEY_OFFSET, MAX_KEY_OFFSET);
+        }
+    }
+
+    /// Keys with a non-zero offset, lowest first.
+    pub fn retuned_keys(&self) -> impl Iterator<Item = (u8, f32)> + '_ {
+        (0..NUM_KEYS as u8).map(|note| (note, self.offset(note))).filter(|&(_, cents)| cents != 0.0)
+    }
+
+    /// Reads a table saved by `save`. Keys missing from the file stay in
+    /// equal temperament, and an offset that is not a finite number within
+    /// MAX_KEY_OFFSET fails the load rather than being clamped.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let file: KeyTuningFile = toml::from_str(&text)?;
+        let mut tuning = Self::new();
+        for key in file.key {
+            if key.note as usize >= NUM_KEYS {
+                return Err(RustWaveError::InvalidKey(key.note));
+            }
+            let cents = params::validate("key offset", -MAX_KEY_OFFSET..=MAX_KEY_OFFSET, key.cents as f32)
+                .map_err(|source| RustWaveError::InvalidKeyOffset { note: key.note, source })?;
+            tuning.set_offset(key.note, cents);
+        }
+        Ok(tuning)
+    }
+
+    /// Loads the table from the working directory. A missing file means equal
+    /// temperament; a broken one is reported and ignored.
+    pub fn load_default() -> Self {
+        let path = Path::new(KEY_TUNING_FILE);
+        if !path.exists() {
+            return Self::new();
+        }
+        match Self::load(path) {
+            Ok(tuning) => {
+                println!("Loaded key tuning from {}", path.display());
+                tuning
+            }
+            Err(err) => {
+                eprintln!("Ignoring {}: {}", path.display(), err);
+                Self::new()
+            }
+        }
+    }
+
+    /// Writes the retuned keys from low to high, rounded to a hundredth of a cent.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = KeyTuningFile {
+            key: self.retuned_keys()
+                .map(|(note, cents)| KeyOffset { note, cents: (cents as f64 * 100.0).round() / 100.0 })
+                .collect(),
+        };
+        std::fs::write(path, toml::to_string(&file)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_text(name: &str, text: &str) -> Result<KeyTuning> {
+        let path = std::env::temp_dir().join(format!("rustwave-{}-{}.toml", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let tuning = KeyTuning::load(&path);
+        let _ = std::fs::remove_file(&path);
+        tuning
+    }
+
+    #[test]
+    fn loads_offsets() {
+        let tuning = load_text("valid", "[[key]]\nnote = 64\ncents = -13.69\n").unwrap();
+        assert_eq!(tuning.offset(64), -13.69);
+        assert_eq!(tuning.offset(65), 0.0);
+    }
+
+    #[test]
+    fn rejects_bad_offsets() {
+        for cents in ["nan", "inf", "250.0"] {
+            let result = load_text("invalid", &format!("[[key]]\nnote = 60\ncents = {}\n", cents));
+            assert!(matches!(result, Err(RustWaveError::InvalidKeyOffset { note: 60, .. })), "{} was accepted", cents);
+        }
+    }
+}
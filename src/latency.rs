@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{Sample, SampleFormat, SizedSample};
+use crossbeam_channel::Sender;
+use dasp_sample::FromSample;
+
+use crate::error::{Result, RustWaveError};
+
+/// Clicks played in one measurement
+const CLICK_COUNT: usize = 8;
+/// Time between clicks, longer than any plausible round trip
+const CLICK_INTERVAL: Duration = Duration::from_millis(600);
+/// Length of each click burst
+const CLICK_DURATION: Duration = Duration::from_millis(2);
+/// Input level that counts as the click arriving
+const DETECTION_THRESHOLD: f32 = 0.05;
+/// Returns later than this after a click are not taken as that click
+const MAX_LATENCY: Duration = Duration::from_millis(500);
+
+/// Whether `--latency-test` was given on the command line.
+pub fn requested() -> bool {
+    std::env::args().any(|arg| arg == "--latency-test")
+}
+
+/// Measures the round trip from the output callback to the input callback.
+/// Clicks are played on the default output and listened for on the default
+/// input, so the output must be looped back to the input: a cable from the
+/// headphone jack to line in, or a speaker close to the microphone.
+pub fn run() -> Result<()> {
+    let host = cpal::default_host();
+    let output = host.default_output_device().ok_or(RustWaveError::NoOutputDevice)?;
+    let input = host.default_input_device().ok_or(RustWaveError::NoInputDevice)?;
+    println!("Playing clicks on {} and listening on {}", output.name()?, input.name()?);
+
+    let (click_tx, click_rx) = crossbeam_channel::unbounded();
+    let (heard_tx, heard_rx) = crossbeam_channel::unbounded();
+
+    let output_config = output.default_output_config()?;
+    let output_stream = match output_config.sample_format() {
+        SampleFormat::F32 => build_click_stream::<f32>(&output, &output_config.into(), click_tx)?,
+        SampleFormat::I16 => build_click_stream::<i16>(&output, &output_config.into(), click_tx)?,
+        SampleFormat::U16 => build_click_stream::<u16>(&output, &output_config.into(), click_tx)?,
+        _ => return Err(RustWaveError::NoUsableConfig),
+    };
+    let input_config = input.default_input_config()?;
+    let input_stream = match input_config.sample_format() {
+        SampleFormat::F32 => build_listen_stream::<f32>(&input, &input_config.into(), heard_tx)?,
+        SampleFormat::I16 => build_listen_stream::<i16>(&input, &input_config.into(), heard_tx)?,
+        SampleFormat::U16 => build_listen_stream::<u16>(&input, &input_config.into(), heard_tx)?,
+        _ => return Err(RustWaveError::NoUsableConfig),
+    };
+    input_stream.play()?;
+    output_stream.play()?;
+
+    std::thread::sleep(CLICK_INTERVAL * (CLICK_COUNT as u32 + 1));
+    drop(output_stream);
+    drop(input_stream);
+
+    // Pair every arrival with the latest click played before it
+    let clicks: Vec<Instant> = click_rx.try_iter().collect();
+    let mut latencies: Vec<Duration> = heard_rx
+        .try_iter()
+        .filter_map(|heard| {
+            let played = clicks.iter().rev().find(|&&played| played <= heard)?;
+            Some(heard - *played).filter(|&latency| latency <= MAX_LATENCY)
+        })
+        .collect();
+
+    if latencies.is_empty() {
+        println!("None of the {} clicks came back. Check that the output is looped back to the input.", clicks.len());
+        return Ok(());
+    }
+    latencies.sort();
+    let median = latencies[latencies.len() / 2];
+    println!(
+        "Round-trip latency: {:.1} ms (median of {} of {} clicks, {:.1}-{:.1} ms)",
+        median.as_secs_f64() * 1000.0,
+        latencies.len(),
+        clicks.len(),
+        latencies[0].as_secs_f64() * 1000.0,
+        latencies[latencies.len() - 1].as_secs_f64() * 1000.0,
+    );
+    Ok(())
+}
+
+/// Output stream that plays a click every `CLICK_INTERVAL`, reporting when
+/// each one was handed to the device.
+fn build_click_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, clicks: Sender<Instant>) -> Result<cpal::Stream>
+where
+    T: Sample + SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let interval = (CLICK_INTERVAL.as_secs_f32() * sample_rate) as usize;
+    let click_len = (CLICK_DURATION.as_secs_f32() * sample_rate).max(1.0) as usize;
+    let mut frame = 0;
+    let mut played = 0;
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let now = Instant::now();
+            for (index, output) in data.chunks_mut(channels).enumerate() {
+                let position = frame % interval;
+                let clicking = played < CLICK_COUNT && position < click_len;
+                if clicking && position == 0 {
+                    let _ = clicks.send(now + Duration::from_secs_f32(index as f32 / sample_rate));
+                }
+                // A square burst, loud and sharp enough to stand out from room noise
+                let value = if clicking {
+                    if position.is_multiple_of(2) { 0.8 } else { -0.8 }
+                } else {
+                    0.0
+                };
+                if clicking && position == click_len - 1 {
+                    played += 1;
+                }
+                output.fill(T::from_sample(value));
+                frame += 1;
+            }
+        },
+        |err| eprintln!("an error occurred on the output stream: {}", err),
+        None,
+    )?;
+    Ok(stream)
+}
+
+/// Input stream that reports when a click arrives, ignoring the rest of the
+/// burst and its echoes for a while after each one.
+fn build_listen_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, heard: Sender<Instant>) -> Result<cpal::Stream>
+where
+    T: Sample + SizedSample,
+    f32: FromSample<T>,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let hold_off = (CLICK_INTERVAL.as_secs_f32() * 0.5 * sample_rate) as usize;
+    let mut quiet_for = hold_off;
+    let mut ignoring = 0;
+
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // The callback runs once the whole buffer is captured, so each
+            // frame arrived this long before the end of it
+            let now = Instant::now();
+            let frames = data.len() / channels;
+            for (index, input) in data.chunks(channels).enumerate() {
+                let level = input.iter().fold(0.0f32, |level, &sample| level.max(f32::from_sample(sample).abs()));
+                if ignoring > 0 {
+                    ignoring -= 1;
+                } else if level >= DETECTION_THRESHOLD && quiet_for >= hold_off / 4 {
+                    let _ = heard.send(now - Duration::from_secs_f32((frames - index) as f32 / sample_rate));
+                    ignoring = hold_off;
+                }
+                quiet_for = if level < DETECTION_THRESHOLD { quiet_for + 1 } else { 0 };
+            }
+        },
+        |err| eprintln!("an error occurred on the input stream: {}", err),
+        None,
+    )?;
+    Ok(stream)
+}
@@ -0,0 +1,258 @@
+use std::f32::consts::PI;
+use crate::tempo::NoteDivision;
+
+/// Shape of a low-frequency oscillator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    /// Rising ramp
+    Saw,
+    Square,
+    /// A new random level every cycle
+    SampleAndHold,
+    /// Glides from one random level to the next over each cycle
+    SmoothRandom,
+    /// Rising staircase of `STEPS` levels
+    Stepped,
+}
+
+impl LfoShape {
+    pub const ALL: [LfoShape; 7] = [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+        LfoShape::SampleAndHold,
+        LfoShape::SmoothRandom,
+        LfoShape::Stepped,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LfoShape::Sine => "Sine",
+            LfoShape::Triangle => "Triangle",
+            LfoShape::Saw => "Saw",
+            LfoShape::Square => "Square",
+            LfoShape::SampleAndHold => "S&H",
+            LfoShape::SmoothRandom => "Smooth Random",
+            LfoShape::Stepped => "Stepped",
+        }
+    }
+}
+
+/// What an LFO does when a note is played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoTrigger {
+    /// Keeps running, only fading in again
+    Free,
+    /// Starts the cycle over at its start phase
+    Retrigger,
+    /// Plays a single cycle from the start phase, then holds its last level
+    OneShot,
+}
+
+impl LfoTrigger {
+    pub const ALL: [LfoTrigger; 3] = [LfoTrigger::Free, LfoTrigger::Retrigger, LfoTrigger::OneShot];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LfoTrigger::Free => "Free",
+            LfoTrigger::Retrigger => "Retrigger",
+            LfoTrigger::OneShot => "One Shot",
+        }
+    }
+}
+
+/// Levels in one cycle of the stepped shape
+const STEPS: f32 = 8.0;
+
+/// Bipolar (-1.0 to 1.0) low-frequency oscillator for modulation.
+pub struct Lfo {
+    sample_rate: f32,
+    /// Free-running rate in Hz
+    rate: f32,
+    /// Note length of one cycle, replacing the free rate when set
+    sync: Option<NoteDivision>,
+    /// Tempo synced cycles follow, in BPM
+    tempo: f32,
+    /// Phase advance per sample at the current rate
+    increment: f32,
+    shape: LfoShape,
+    trigger: LfoTrigger,
+    /// Output scale (0.0-1.0)
+    depth: f32,
+    /// Position in the cycle (0.0-1.0)
+    phase: f32,
+    /// Shift of the cycle, so a restart begins part way through it (0.0-1.0)
+    phase_offset: f32,
+    /// Seconds the output takes to rise to full depth after a restart
+    fade_in: f32,
+    /// How far the fade-in has got (0.0-1.0)
+    fade: f32,
+    /// Level a finished one-shot cycle holds, `None` while the cycle runs
+    finished: Option<f32>,
+    /// Level held by the sample and hold shape for the current cycle, which
+    /// the smooth random shape glides to from `previous`
+    held: f32,
+    previous: f32,
+    /// xorshift32 state for the random shapes
+    rng: u32,
+}
+
+impl Lfo {
+    pub fn new(sample_rate: f32) -> Self {
+        Self {
+            sample_rate,
+            rate: 1.0,
+            sync: None,
+            tempo: 120.0,
+            increment: 1.0 / sample_rate,
+            shape: LfoShape::Sine,
+            trigger: LfoTrigger::Retrigger,
+            depth: 1.0,
+            phase: 0.0,
+            phase_offset: 0.0,
+            fade_in: 0.0,
+            fade: 1.0,
+            finished: None,
+            held: 0.0,
+            previous: 0.0,
+            rng: 0x9e37_79b9,
+        }
+    }
+
+    /// Seeds the random levels, so LFOs started together don't step in unison.
+    pub fn seed(&mut self, seed: u32) {
+        self.rng = seed.wrapping_mul(0x9e37_79b9).max(1);
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        self.rate = rate.max(0.0);
+        self.update_increment();
+    }
+
+    /// Locks the cycle to a note length at the current tempo, or back to the
+    /// free rate with `None`.
+    pub fn set_sync(&mut self, sync: Option<NoteDivision>) {
+        self.sync = sync;
+        self.update_increment();
+    }
+
+    pub fn set_tempo(&mut self, bpm: f32) {
+        self.tempo = bpm.max(1.0);
+        self.update_increment();
+    }
+
+    fn update_increment(&mut self) {
+        let rate = match self.sync {
+            Some(division) => division.hz(self.tempo),
+            None => self.rate,
+        };
+        self.increment = rate / self.sample_rate;
+    }
+
+    pub fn set_shape(&mut self, shape: LfoShape) {
+        self.shape = shape;
+    }
+
+    pub fn set_trigger(&mut self, trigger: LfoTrigger) {
+        self.trigger = trigger;
+        if trigger != LfoTrigger::OneShot {
+            self.finished = None;
+        }
+    }
+
+    /// Whether every note restarts the cycle.
+    pub fn retriggers(&self) -> bool {
+        self.trigger != LfoTrigger::Free
+    }
+
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    /// Sets where in the cycle the LFO starts, in degrees.
+    pub fn set_phase(&mut self, degrees: f32) {
+        self.phase_offset = (degrees / 360.0).rem_euclid(1.0);
+    }
+
+    pub fn set_fade_in(&mut self, seconds: f32) {
+        self.fade_in = seconds.max(0.0);
+        if self.fade_in == 0.0 {
+            self.fade = 1.0;
+        }
+    }
+
+    /// Starts the cycle over, so every note sweeps from the same point.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+        self.finished = None;
+        self.previous = self.held;
+        self.held = self.next_random();
+        self.restart_fade();
+    }
+
+    /// Restarts the cycle or just the fade-in for a new note, depending on
+    /// the trigger mode.
+    pub fn note_on(&mut self) {
+        match self.trigger {
+            LfoTrigger::Free => self.restart_fade(),
+            LfoTrigger::Retrigger | LfoTrigger::OneShot => self.reset(),
+        }
+    }
+
+    /// Fades the output in again without moving the cycle.
+    fn restart_fade(&mut self) {
+        if self.fade_in > 0.0 {
+            self.fade = 0.0;
+        }
+    }
+
+    pub fn next(&mut self) -> f32 {
+        let value = match self.finished {
+            Some(level) => level,
+            None => self.advance(),
+        };
+        if self.fade < 1.0 {
+            self.fade = (self.fade + 1.0 / (self.fade_in * self.sample_rate)).min(1.0);
+        }
+        value * self.depth * self.fade
+    }
+
+    /// The shape's level at the current phase, moving the phase on a sample.
+    fn advance(&mut self) -> f32 {
+        let phase = (self.phase + self.phase_offset).fract();
+        let value = match self.shape {
+            LfoShape::Sine => (phase * 2.0 * PI).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::Saw => phase * 2.0 - 1.0,
+            LfoShape::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            LfoShape::SampleAndHold => self.held,
+            LfoShape::SmoothRandom => {
+                let glide = (1.0 - (self.phase * PI).cos()) * 0.5;
+                self.previous + (self.held - self.previous) * glide
+            }
+            LfoShape::Stepped => (phase * STEPS).floor() / (STEPS - 1.0) * 2.0 - 1.0,
+        };
+
+        self.phase += self.increment;
+        if self.phase >= 1.0 {
+            if self.trigger == LfoTrigger::OneShot {
+                self.finished = Some(value);
+            }
+            self.phase -= 1.0;
+            self.previous = self.held;
+            self.held = self.next_random();
+        }
+        value
+    }
+
+    /// A random level in -1.0-1.0 from xorshift32.
+    fn next_random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
@@ -0,0 +1,72 @@
+use crate::multi_engine::NUM_PARTS;
+use crate::params::PartParam;
+
+/// Macro knobs shared by the whole synth
+pub const MACRO_COUNT: usize = 4;
+/// Parameters each macro can drive
+pub const MACRO_TARGETS: usize = 4;
+/// MIDI controllers that turn the macros, General Purpose 1-4, on any channel
+pub const MACRO_CCS: [u8; MACRO_COUNT] = [16, 17, 18, 19];
+
+/// One part parameter a macro drives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MacroTarget {
+    pub part: usize,
+    pub param: PartParam,
+    /// How far a fully turned macro moves the parameter, as a fraction of its
+    /// range (-1.0 to 1.0). Negative amounts turn it down.
+    pub amount: f32,
+}
+
+/// The macros' assignments and values, and the offsets they add to the part
+/// parameters. Offsets are recomputed only when a macro moves or is
+/// reassigned, so applying them costs one lookup per parameter.
+pub struct Macros {
+    targets: [[Option<MacroTarget>; MACRO_TARGETS]; MACRO_COUNT],
+    /// Knob positions (0.0-1.0)
+    values: [f32; MACRO_COUNT],
+    offsets: Vec<[f32; PartParam::ALL.len()]>,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self {
+            targets: [[None; MACRO_TARGETS]; MACRO_COUNT],
+            values: [0.0; MACRO_COUNT],
+            offsets: vec![[0.0; PartParam::ALL.len()]; NUM_PARTS],
+        }
+    }
+
+    pub fn set_value(&mut self, index: usize, value: f32) {
+        if let Some(slot) = self.values.get_mut(index) {
+            *slot = value.clamp(0.0, 1.0);
+            self.update_offsets();
+        }
+    }
+
+    /// Assigns a target slot of a macro, or clears it with `None`.
+    pub fn set_target(&mut self, index: usize, slot: usize, target: Option<MacroTarget>) {
+        let target = target.filter(|t| t.part < NUM_PARTS);
+        if let Some(slot) = self.targets.get_mut(index).and_then(|targets| targets.get_mut(slot)) {
+            *slot = target.map(|t| MacroTarget { amount: t.amount.clamp(-1.0, 1.0), ..t });
+            self.update_offsets();
+        }
+    }
+
+    /// The amount every macro together adds to a part parameter.
+    pub fn offset(&self, part: usize, param: PartParam) -> f32 {
+        self.offsets[part][param as usize]
+    }
+
+    fn update_offsets(&mut self) {
+        for offsets in &mut self.offsets {
+            offsets.fill(0.0);
+        }
+        for (targets, &value) in self.targets.iter().zip(&self.values) {
+            for target in targets.iter().flatten() {
+                let range = target.param.range();
+                self.offsets[target.part][target.param as usize] += value * target.amount * (range.end() - range.start());
+            }
+        }
+    }
+}
@@ -1,24 +1,74 @@
+// Without the UI most of the control API (parameter ranges, waveform and steal
+// policy selection) has no caller, which is expected for a minimal engine build.
+#![cfg_attr(not(feature = "ui"), allow(dead_code))]
+
+mod advanced;
 mod envelope;
+mod lfo;
+mod macros;
+mod modulation;
+mod key_tuning;
 mod oscillator;
+#[cfg(feature = "ui")]
 mod ui;
 mod voice;
 mod voice_manager;
+mod wavefolder;
+mod wavetable;
+mod widener;
+mod sampler;
+mod multi_engine;
 mod filter;
+#[cfg(feature = "reverb")]
 mod reverb;
+#[cfg(feature = "chorus")]
 mod chorus;
+#[cfg(feature = "delay")]
+mod delay;
+#[cfg(feature = "flanger")]
+mod flanger;
+mod comb;
+mod effects;
+mod error;
+mod latency;
+mod noise;
+mod params;
+mod polyphony;
+mod probe;
+mod status;
+mod tempo;
+mod safe_mode;
+mod self_test;
+mod stress;
+#[cfg(feature = "midi")]
 mod midi_handler;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Sample, SampleFormat, SizedSample};
 use dasp_sample::FromSample;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
-use parking_lot::Mutex;
+use std::sync::Arc;
+#[cfg(feature = "ui")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+#[cfg(feature = "ui")]
 use eframe::egui;
+use crossbeam_channel::Receiver;
 
-use crate::voice_manager::VoiceManager;
+use crate::advanced::ConfigWatch;
+use crate::error::{Result, RustWaveError};
+use crate::key_tuning::KeyTuning;
+use crate::multi_engine::{MultiEngine, EngineEvent, EventSender, MAX_BLOCK_SIZE};
+use crate::params::SynthParams;
+use crate::polyphony::MAX_VOICES_PER_PART;
+use crate::safe_mode::{RunMarker, SafeModeReason};
+use crate::status::EngineStatus;
+use crate::wavetable::Wavetable;
+#[cfg(feature = "ui")]
 use crate::ui::SynthUI;
+#[cfg(feature = "midi")]
 use crate::midi_handler::MidiHandler;
 
+#[cfg(feature = "ui")]
 impl eframe::App for SynthApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.ui.update(ctx);
@@ -29,29 +79,68 @@ impl eframe::App for SynthApp {
     }
 }
 
+#[cfg(feature = "ui")]
 struct SynthApp {
     ui: SynthUI,
     _stream: cpal::Stream,
     running: Arc<AtomicBool>,
 }
 
-fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> Result<(), Box<dyn std::error::Error>>
+fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig, safe_mode: Option<&SafeModeReason>) -> Result<()>
 where
     T: Sample + SizedSample + FromSample<f32>,
 {
     let sample_rate = config.sample_rate.0 as f32;
     let channels = config.channels as usize;
 
-    let voice_manager = Arc::new(Mutex::new(VoiceManager::new(sample_rate, 8))); // 8 voices
+    if std::env::args().any(|arg| arg == "--self-test") {
+        self_test::run(sample_rate);
+    }
+
+    let mut engine = MultiEngine::new(sample_rate, MAX_VOICES_PER_PART);
+    let params = Arc::new(SynthParams::new());
+    let status = Arc::new(EngineStatus::new());
+    let (event_tx, event_rx) = multi_engine::event_queue();
+
+    // Tuning constants from advanced.toml, reloaded whenever the file changes.
+    // Safe mode sticks to the built-in values.
+    if safe_mode.is_none() {
+        let mut config_watch = ConfigWatch::new(advanced::CONFIG_FILE);
+        if let Some(config) = config_watch.poll() {
+            engine.set_advanced(&config);
+        }
+        config_watch.spawn(event_tx.clone());
+    }
+
+    // Per-key microtuning from tuning.toml, edited from the keyboard in the UI
+    let key_tuning = if safe_mode.is_none() { KeyTuning::load_default() } else { KeyTuning::new() };
+    engine.set_key_tuning(&key_tuning);
+
+    #[cfg(feature = "midi")]
     let (mut midi_handler, _midi_rx) = MidiHandler::new()?;
-    midi_handler.set_voice_manager(Arc::clone(&voice_manager));
-    let running = Arc::new(AtomicBool::new(true));
-    let vm_clone = Arc::clone(&voice_manager);
+    #[cfg(feature = "midi")]
+    midi_handler.set_event_sender(event_tx.clone());
+    #[cfg(feature = "midi")]
+    midi_handler.set_params(Arc::clone(&params));
+    // Apps route to the synth through its own port; failing to open one
+    // leaves hardware input working
+    #[cfg(feature = "midi")]
+    if let Err(err) = midi_handler.open_virtual_port(midi_handler::VIRTUAL_PORT_NAME) {
+        eprintln!("Could not open a virtual MIDI input: {}", err);
+    }
+    #[cfg(feature = "midi")]
+    if let Some(index) = midi_handler::device_from_args() {
+        if let Err(err) = midi_handler.connect_to_device(index) {
+            eprintln!("Could not connect to MIDI device {}: {}", index, err);
+        }
+    }
+    let params_clone = Arc::clone(&params);
+    let status_clone = Arc::clone(&status);
 
     let stream = device.build_output_stream(
         config,
         move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &vm_clone)
+            write_data(data, channels, sample_rate, &mut engine, &params_clone, &status_clone, &event_rx)
         },
         |err| eprintln!("an error occurred on stream: {}", err),
         None,
@@ -59,7 +148,25 @@ where
 
     stream.play()?;
 
-    let ui = SynthUI::new(Arc::clone(&voice_manager));
+    if let Some(density) = stress::density_from_args() {
+        stress::spawn(event_tx.clone(), Arc::clone(&status), density);
+    }
+
+    run_frontend(stream, status, params, event_tx, key_tuning, safe_mode.cloned())
+}
+
+/// Runs the GUI until its window is closed, keeping the audio stream alive.
+#[cfg(feature = "ui")]
+fn run_frontend(
+    stream: cpal::Stream,
+    status: Arc<EngineStatus>,
+    params: Arc<SynthParams>,
+    events: EventSender,
+    key_tuning: KeyTuning,
+    safe_mode: Option<SafeModeReason>,
+) -> Result<()> {
+    let ui = SynthUI::new(status, params, events, key_tuning, safe_mode);
+    let running = Arc::new(AtomicBool::new(true));
 
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::Vec2::new(1200.0, 800.0)),
@@ -70,29 +177,114 @@ where
         "Rust Synth",
         options,
         Box::new(|_cc| Box::new(SynthApp { ui, _stream: stream, running })),
-    ).map_err(|e| e.to_string())?;
+    )?;
+
+    Ok(())
+}
 
+/// Without the UI, the engine runs headless (driven by MIDI, if compiled in)
+/// until Enter is pressed.
+#[cfg(not(feature = "ui"))]
+fn run_frontend(
+    stream: cpal::Stream,
+    _status: Arc<EngineStatus>,
+    _params: Arc<SynthParams>,
+    _events: EventSender,
+    _key_tuning: KeyTuning,
+    safe_mode: Option<SafeModeReason>,
+) -> Result<()> {
+    let mut line = String::new();
+    let config = std::path::Path::new(advanced::CONFIG_FILE);
+    if safe_mode.is_some() && config.exists() {
+        println!("Back up {} so it is not loaded next time? [y/N]", config.display());
+        std::io::stdin().read_line(&mut line)?;
+        if line.trim().eq_ignore_ascii_case("y") {
+            let backup = safe_mode::back_up(config)?;
+            println!("Moved it to {}", backup.display());
+        }
+        line.clear();
+    }
+
+    println!("Running without a UI, press Enter to quit");
+    std::io::stdin().read_line(&mut line)?;
+    drop(stream);
     Ok(())
 }
 
-fn write_data<T>(output: &mut [T], channels: usize, voice_manager: &Arc<Mutex<VoiceManager>>)
+fn write_data<T>(
+    output: &mut [T],
+    channels: usize,
+    sample_rate: f32,
+    engine: &mut MultiEngine,
+    params: &SynthParams,
+    status: &EngineStatus,
+    events: &Receiver<EngineEvent>,
+)
 where
     T: Sample + FromSample<f32>,
 {
-    for frame in output.chunks_mut(channels) {
-        let (left, right) = voice_manager.lock().render_next();
-        let left_sample = T::from_sample(left);
-        let right_sample = T::from_sample(right);
+    let started = Instant::now();
+
+    // Render the whole buffer one chunk at a time. The engine takes queued
+    // events and parameter changes on its own control-rate grid, so the host's
+    // buffer size doesn't change how often they are applied.
+    let mut left = [0.0; MAX_BLOCK_SIZE];
+    let mut right = [0.0; MAX_BLOCK_SIZE];
+    for chunk in output.chunks_mut(MAX_BLOCK_SIZE * channels) {
+        let frames = chunk.len() / channels;
+        engine.process(&mut left[..frames], &mut right[..frames], params, events);
 
-        for (i, sample) in frame.iter_mut().enumerate() {
-            *sample = if i % 2 == 0 { left_sample } else { right_sample };
+        for (frame_index, frame) in chunk.chunks_mut(channels).enumerate() {
+            let left_sample = T::from_sample(left[frame_index]);
+            let right_sample = T::from_sample(right[frame_index]);
+
+            for (i, sample) in frame.iter_mut().enumerate() {
+                *sample = if i % 2 == 0 { left_sample } else { right_sample };
+            }
         }
     }
+
+    engine.publish_status(status);
+
+    // Compare the time spent rendering with the time the buffer lasts
+    let buffer_duration = (output.len() / channels) as f32 / sample_rate;
+    if buffer_duration > 0.0 {
+        let load = started.elapsed().as_secs_f32() / buffer_duration;
+        engine.record_load(load, buffer_duration);
+        status.record_dsp_load(load);
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Builds a wavetable from an audio snippet and saves it to the user
+/// wavetable folder under the snippet's name.
+fn make_wavetable(snippet: &std::path::Path) -> Result<()> {
+    let (table, pitch) = Wavetable::extract(snippet)?;
+    let name = snippet.file_name().unwrap_or(snippet.as_os_str());
+    let output = std::path::Path::new(wavetable::WAVETABLE_DIR).join(name).with_extension("wav");
+    table.save(&output)?;
+    println!("Detected {:.1} Hz, saved {} frames to {}", pitch, table.frames(), output.display());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    if probe::requested() {
+        return probe::run();
+    }
+    if latency::requested() {
+        return latency::run();
+    }
+    if let Some(snippet) = wavetable::snippet_from_args() {
+        return make_wavetable(&snippet);
+    }
+
+    let safe_mode = safe_mode::check();
+    if let Some(reason) = &safe_mode {
+        println!("Starting in safe mode with built-in defaults: {}", reason);
+    }
+    let _run_marker = RunMarker::create();
+
     let host = cpal::default_host();
-    let device = host.default_output_device().expect("no output device available");
+    let device = host.default_output_device().ok_or(RustWaveError::NoOutputDevice)?;
 
     println!("Output device: {}", device.name()?);
 
@@ -111,7 +303,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut fallback_config = None;
 
     // First try to find one of our preferred configs
-    for supported_config in device.supported_output_configs().expect("error querying configs") {
+    for supported_config in device.supported_output_configs()? {
         // Save the first config as a fallback
         if fallback_config.is_none() {
             fallback_config = Some(supported_config.clone());
@@ -137,9 +329,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     
     // Use fallback if no preferred config found
-    let supported_config = selected_config.unwrap_or_else(|| {
-        fallback_config.expect("no supported config found").with_max_sample_rate()
-    });
+    let supported_config = match selected_config {
+        Some(config) => config,
+        None => fallback_config.ok_or(RustWaveError::NoUsableConfig)?.with_max_sample_rate(),
+    };
     
     println!("Selected output config: {:?}", supported_config);
     
@@ -147,17 +340,16 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config: cpal::StreamConfig = supported_config.into();
 
     match sample_format {
-        SampleFormat::F32 => run::<f32>(&device, &config)?,
-        SampleFormat::I16 => run::<i16>(&device, &config)?,
-        SampleFormat::U16 => run::<u16>(&device, &config)?,
-        SampleFormat::U8 => run::<u8>(&device, &config)?,
-        SampleFormat::I8 => run::<i8>(&device, &config)?,
+        SampleFormat::F32 => run::<f32>(&device, &config, safe_mode.as_ref())?,
+        SampleFormat::I16 => run::<i16>(&device, &config, safe_mode.as_ref())?,
+        SampleFormat::U16 => run::<u16>(&device, &config, safe_mode.as_ref())?,
+        SampleFormat::U8 => run::<u8>(&device, &config, safe_mode.as_ref())?,
+        SampleFormat::I8 => run::<i8>(&device, &config, safe_mode.as_ref())?,
         _ => {
             println!("Unsupported sample format: {:?}, trying to use a different format...", sample_format);
             
             // Try to find a supported format
-            let mut configs = device.supported_output_configs()
-                .expect("error while querying configs");
+            let mut configs = device.supported_output_configs()?;
             
             while let Some(config) = configs.next() {
                 let format = config.sample_format();
@@ -168,17 +360,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     println!("Trying alternative config: {:?}", config);
                     
                     match format {
-                        SampleFormat::F32 => return run::<f32>(&device, &stream_config),
-                        SampleFormat::I16 => return run::<i16>(&device, &stream_config),
-                        SampleFormat::U16 => return run::<u16>(&device, &stream_config),
-                        SampleFormat::U8 => return run::<u8>(&device, &stream_config),
-                        SampleFormat::I8 => return run::<i8>(&device, &stream_config),
+                        SampleFormat::F32 => return run::<f32>(&device, &stream_config, safe_mode.as_ref()),
+                        SampleFormat::I16 => return run::<i16>(&device, &stream_config, safe_mode.as_ref()),
+                        SampleFormat::U16 => return run::<u16>(&device, &stream_config, safe_mode.as_ref()),
+                        SampleFormat::U8 => return run::<u8>(&device, &stream_config, safe_mode.as_ref()),
+                        SampleFormat::I8 => return run::<i8>(&device, &stream_config, safe_mode.as_ref()),
                         _ => continue,
                     }
                 }
             }
             
-            panic!("Could not find any usable audio configuration");
+            return Err(RustWaveError::NoUsableConfig);
         }
     }
 
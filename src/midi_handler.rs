@@ -6,13 +6,88 @@
 
 use crossbeam_channel::{bounded, Receiver, Sender};
 use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort};
-use midly::{live::LiveEvent, MidiMessage};
-use parking_lot::Mutex; // Using parking_lot::Mutex instead of std::sync::Mutex as per project convention
-use std::error::Error;
+use midly::{live::{LiveEvent, SystemRealtime}, MidiMessage};
+
+// Import the MultiEngine and its event type from our project
 use std::sync::Arc;
+use crate::error::{Result, RustWaveError};
+use crate::macros::MACRO_CCS;
+use crate::multi_engine::{MultiEngine, EngineEvent, EventSender, NUM_PARTS};
+use crate::params::{MasterParam, PartParam, SynthParams};
+
+/// Name of the virtual MIDI input RustWave creates, as other apps list it.
+pub const VIRTUAL_PORT_NAME: &str = "RustWave";
+
+/// Reads `--midi-in=<index>` from the command line, picking a device from the
+/// list printed at startup.
+pub fn device_from_args() -> Option<usize> {
+    std::env::args().find_map(|arg| arg.strip_prefix("--midi-in=").and_then(|index| index.parse().ok()))
+}
+
+/// Controller number of the mod wheel
+const MOD_WHEEL_CC: u8 = 1;
+/// Standard controllers for the mixer strips of the parts on a channel:
+/// channel volume, pan and effects send
+const MIXER_CCS: [(u8, PartParam); 3] = [(7, PartParam::Level), (10, PartParam::Pan), (91, PartParam::FxSend)];
+/// MIDI clock ticks per quarter note
+const CLOCK_TICKS_PER_BEAT: f32 = 24.0;
+/// Ticks between tempo updates sent to the engine, a sixteenth note
+const CLOCK_REPORT_TICKS: u32 = 6;
+/// A gap between ticks longer than this (µs) means the clock stopped and restarted
+const CLOCK_GAP: u64 = 500_000;
+
+/// Works out the tempo of incoming MIDI clock from the time between ticks.
+#[derive(Default)]
+struct ClockTracker {
+    last_tick: Option<u64>,
+    /// Smoothed time between ticks in µs, 0 until two ticks have arrived
+    interval: f32,
+    ticks: u32,
+}
+
+impl ClockTracker {
+    /// Takes the timestamp (µs) of a tick, returning the tempo in BPM every
+    /// `CLOCK_REPORT_TICKS` ticks.
+    fn tick(&mut self, timestamp: u64) -> Option<f32> {
+        let elapsed = timestamp.checked_sub(self.last_tick.replace(timestamp)?)?;
+        if elapsed > CLOCK_GAP {
+            self.interval = 0.0;
+            self.ticks = 0;
+            return None;
+        }
+        // Ticks jitter by a millisecond or so, so follow the average interval
+        self.interval = if self.interval == 0.0 {
+            elapsed as f32
+        } else {
+            self.interval + (elapsed as f32 - self.interval) * 0.1
+        };
+        self.ticks += 1;
+        (self.ticks.is_multiple_of(CLOCK_REPORT_TICKS) && self.interval > 0.0)
+            .then(|| 60_000_000.0 / (self.interval * CLOCK_TICKS_PER_BEAT))
+    }
+}
 
-// Import the VoiceManager from our project
-use crate::voice_manager::VoiceManager;
+/// Scales a mixer controller (0-127) to its parameter. Pan is centred at 64,
+/// and volume reaches unity at 127 like General MIDI channel volume.
+fn mixer_value(param: PartParam, value: u8) -> f32 {
+    let value = value as f32;
+    match param {
+        PartParam::Pan => ((value - 64.0) / 63.0).max(-1.0),
+        PartParam::Level => value / 127.0,
+        _ => {
+            let range = param.range();
+            range.start() + value / 127.0 * (range.end() - range.start())
+        }
+    }
+}
+
+/// Queues an incoming message for the audio thread, reporting any the queue
+/// turned away.
+fn send_event(engine_events: &EventSender, event: EngineEvent) {
+    if let Err(err) = engine_events.try_send(event.clone()) {
+        eprintln!("Dropped MIDI {:?}: {}", event, err);
+    }
+}
 
 /// Represents the types of MIDI events our synthesizer will process.
 /// 
@@ -20,12 +95,12 @@ use crate::voice_manager::VoiceManager;
 /// in the future to handle control changes, pitch bend, etc.
 #[derive(Debug, Clone)]
 pub enum MidiEvent {
-    /// Note On event with note number (0-127) and velocity (0-127)
-    NoteOn { note: u8, velocity: u8 },
+    /// Note On event with channel (0-15), note number (0-127) and velocity (0-127)
+    NoteOn { channel: u8, note: u8, velocity: u8 },
     
-    /// Note Off event with note number (0-127) and velocity (0-127)
+    /// Note Off event with channel (0-15), note number (0-127) and velocity (0-127)
     /// Note: Most MIDI keyboards send velocity with Note Off, but we don't use it currently
-    NoteOff { note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8, velocity: u8 },
     
     // Future expansion possibilities:
     // ControlChange { controller: u8, value: u8 },
@@ -36,11 +111,11 @@ pub enum MidiEvent {
 /// Manages MIDI input device connections and routes MIDI messages to the synthesizer.
 ///
 /// The MidiHandler provides two methods of operation:
-/// 1. Direct connection to the VoiceManager (preferred for this project)
+/// 1. Forwarding to the MultiEngine's event queue (preferred for this project)
 /// 2. Channel-based communication
 ///
-/// The direct connection is more efficient for our needs since it doesn't require
-/// additional message passing between threads.
+/// The engine event queue is drained by the audio thread once per block, so the
+/// MIDI callback never has to lock the engine.
 pub struct MidiHandler {
     /// The MidiInput instance used for scanning available ports.
     /// This is kept separate from the connection to allow rescanning while connected.
@@ -49,20 +124,28 @@ pub struct MidiHandler {
     /// The active MIDI input connection. When this is Some, we are connected to a device.
     connection: Option<MidiInputConnection<()>>,
 
+    /// RustWave's own virtual input port, when one has been opened.
+    virtual_port: Option<MidiInputConnection<()>>,
+
     /// List of available MIDI ports with their indices, names, and port objects.
     /// This is populated by the scan_devices() method.
     available_ports: Vec<(usize, String, MidiInputPort)>,
 
     /// Channel for sending MIDI events to other threads if needed.
-    /// This is an alternative to the direct VoiceManager approach.
+    /// This is an alternative to the engine event queue approach.
     sender: Sender<MidiEvent>,
 
     /// Channel for receiving MIDI events (for the channel-based approach).
     receiver: Receiver<MidiEvent>,
 
-    /// Reference to the VoiceManager for direct event handling.
-    /// When this is set, MIDI events directly trigger voice_manager methods.
-    voice_manager: Option<Arc<Mutex<VoiceManager>>>,
+    /// Sender half of the engine's event queue.
+    /// When this is set, MIDI events are pushed onto the queue and the engine routes
+    /// them to the parts listening on the event's MIDI channel.
+    engine_events: Option<EventSender>,
+
+    /// Shared parameter store, for controllers that set parameters directly
+    /// so the UI follows them.
+    params: Option<Arc<SynthParams>>,
 }
 
 impl MidiHandler {
@@ -84,7 +167,7 @@ impl MidiHandler {
     /// ```rust,no_run
     /// let (mut midi_handler, midi_receiver) = MidiHandler::new().unwrap()
     /// ```
-    pub fn new() -> Result<(Self, Receiver<MidiEvent>), Box<dyn Error>> {
+    pub fn new() -> Result<(Self, Receiver<MidiEvent>)> {
         let (sender, receiver) = bounded(128);
         let receiver_clone = receiver.clone();
     
@@ -93,65 +176,47 @@ impl MidiHandler {
         let mut handler = Self {
             midi_in: Some(midi_in),
             connection: None,
+            virtual_port: None,
             available_ports: Vec::new(),
             sender,
             receiver,
-            voice_manager: None,
+            engine_events: None,
+            params: None,
         };
 
         // Scan for devices immediately
         handler.scan_devices()?;
-        
-        // Attempt to auto-connect to IAC Driver if available
-        let iac_index = handler.find_iac_driver();
-        if let Some(idx) = iac_index {
-            println!("Auto-connecting to IAC Driver at index {}", idx);
-            // Don't fail if we can't connect - just log it
-            if let Err(e) = handler.connect_to_device(idx) {
-                eprintln!("Failed to auto-connect to IAC Driver: {}", e);
-            }
-        } else {
-            println!("No IAC Driver found for auto-connection");
-        }
-        
+
         Ok((handler, receiver_clone))
     }
-    
-    /// Finds the index of the IAC Driver in the available ports list
-    fn find_iac_driver(&self) -> Option<usize> {
-        for (idx, (_, name, _)) in self.available_ports.iter().enumerate() {
-            if name.contains("IAC") {
-                return Some(idx);
-            }
-        }
-        None
-    }
 
-    /// Sets the voice manager for direct MIDI event handling.
+    /// Sets the engine event queue for MIDI event handling.
     ///
-    /// When set, incoming MIDI events will directly trigger methods on the voice manager
-    /// without going through the channel. This is the preferred approach for this project
-    /// as it simplifies the architecture and avoids extra message passing.
+    /// When set, incoming MIDI events are pushed onto the engine's event queue
+    /// instead of the MidiEvent channel. This is the preferred approach for this project
+    /// as the audio thread applies the events at the start of each block without the
+    /// MIDI thread ever taking a lock the audio thread needs.
     ///
     /// # Parameters
     ///
-    /// * `voice_manager` - An Arc<Mutex<VoiceManager>> reference, which matches how
-    ///                     VoiceManager is used throughout the project
+    /// * `engine_events` - The sender half of the bounded queue drained by the audio thread
     ///
     /// # Example
     ///
     /// ```rust,no_run
-    /// let voice_manager = Arc::new(Mutex::new(VoiceManager::new(sample_rate, 8)));
-    /// midi_handler.set_voice_manager(Arc::clone(&voice_manager));
+    /// let (event_tx, event_rx) = multi_engine::event_queue();
+    /// midi_handler.set_event_sender(event_tx.clone());
     /// ```
-    pub fn set_voice_manager(&mut self, voice_manager: Arc<Mutex<VoiceManager>>) {
-        // Store the reference to the voice manager for use in the MIDI callback
-        self.voice_manager = Some(voice_manager);
-        
-        // Note: This works because our voice_manager is already designed to be
-        // accessed safely from multiple threads via Arc<Mutex<>>
+    pub fn set_event_sender(&mut self, engine_events: EventSender) {
+        // Store the sender for use in the MIDI callback
+        self.engine_events = Some(engine_events);
     }
     
+    /// Sets the parameter store the macro controllers write to.
+    pub fn set_params(&mut self, params: Arc<SynthParams>) {
+        self.params = Some(params);
+    }
+
     /// Scans for available MIDI input devices and updates the internal list.
     ///
     /// This method queries the operating system's MIDI system to find all available
@@ -171,7 +236,7 @@ impl MidiHandler {
     ///     println!("{}: {}", index, name);
     /// }
     /// ```
-    pub fn scan_devices(&mut self) -> Result<(), Box<dyn Error>> {
+    pub fn scan_devices(&mut self) -> Result<()> {
         // Create a new MidiInput instance if needed
         if self.midi_in.is_none() {
             self.midi_in = Some(MidiInput::new("rust_synth_midi_input")?);
@@ -229,7 +294,7 @@ impl MidiHandler {
     /// This method establishes a connection to the selected MIDI device and sets up
     /// a callback to handle incoming MIDI messages. When a MIDI message is received,
     /// it will be parsed and either:
-    /// 1. Directly handled by calling methods on the VoiceManager (if set), or
+    /// 1. Pushed onto the engine event queue (if set), or
     /// 2. Sent through the channel for processing elsewhere
     ///
     /// # Parameters
@@ -256,13 +321,13 @@ impl MidiHandler {
     ///     midi_handler.connect_to_device(0).unwrap(); // Connect to the first device
     /// }
     /// ```
-    fn connect_to_device(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+    pub fn connect_to_device(&mut self, index: usize) -> Result<()> {
         // Disconnect any existing connection first
         self.disconnect();
         
         // Verify the index is valid
         if index >= self.available_ports.len() {
-            return Err("Invalid MIDI device index".into());
+            return Err(RustWaveError::NoSuchMidiDevice(index));
         }
         
         // Clone the port and name for the selected device
@@ -276,83 +341,157 @@ impl MidiHandler {
         let mut midi_in = MidiInput::new("rust_synth_midi_connection")?;
         midi_in.ignore(Ignore::None);
         
-        // Clone sender and voice_manager for the closure
-        let sender = self.sender.clone();
-        let voice_manager = self.voice_manager.clone();
-        
-        // Add debug print in the callback to confirm we're receiving MIDI messages
         let connection = midi_in.connect(
             &port,
             "rust_synth",
-            move |_timestamp, message, _| {
-                // This closure is called for each incoming MIDI message
-                
-                // Try to parse the raw MIDI bytes using midly
-                if let Ok(event) = LiveEvent::parse(message) {
-                    // Process standard MIDI channel messages
-                    if let LiveEvent::Midi { channel: _, message } = event {
-                        match message {
-                            // Handle Note On messages
-                            MidiMessage::NoteOn { key, vel } => {
-                                let note = key.as_int();
-                                let velocity = vel.as_int();
-                                
-                                // MIDI spec: Note On with velocity 0 is equivalent to Note Off
-                                if velocity > 0 {
-                                    // This is a genuine Note On message
-                                    if let Some(vm) = &voice_manager {
-                                        // Direct approach: call note_on() on the VoiceManager
-                                        vm.lock().note_on(note);
-                                    } else {
-                                        // Channel approach: send a NoteOn event through the channel
-                                        let _ = sender.send(MidiEvent::NoteOn { 
-                                            note, 
-                                            velocity 
-                                        });
-                                    }
+            self.message_callback(),
+            (),
+        ).map_err(|err| RustWaveError::MidiConnect { port: port_name.clone(), kind: err.kind() })?;
+        
+        println!("Connected to MIDI device: {}", port_name);
+        self.connection = Some(connection);
+        
+        Ok(())
+    }
+    
+    /// Builds the callback that parses incoming MIDI bytes and forwards notes,
+    /// aftertouch and clock tempo, shared by device connections and the virtual port.
+    fn message_callback(&self) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+        let sender = self.sender.clone();
+        let engine_events = self.engine_events.clone();
+        let params = self.params.clone();
+        let mut clock = ClockTracker::default();
+        move |timestamp, message, _| {
+            // This closure is called for each incoming MIDI message
+            
+            // Try to parse the raw MIDI bytes using midly
+            if let Ok(event) = LiveEvent::parse(message) {
+                // Clock ticks set the tempo synced LFOs follow
+                if let LiveEvent::Realtime(SystemRealtime::TimingClock) = event {
+                    if let (Some(engine_events), Some(bpm)) = (&engine_events, clock.tick(timestamp)) {
+                        send_event(engine_events, EngineEvent::ClockTempo(bpm));
+                    }
+                }
+
+                // Process standard MIDI channel messages
+                if let LiveEvent::Midi { channel, message } = event {
+                    let channel = channel.as_int();
+                    match message {
+                        // Handle Note On messages
+                        MidiMessage::NoteOn { key, vel } => {
+                            let note = key.as_int();
+                            let velocity = vel.as_int();
+                            
+                            // MIDI spec: Note On with velocity 0 is equivalent to Note Off
+                            if velocity > 0 {
+                                // This is a genuine Note On message
+                                if let Some(engine_events) = &engine_events {
+                                    // Preferred approach: queue the note for the audio thread
+                                    send_event(engine_events, EngineEvent::NoteOn { channel, note, velocity });
                                 } else {
-                                    // This is a Note Off message disguised as Note On with velocity 0
-                                    if let Some(vm) = &voice_manager {
-                                        vm.lock().note_off(note);
-                                    } else {
-                                        let _ = sender.send(MidiEvent::NoteOff { 
-                                            note, 
-                                            velocity: 0 
-                                        });
-                                    }
+                                    // Channel approach: send a NoteOn event through the channel
+                                    let _ = sender.send(MidiEvent::NoteOn { 
+                                        channel,
+                                        note, 
+                                        velocity 
+                                    });
                                 }
-                            },
-                            // Handle explicit Note Off messages
-                            MidiMessage::NoteOff { key, vel: _ } => {
-                                let note = key.as_int();
-                                
-                                if let Some(vm) = &voice_manager {
-                                    vm.lock().note_off(note);
+                            } else {
+                                // This is a Note Off message disguised as Note On with velocity 0
+                                if let Some(engine_events) = &engine_events {
+                                    send_event(engine_events, EngineEvent::NoteOff { channel, note });
                                 } else {
                                     let _ = sender.send(MidiEvent::NoteOff { 
+                                        channel,
                                         note, 
-                                        velocity: 0 // We don't currently use Note Off velocity
+                                        velocity: 0 
                                     });
                                 }
-                            },
-                            // Other message types can be handled here in the future
-                            // For example:
-                            // MidiMessage::Controller { controller, value } => { ... }
-                            // MidiMessage::PitchBend { bend } => { ... }
-                            _ => {} // Ignore other message types for now
-                        }
+                            }
+                        },
+                        // Handle explicit Note Off messages
+                        MidiMessage::NoteOff { key, vel: _ } => {
+                            let note = key.as_int();
+                            
+                            if let Some(engine_events) = &engine_events {
+                                send_event(engine_events, EngineEvent::NoteOff { channel, note });
+                            } else {
+                                let _ = sender.send(MidiEvent::NoteOff { 
+                                    channel,
+                                    note, 
+                                    velocity: 0 // We don't currently use Note Off velocity
+                                });
+                            }
+                        },
+                        // Channel pressure drives the aftertouch modulation
+                        MidiMessage::ChannelAftertouch { vel } => {
+                            if let Some(engine_events) = &engine_events {
+                                let pressure = vel.as_int() as f32 / 127.0;
+                                send_event(engine_events, EngineEvent::ChannelPressure { channel, pressure });
+                            }
+                        },
+                        // The mod wheel and pitch bend are modulation sources
+                        MidiMessage::Controller { controller, value } if controller.as_int() == MOD_WHEEL_CC => {
+                            if let Some(engine_events) = &engine_events {
+                                let value = value.as_int() as f32 / 127.0;
+                                send_event(engine_events, EngineEvent::ModWheel { channel, value });
+                            }
+                        },
+                        MidiMessage::Controller { controller, value } => {
+                            let macro_param = MACRO_CCS.iter().position(|&cc| cc == controller.as_int()).map(|i| MasterParam::MACROS[i]);
+                            if let (Some(params), Some(param)) = (&params, macro_param) {
+                                if let Err(err) = params.try_set_master(param, value.as_int() as f32 / 127.0) {
+                                    eprintln!("Ignored MIDI CC {}: {}", controller, err);
+                                }
+                            }
+                            let mixer_param = MIXER_CCS.iter().find(|(cc, _)| *cc == controller.as_int()).map(|&(_, param)| param);
+                            if let (Some(params), Some(param)) = (&params, mixer_param) {
+                                let value = mixer_value(param, value.as_int());
+                                for part in (0..NUM_PARTS).filter(|&part| params.channel(part) == channel) {
+                                    if let Err(err) = params.try_set_part(part, param, value) {
+                                        eprintln!("Ignored MIDI CC {}: {}", controller, err);
+                                    }
+                                }
+                            }
+                        },
+                        MidiMessage::PitchBend { bend } => {
+                            if let Some(engine_events) = &engine_events {
+                                send_event(engine_events, EngineEvent::PitchBend { channel, bend: bend.as_f32() });
+                            }
+                        },
+                        // Other message types can be handled here in the future
+                        _ => {} // Ignore other message types for now
                     }
                 }
-            },
-            (),
-        )?;
-        
-        println!("Connected to MIDI device: {}", port_name);
-        self.connection = Some(connection);
-        
+            }
+        }
+    }
+
+    /// Creates a named virtual MIDI input that DAWs and other apps can send to
+    /// directly, with no loopback driver in between. It runs alongside any
+    /// device connection. Call after `set_event_sender` and `set_params`, since
+    /// the port's callback takes them when it is created.
+    #[cfg(unix)]
+    pub fn open_virtual_port(&mut self, name: &str) -> Result<()> {
+        use midir::os::unix::VirtualInput;
+
+        let mut midi_in = MidiInput::new("rust_synth_virtual_input")?;
+        midi_in.ignore(Ignore::None);
+        let port = midi_in.create_virtual(name, self.message_callback(), ())
+            .map_err(|err| RustWaveError::MidiConnect { port: name.to_string(), kind: err.kind() })?;
+        println!("Opened virtual MIDI input: {}", name);
+        self.virtual_port = Some(port);
         Ok(())
     }
-    
+
+    /// Windows has no virtual MIDI ports, so apps have to reach the synth
+    /// through a loopback device such as loopMIDI.
+    #[cfg(not(unix))]
+    pub fn open_virtual_port(&mut self, _name: &str) -> Result<()> {
+        println!("Virtual MIDI ports are not supported on this platform, connect through a loopback device");
+        Ok(())
+    }
+
     /// Disconnects from the current MIDI device if connected.
     ///
     /// This method safely closes the current MIDI connection and releases resources.
@@ -377,16 +516,16 @@ impl MidiHandler {
     /// Processes pending MIDI events from the channel.
     ///
     /// This method should be called regularly (e.g., from the audio thread) if
-    /// using the channel-based approach rather than direct voice manager access.
+    /// using the channel-based approach rather than the engine event queue.
     /// It processes all pending MIDI events without blocking.
     ///
-    /// Note: This method is only needed if NOT using the direct voice_manager
-    /// approach via set_voice_manager(). With our project structure, the direct
+    /// Note: This method is only needed if NOT using the engine event queue
+    /// approach via set_event_sender(). With our project structure, the queue
     /// approach is preferred.
     ///
     /// # Parameters
     ///
-    /// * `voice_manager` - A mutable reference to the VoiceManager to handle the events
+    /// * `engine` - A mutable reference to the MultiEngine to handle the events
     ///
     /// # Returns
     ///
@@ -396,18 +535,18 @@ impl MidiHandler {
     ///
     /// ```rust,no_run
     /// // In your audio processing callback:
-    /// midi_handler.process_events(&mut voice_manager).unwrap();
+    /// midi_handler.process_events(&mut engine).unwrap();
     /// ```
-    pub fn process_events(&self, voice_manager: &mut VoiceManager) -> Result<(), Box<dyn Error>> {
+    pub fn process_events(&self, engine: &mut MultiEngine) -> Result<()> {
         // Try to receive all pending MIDI events without blocking
         // This we don't stall the audio thread if the channel is empty
         while let Ok(event) = self.receiver.try_recv() {
             match event {
-                MidiEvent::NoteOn { note, velocity: _ } => {
-                    voice_manager.note_on(note);
+                MidiEvent::NoteOn { channel, note, velocity } => {
+                    engine.note_on(channel, note, velocity);
                 },
-                MidiEvent::NoteOff { note, velocity: _ } => {
-                    voice_manager.note_off(note);
+                MidiEvent::NoteOff { channel, note, velocity: _ } => {
+                    engine.note_off(channel, note);
                 },
                 // Handle other event types here as they're added
             }
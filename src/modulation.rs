@@ -0,0 +1,208 @@
+/// Routings each part's modulation matrix holds
+pub const MOD_SLOTS: usize = 4;
+
+/// Something that produces a modulation signal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModSource {
+    /// One LFO shared by every voice of the part, free-running by default
+    Lfo1,
+    /// An LFO on each voice, by default restarted with the voice's note
+    Lfo2,
+    /// The note-on velocity of each voice's note
+    Velocity,
+    /// MIDI CC 1
+    ModWheel,
+    /// Channel pressure
+    Aftertouch,
+    PitchBend,
+    /// A value drawn for each voice when its note starts
+    Random,
+}
+
+impl ModSource {
+    pub const ALL: [ModSource; 7] = [
+        ModSource::Lfo1,
+        ModSource::Lfo2,
+        ModSource::Velocity,
+        ModSource::ModWheel,
+        ModSource::Aftertouch,
+        ModSource::PitchBend,
+        ModSource::Random,
+    ];
+
+    /// The sources that are LFOs with settings of their own
+    pub const LFOS: [ModSource; 2] = [ModSource::Lfo1, ModSource::Lfo2];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModSource::Lfo1 => "LFO 1",
+            ModSource::Lfo2 => "LFO 2",
+            ModSource::Velocity => "Velocity",
+            ModSource::ModWheel => "Mod Wheel",
+            ModSource::Aftertouch => "Aftertouch",
+            ModSource::PitchBend => "Pitch Bend",
+            ModSource::Random => "Random",
+        }
+    }
+}
+
+/// Something a modulation signal can move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ModDestination {
+    /// The slot is unused
+    Off,
+    Pitch,
+    Cutoff,
+    Level,
+    /// Updated once per block rather than per sample
+    Pan,
+    PulseWidth,
+}
+
+impl ModDestination {
+    pub const ALL: [ModDestination; 6] = [
+        ModDestination::Off,
+        ModDestination::Pitch,
+        ModDestination::Cutoff,
+        ModDestination::Level,
+        ModDestination::Pan,
+        ModDestination::PulseWidth,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModDestination::Off => "Off",
+            ModDestination::Pitch => "Pitch",
+            ModDestination::Cutoff => "Cutoff",
+            ModDestination::Level => "Level",
+            ModDestination::Pan => "Pan",
+            ModDestination::PulseWidth => "Pulse Width",
+        }
+    }
+
+    /// How far a full-scale source moves the destination at amount 1.0:
+    /// semitones of pitch, octaves of cutoff, and fractions of level, pan and
+    /// pulse width.
+    fn scale(self) -> f32 {
+        match self {
+            ModDestination::Off => 0.0,
+            ModDestination::Pitch => 12.0,
+            ModDestination::Cutoff => 4.0,
+            ModDestination::Level => 1.0,
+            ModDestination::Pan => 1.0,
+            ModDestination::PulseWidth => 0.45,
+        }
+    }
+}
+
+/// One routing from a source to a destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModSlot {
+    pub source: ModSource,
+    pub destination: ModDestination,
+    /// -1.0 to 1.0, negative inverts the source
+    pub amount: f32,
+}
+
+/// The current value of every source. The LFOs, pitch bend and random are
+/// bipolar (-1.0 to 1.0), the rest run from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModSources {
+    pub lfo1: f32,
+    pub lfo2: f32,
+    pub velocity: f32,
+    pub mod_wheel: f32,
+    pub aftertouch: f32,
+    pub pitch_bend: f32,
+    pub random: f32,
+}
+
+impl ModSources {
+    fn value(&self, source: ModSource) -> f32 {
+        match source {
+            ModSource::Lfo1 => self.lfo1,
+            ModSource::Lfo2 => self.lfo2,
+            ModSource::Velocity => self.velocity,
+            ModSource::ModWheel => self.mod_wheel,
+            ModSource::Aftertouch => self.aftertouch,
+            ModSource::PitchBend => self.pitch_bend,
+            ModSource::Random => self.random,
+        }
+    }
+}
+
+/// How far the routings move each destination for one sample, summed over
+/// every slot that targets it.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModOffsets {
+    /// Semitones
+    pub pitch: f32,
+    /// Octaves
+    pub cutoff: f32,
+    /// Added to a gain of 1.0
+    pub level: f32,
+    pub pan: f32,
+    pub pulse_width: f32,
+}
+
+/// A part's modulation routings.
+#[derive(Debug, Clone, Copy)]
+pub struct ModMatrix {
+    slots: [ModSlot; MOD_SLOTS],
+}
+
+impl ModMatrix {
+    pub fn new() -> Self {
+        Self {
+            slots: [ModSlot { source: ModSource::Lfo1, destination: ModDestination::Off, amount: 0.0 }; MOD_SLOTS],
+        }
+    }
+
+    pub fn set_route(&mut self, slot: usize, source: ModSource, destination: ModDestination) {
+        if let Some(slot) = self.slots.get_mut(slot) {
+            slot.source = source;
+            slot.destination = destination;
+        }
+    }
+
+    pub fn set_amount(&mut self, slot: usize, amount: f32) {
+        if let Some(slot) = self.slots.get_mut(slot) {
+            slot.amount = amount.clamp(-1.0, 1.0);
+        }
+    }
+
+    fn active_slots(&self) -> impl Iterator<Item = &ModSlot> {
+        self.slots.iter().filter(|slot| slot.destination != ModDestination::Off && slot.amount != 0.0)
+    }
+
+    /// Whether any routing would move anything, so rendering can skip the matrix.
+    pub fn is_active(&self) -> bool {
+        self.active_slots().next().is_some()
+    }
+
+    /// Whether `source` is routed anywhere, so it only has to be run when heard.
+    pub fn uses(&self, source: ModSource) -> bool {
+        self.active_slots().any(|slot| slot.source == source)
+    }
+
+    /// Whether anything is routed to `destination`.
+    pub fn targets(&self, destination: ModDestination) -> bool {
+        self.active_slots().any(|slot| slot.destination == destination)
+    }
+
+    pub fn offsets(&self, sources: &ModSources) -> ModOffsets {
+        let mut offsets = ModOffsets::default();
+        for slot in self.active_slots() {
+            let value = sources.value(slot.source) * slot.amount * slot.destination.scale();
+            match slot.destination {
+                ModDestination::Off => {}
+                ModDestination::Pitch => offsets.pitch += value,
+                ModDestination::Cutoff => offsets.cutoff += value,
+                ModDestination::Level => offsets.level += value,
+                ModDestination::Pan => offsets.pan += value,
+                ModDestination::PulseWidth => offsets.pulse_width += value,
+            }
+        }
+        offsets
+    }
+}
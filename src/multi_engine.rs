@@ -0,0 +1,859 @@
+use crate::advanced::AdvancedConfig;
+use crate::voice_manager::VoiceManager;
+use crate::effects::Effects;
+use crate::envelope::{EnvelopeCurve, EnvelopeStage, ReleaseMode, RetriggerMode};
+use crate::key_tuning::KeyTuning;
+use crate::lfo::{LfoShape, LfoTrigger};
+use crate::macros::{MacroTarget, Macros};
+use crate::modulation::{ModDestination, ModSource};
+use crate::effects::{ChorusLfo, ChorusMode};
+use crate::filter::{FilterType, Oversampling};
+use crate::noise::{NoiseColor, NoiseFloor};
+use crate::params::{ParamError, SynthParams, PartParam, MasterParam};
+use crate::polyphony::AdaptivePolyphony;
+use crate::sampler::SampleBuffer;
+use crate::oscillator::Waveform;
+use crate::voice_manager::{NotePriority, PhaseMode, SameNotePolicy, StealPolicy, UnisonStereo, VoiceMode};
+use crate::status::{EngineStatus, SCOPE_SIZE};
+use crate::tempo::NoteDivision;
+use crate::wavetable::Wavetable;
+use crate::widener::Widener;
+use std::sync::Arc;
+use std::time::Duration;
+use crossbeam_channel::{Receiver, SendTimeoutError, Sender, TrySendError};
+use thiserror::Error;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+/// Number of independent parts hosted by the engine.
+pub const NUM_PARTS: usize = 4;
+
+/// Capacity of the event queue feeding the audio thread.
+pub const EVENT_QUEUE_SIZE: usize = 1024;
+
+/// How full the queue may get before note-ons are turned away. The rest is
+/// kept for note-offs and settings, so a storm of notes can't crowd out the
+/// note-offs that end them.
+const NOTE_ON_HIGH_WATER: usize = EVENT_QUEUE_SIZE * 3 / 4;
+
+/// Longest a note-off waits for room in the queue. The audio thread drains it
+/// every block, so this only runs out if the engine has stalled.
+const NOTE_OFF_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Largest block rendered in one pass. Longer buffers are split into chunks of
+/// this size so the scratch buffers can live in the engine without reallocation.
+pub const MAX_BLOCK_SIZE: usize = 512;
+
+/// Samples between control updates. Queued events and parameter changes are
+/// applied on this fixed grid, whatever buffer sizes the audio backend delivers.
+pub const CONTROL_BLOCK_SIZE: usize = 32;
+
+/// Seconds without a MIDI clock tempo before the internal tempo takes over again
+const CLOCK_TIMEOUT: f32 = 2.0;
+
+/// Point in the signal chain the oscilloscope listens to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScopeTap {
+    /// Oscillators through the envelopes, before the filter
+    Voices,
+    /// All parts after their filters and mixer strips
+    Filter,
+    /// The effects bus after the reverb
+    Reverb,
+    /// The master output after the chorus
+    Chorus,
+}
+
+impl ScopeTap {
+    pub const ALL: [ScopeTap; 4] = [ScopeTap::Voices, ScopeTap::Filter, ScopeTap::Reverb, ScopeTap::Chorus];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ScopeTap::Voices => "Post-voice",
+            ScopeTap::Filter => "Post-filter",
+            ScopeTap::Reverb => "Post-reverb",
+            ScopeTap::Chorus => "Post-chorus",
+        }
+    }
+}
+
+/// Discrete events sent to the engine from the UI and MIDI threads. They are
+/// queued and applied by the audio thread at the start of each block.
+#[derive(Debug, Clone)]
+pub enum EngineEvent {
+    /// Note on from MIDI, routed to every part listening on the channel
+    #[cfg(feature = "midi")]
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    #[cfg(feature = "midi")]
+    NoteOff { channel: u8, note: u8 },
+    /// Note on addressed to a single part, e.g. from the on-screen keyboard
+    PartNoteOn { part: usize, note: u8, velocity: u8 },
+    PartNoteOff { part: usize, note: u8 },
+    /// Channel aftertouch (0.0-1.0) from MIDI, routed like notes
+    #[cfg(feature = "midi")]
+    ChannelPressure { channel: u8, pressure: f32 },
+    /// Aftertouch addressed to a single part, e.g. simulated from held keys
+    PartPressure { part: usize, pressure: f32 },
+    /// Mod wheel (0.0-1.0) from MIDI CC 1, routed like notes
+    #[cfg(feature = "midi")]
+    ModWheel { channel: u8, value: f32 },
+    PartModWheel { part: usize, value: f32 },
+    /// Pitch bend (-1.0 to 1.0) from MIDI, routed like notes
+    #[cfg(feature = "midi")]
+    PitchBend { channel: u8, bend: f32 },
+    PartPitchBend { part: usize, bend: f32 },
+    SetWaveform { part: usize, waveform: Waveform },
+    /// A wavetable loaded off the audio thread, for the wavetable waveform
+    SetWavetable { part: usize, table: Arc<Wavetable> },
+    /// A recording loaded off the audio thread, for the sampler waveform
+    SetSample { part: usize, sample: Arc<SampleBuffer> },
+    SetSampleLoop { part: usize, enabled: bool },
+    SetPhaseMode { part: usize, mode: PhaseMode },
+    SetStealPolicy { part: usize, policy: StealPolicy },
+    SetSameNotePolicy { part: usize, policy: SameNotePolicy },
+    SetVoiceMode { part: usize, mode: VoiceMode },
+    SetLatch { part: usize, enabled: bool },
+    SetReleaseMode { part: usize, mode: ReleaseMode },
+    /// What a note-on does to an envelope that is still sounding
+    SetRetriggerMode { part: usize, mode: RetriggerMode },
+    /// Shape of the attack, decay or release segment
+    SetEnvelopeCurve { part: usize, stage: EnvelopeStage, curve: EnvelopeCurve },
+    /// Retunes one key of the shared key tuning table, in cents
+    SetKeyOffset { note: u8, cents: f32 },
+    /// Whether the part plays the key tuning table or equal temperament
+    SetKeyTuningEnabled { part: usize, enabled: bool },
+    /// Releases every note on every part, including latched ones
+    AllNotesOff,
+    SetNotePriority { part: usize, priority: NotePriority },
+    SetUnisonVoices { part: usize, voices: usize },
+    SetUnisonStereo { part: usize, mode: UnisonStereo },
+    SetAutoGain { part: usize, enabled: bool },
+    SetFilterOversampling { part: usize, oversampling: Oversampling },
+    SetFilterType { part: usize, filter_type: FilterType },
+    SetCutoffLfoShape { part: usize, shape: LfoShape },
+    SetLfoShape { part: usize, lfo: ModSource, shape: LfoShape },
+    SetLfoTrigger { part: usize, lfo: ModSource, trigger: LfoTrigger },
+    /// Locks an LFO to a note length, or frees it with `None`
+    SetLfoSync { part: usize, lfo: ModSource, sync: Option<NoteDivision> },
+    /// Assigns one of a macro's target slots, or clears it with `None`. The
+    /// macro positions themselves are master parameters
+    SetMacroTarget { index: usize, slot: usize, target: Option<MacroTarget> },
+    /// Routes a modulation matrix slot, its amount being a part parameter
+    SetModRoute { part: usize, slot: usize, source: ModSource, destination: ModDestination },
+    SetChorusMode(ChorusMode),
+    SetChorusLfo(ChorusLfo),
+    /// Locks one side of the delay (0 left, 1 right) to a note length, or
+    /// frees it with `None`
+    SetDelaySync { channel: usize, sync: Option<NoteDivision> },
+    /// Bounces the delay's repeats between the sides
+    SetDelayPingPong(bool),
+    /// Switches the flanger between its normal sweep and through-zero mode
+    SetFlangerThroughZero(bool),
+    SetNoiseColor(NoiseColor),
+    SetScopeTap(ScopeTap),
+    /// Tempo measured from incoming MIDI clock, in BPM
+    #[cfg(feature = "midi")]
+    ClockTempo(f32),
+    /// Voices per part, fixed at `max_voices` or adapted to the DSP load
+    /// between the two bounds
+    SetPolyphony { adaptive: bool, min_voices: usize, max_voices: usize },
+    /// New tuning constants from the advanced config file
+    SetAdvanced(AdvancedConfig),
+}
+
+impl EngineEvent {
+    /// The part an event is addressed to, for events sent to a single part.
+    pub fn part(&self) -> Option<usize> {
+        match self {
+            EngineEvent::PartNoteOn { part, .. }
+            | EngineEvent::PartNoteOff { part, .. }
+            | EngineEvent::PartPressure { part, .. }
+            | EngineEvent::PartModWheel { part, .. }
+            | EngineEvent::PartPitchBend { part, .. }
+            | EngineEvent::SetWaveform { part, .. }
+            | EngineEvent::SetWavetable { part, .. }
+            | EngineEvent::SetSample { part, .. }
+            | EngineEvent::SetSampleLoop { part, .. }
+            | EngineEvent::SetPhaseMode { part, .. }
+            | EngineEvent::SetStealPolicy { part, .. }
+            | EngineEvent::SetSameNotePolicy { part, .. }
+            | EngineEvent::SetVoiceMode { part, .. }
+            | EngineEvent::SetLatch { part, .. }
+            | EngineEvent::SetReleaseMode { part, .. }
+            | EngineEvent::SetRetriggerMode { part, .. }
+            | EngineEvent::SetEnvelopeCurve { part, .. }
+            | EngineEvent::SetKeyTuningEnabled { part, .. }
+            | EngineEvent::SetNotePriority { part, .. }
+            | EngineEvent::SetUnisonVoices { part, .. }
+            | EngineEvent::SetUnisonStereo { part, .. }
+            | EngineEvent::SetAutoGain { part, .. }
+            | EngineEvent::SetFilterOversampling { part, .. }
+            | EngineEvent::SetFilterType { part, .. }
+            | EngineEvent::SetCutoffLfoShape { part, .. }
+            | EngineEvent::SetLfoShape { part, .. }
+            | EngineEvent::SetLfoTrigger { part, .. }
+            | EngineEvent::SetLfoSync { part, .. }
+            | EngineEvent::SetModRoute { part, .. } => Some(*part),
+            _ => None,
+        }
+    }
+
+    fn is_note_on(&self) -> bool {
+        match self {
+            #[cfg(feature = "midi")]
+            EngineEvent::NoteOn { .. } => true,
+            EngineEvent::PartNoteOn { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Events that end notes, which must not be lost or notes hang.
+    fn is_note_off(&self) -> bool {
+        match self {
+            #[cfg(feature = "midi")]
+            EngineEvent::NoteOff { .. } => true,
+            EngineEvent::PartNoteOff { .. } | EngineEvent::AllNotesOff => true,
+            _ => false,
+        }
+    }
+}
+
+/// Why an event was not queued.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EventError {
+    /// The queue had no room, or too little to take another note-on
+    #[error("the engine's event queue is full")]
+    QueueFull,
+    /// The audio thread has shut down
+    #[error("the engine has stopped")]
+    Stopped,
+    /// The event addresses a part that doesn't exist
+    #[error(transparent)]
+    Invalid(#[from] ParamError),
+}
+
+/// Sending half of the engine event queue, cloned into the UI, MIDI and
+/// background threads.
+#[derive(Clone)]
+pub struct EventSender {
+    sender: Sender<EngineEvent>,
+}
+
+impl EventSender {
+    /// Queues an event for the audio thread. Note-ons are turned away once
+    /// the queue is past its high-water mark, keeping room for the note-offs;
+    /// those wait briefly for room rather than fail, so they are only lost if
+    /// the engine has stalled. Anything else fails straight away on a full
+    /// queue, for the sender to report. Events for a part that doesn't exist
+    /// are rejected before they reach the audio thread.
+    pub fn try_send(&self, event: EngineEvent) -> Result<(), EventError> {
+        if let Some(part) = event.part().filter(|&part| part >= NUM_PARTS) {
+            return Err(ParamError::NoSuchPart(part).into());
+        }
+        if event.is_note_on() && self.sender.len() >= NOTE_ON_HIGH_WATER {
+            return Err(EventError::QueueFull);
+        }
+        if event.is_note_off() {
+            return self.sender.send_timeout(event, NOTE_OFF_TIMEOUT).map_err(|err| match err {
+                SendTimeoutError::Timeout(_) => EventError::QueueFull,
+                SendTimeoutError::Disconnected(_) => EventError::Stopped,
+            });
+        }
+        self.sender.try_send(event).map_err(|err| match err {
+            TrySendError::Full(_) => EventError::QueueFull,
+            TrySendError::Disconnected(_) => EventError::Stopped,
+        })
+    }
+}
+
+/// Creates the bounded queue that carries events to the audio thread.
+pub fn event_queue() -> (EventSender, Receiver<EngineEvent>) {
+    let (sender, receiver) = crossbeam_channel::bounded(EVENT_QUEUE_SIZE);
+    (EventSender { sender }, receiver)
+}
+
+/// A single timbre of the multi-timbral engine: its own patch (voices, envelope,
+/// filter settings) listening on its own MIDI channel, with mixer settings.
+pub struct Part {
+    pub voice_manager: VoiceManager,
+    /// MIDI channel this part responds to (0-15), kept in step with the
+    /// parameter store
+    pub midi_channel: u8,
+    pub level: f32,
+    /// -1.0 (left) to 1.0 (right)
+    pub pan: f32,
+    /// Widens the part's sound before it reaches the mixer
+    widener: Widener,
+    /// Amount of the part routed through the master effects, the rest bypasses them
+    pub fx_send: f32,
+    pub mute: bool,
+    pub solo: bool,
+    /// Peak output level with a falling release, for the mixer meters
+    pub meter: f32,
+    /// The part's dry output for the block being rendered (left, right)
+    buffer: [[f32; MAX_BLOCK_SIZE]; 2],
+}
+
+impl Part {
+    fn new(sample_rate: f32, num_voices: usize, midi_channel: u8) -> Self {
+        Self {
+            voice_manager: VoiceManager::new(sample_rate, num_voices),
+            midi_channel,
+            level: 1.0,
+            pan: 0.0,
+            widener: Widener::new(sample_rate),
+            fx_send: 1.0,
+            mute: false,
+            solo: false,
+            meter: 0.0,
+            buffer: [[0.0; MAX_BLOCK_SIZE]; 2],
+        }
+    }
+
+    /// Renders the first `len` frames of the part's voices into its own buffer.
+    fn render(&mut self, len: usize) {
+        let [left, right] = &mut self.buffer;
+        self.voice_manager.render_block(&mut left[..len], &mut right[..len]);
+        self.widener.process_block(&mut left[..len], &mut right[..len]);
+    }
+
+    fn set_param(&mut self, param: PartParam, value: f32) {
+        match param {
+            PartParam::Volume => self.voice_manager.set_volume(value),
+            PartParam::Drift => self.voice_manager.set_drift(value),
+            PartParam::HumanizePitch => self.voice_manager.set_humanize_pitch(value),
+            PartParam::HumanizePan => self.voice_manager.set_humanize_pan(value),
+            PartParam::HumanizeLevel => self.voice_manager.set_humanize_level(value),
+            PartParam::Octave => self.voice_manager.set_octave(value),
+            PartParam::Semitone => self.voice_manager.set_semitone(value),
+            PartParam::FineTune => self.voice_manager.set_fine_tune(value),
+            PartParam::StartPhase => self.voice_manager.set_start_phase(value),
+            PartParam::PulseWidth => self.voice_manager.set_pulse_width(value),
+            PartParam::PwmAmount => self.voice_manager.set_pwm_amount(value),
+            PartParam::PwmRate => self.voice_manager.set_pwm_rate(value),
+            PartParam::WavePosition => self.voice_manager.set_wave_position(value),
+            PartParam::SupersawDetune => self.voice_manager.set_supersaw_detune(value),
+            PartParam::SupersawMix => self.voice_manager.set_supersaw_mix(value),
+            PartParam::SampleRoot => self.voice_manager.set_root_note(value),
+            PartParam::SampleStart => self.voice_manager.set_sample_start(value),
+            PartParam::LoopStart => self.voice_manager.set_loop_start(value),
+            PartParam::LoopEnd => self.voice_manager.set_loop_end(value),
+            PartParam::FoldAmount => self.voice_manager.set_fold_amount(value),
+            PartParam::FoldSymmetry => self.voice_manager.set_fold_symmetry(value),
+            PartParam::EnvelopeDelay => self.voice_manager.set_envelope_delay(value),
+            PartParam::Attack => self.voice_manager.set_attack(value),
+            PartParam::Hold => self.voice_manager.set_hold(value),
+            PartParam::Decay => self.voice_manager.set_decay(value),
+            PartParam::Sustain => self.voice_manager.set_sustain(value),
+            PartParam::Release => self.voice_manager.set_release(value),
+            PartParam::VelocityToLevel => self.voice_manager.set_velocity_to_level(value),
+            PartParam::VelocityToAttack => self.voice_manager.set_velocity_to_attack(value),
+            PartParam::FilterCutoff => self.voice_manager.set_filter_cutoff(value),
+            PartParam::FilterResonance => self.voice_manager.set_filter_resonance(value),
+            PartParam::FilterDrive => self.voice_manager.set_filter_drive(value),
+            PartParam::FilterSaturation => self.voice_manager.set_filter_saturation(value),
+            PartParam::CombFeedback => self.voice_manager.set_comb_feedback(value),
+            PartParam::CombKeyTrack => self.voice_manager.set_comb_key_tracking(value),
+            PartParam::CutoffLfoRate => self.voice_manager.set_cutoff_lfo_rate(value),
+            PartParam::Lfo1Rate => self.voice_manager.set_lfo_rate(ModSource::Lfo1, value),
+            PartParam::Lfo1Depth => self.voice_manager.set_lfo_depth(ModSource::Lfo1, value),
+            PartParam::Lfo1Phase => self.voice_manager.set_lfo_phase(ModSource::Lfo1, value),
+            PartParam::Lfo1FadeIn => self.voice_manager.set_lfo_fade_in(ModSource::Lfo1, value),
+            PartParam::Lfo2Rate => self.voice_manager.set_lfo_rate(ModSource::Lfo2, value),
+            PartParam::Lfo2Depth => self.voice_manager.set_lfo_depth(ModSource::Lfo2, value),
+            PartParam::Lfo2Phase => self.voice_manager.set_lfo_phase(ModSource::Lfo2, value),
+            PartParam::Lfo2FadeIn => self.voice_manager.set_lfo_fade_in(ModSource::Lfo2, value),
+            PartParam::ModAmount1 => self.voice_manager.set_mod_amount(0, value),
+            PartParam::ModAmount2 => self.voice_manager.set_mod_amount(1, value),
+            PartParam::ModAmount3 => self.voice_manager.set_mod_amount(2, value),
+            PartParam::ModAmount4 => self.voice_manager.set_mod_amount(3, value),
+            PartParam::CutoffLfoDepth => self.voice_manager.set_cutoff_lfo_depth(value),
+            PartParam::UnisonDetune => self.voice_manager.set_unison_detune(value),
+            PartParam::UnisonSpread => self.voice_manager.set_unison_spread(value),
+            PartParam::Level => self.level = value.clamp(0.0, 2.0),
+            PartParam::Pan => self.pan = value.clamp(-1.0, 1.0),
+            PartParam::Width => self.widener.set_width(value),
+            PartParam::FxSend => self.fx_send = value.clamp(0.0, 1.0),
+            PartParam::Mute => self.mute = value >= 0.5,
+            PartParam::Solo => self.solo = value >= 0.5,
+        }
+    }
+
+    /// Balance-style pan gains, so a centered part keeps unity gain on both sides.
+    fn pan_gains(&self) -> (f32, f32) {
+        let left = (1.0 - self.pan).min(1.0);
+        let right = (1.0 + self.pan).min(1.0);
+        (left * self.level, right * self.level)
+    }
+}
+
+/// Hosts several parts on different MIDI channels and mixes them into the
+/// shared master effect chain, so one instance can cover bass, pad and lead.
+pub struct MultiEngine {
+    pub parts: Vec<Part>,
+    effects: Effects,
+    pub master_level: f32,
+    /// Widens the whole mix ahead of the noise floor and master level
+    master_widener: Widener,
+    /// Vintage hiss mixed into the master bus
+    noise: NoiseFloor,
+    /// Peak levels of the master output (left, right)
+    pub master_meter: (f32, f32),
+    meter_release: f32,
+    /// Parameter values last taken from the shared store, to apply only what changed
+    applied_part_params: Vec<[f32; PartParam::ALL.len()]>,
+    applied_master_params: [f32; MasterParam::ALL.len()],
+    /// Scratch buffers for the effects send and dry buses
+    send_buffer: [[f32; MAX_BLOCK_SIZE]; 2],
+    dry_buffer: [[f32; MAX_BLOCK_SIZE]; 2],
+    scope_tap: ScopeTap,
+    /// Mono signal at the scope tap for the chunk being rendered
+    scope_buffer: [f32; MAX_BLOCK_SIZE],
+    /// Ring buffer of recent scope samples, published with the status
+    scope: Vec<f32>,
+    scope_position: usize,
+    /// The control block being played out (left, right), and how much of it
+    /// has been handed to the host so far
+    control_block: [[f32; CONTROL_BLOCK_SIZE]; 2],
+    control_position: usize,
+    /// Voice limit shared by every part
+    polyphony: AdaptivePolyphony,
+    /// Offsets the macro knobs add on top of the part parameters
+    macros: Macros,
+    /// Tempo from the master tempo parameter
+    internal_tempo: f32,
+    /// Tempo of incoming MIDI clock, which takes priority while it keeps coming
+    clock_tempo: Option<f32>,
+    /// Samples since the last clock tempo, and how many mean the clock has stopped
+    clock_age: usize,
+    clock_timeout: usize,
+}
+
+impl MultiEngine {
+    pub fn new(sample_rate: f32, voices_per_part: usize) -> Self {
+        let mut engine = Self {
+            parts: (0..NUM_PARTS)
+                .map(|i| Part::new(sample_rate, voices_per_part, i as u8))
+                .collect(),
+            effects: Effects::new(sample_rate),
+            master_level: 1.0,
+            master_widener: Widener::new(sample_rate),
+            noise: NoiseFloor::new(),
+            master_meter: (0.0, 0.0),
+            // Meters fall by roughly 20 dB over 300 ms
+            meter_release: (-2.3 / (0.3 * sample_rate)).exp(),
+            // NaN never compares equal, so the first apply_params pushes every value
+            applied_part_params: vec![[f32::NAN; PartParam::ALL.len()]; NUM_PARTS],
+            applied_master_params: [f32::NAN; MasterParam::ALL.len()],
+            send_buffer: [[0.0; MAX_BLOCK_SIZE]; 2],
+            dry_buffer: [[0.0; MAX_BLOCK_SIZE]; 2],
+            scope_tap: ScopeTap::Chorus,
+            scope_buffer: [0.0; MAX_BLOCK_SIZE],
+            scope: vec![0.0; SCOPE_SIZE],
+            scope_position: 0,
+            control_block: [[0.0; CONTROL_BLOCK_SIZE]; 2],
+            // Starts used up, so the first callback applies controls first
+            control_position: CONTROL_BLOCK_SIZE,
+            polyphony: AdaptivePolyphony::new(),
+            macros: Macros::new(),
+            internal_tempo: MasterParam::Tempo.default_value(),
+            clock_tempo: None,
+            clock_age: 0,
+            clock_timeout: (CLOCK_TIMEOUT * sample_rate) as usize,
+        };
+        engine.set_voice_limit(engine.polyphony.limit());
+        engine
+    }
+
+    /// Pulls changed values from the shared parameter store into the DSP objects,
+    /// with the macros' offsets added to the part parameters. Called by the
+    /// audio thread once per callback.
+    pub fn apply_params(&mut self, params: &SynthParams) {
+        for (index, part) in self.parts.iter_mut().enumerate() {
+            part.midi_channel = params.channel(index);
+            for param in PartParam::ALL {
+                let mut value = params.part(index, param);
+                let offset = self.macros.offset(index, param);
+                if offset != 0.0 {
+                    let range = param.range();
+                    value = (value + offset).clamp(*range.start(), *range.end());
+                }
+                let applied = &mut self.applied_part_params[index][param as usize];
+                if value != *applied {
+                    *applied = value;
+                    part.set_param(param, value);
+                }
+            }
+        }
+
+        for param in MasterParam::ALL {
+            let value = params.master(param);
+            let applied = &mut self.applied_master_params[param as usize];
+            if value != *applied {
+                *applied = value;
+                match param {
+                    MasterParam::Level => self.master_level = value.clamp(0.0, 2.0),
+                    MasterParam::Width => self.master_widener.set_width(value),
+                    MasterParam::ReverbDecay => self.effects.set_reverb_decay(value),
+                    MasterParam::ReverbWet => self.effects.set_reverb_wet(value),
+                    MasterParam::ChorusRate => self.effects.set_chorus_rate(value),
+                    MasterParam::ChorusDepth => self.effects.set_chorus_depth(value),
+                    MasterParam::NoiseLevel => self.noise.set_level(value),
+                    MasterParam::Tempo => {
+                        self.internal_tempo = value;
+                        self.update_tempo();
+                    }
+                    MasterParam::DelayTimeLeft => self.effects.set_delay_time(0, value),
+                    MasterParam::DelayTimeRight => self.effects.set_delay_time(1, value),
+                    MasterParam::DelayFeedback => self.effects.set_delay_feedback(value),
+                    MasterParam::DelayLowCut => self.effects.set_delay_low_cut(value),
+                    MasterParam::DelayHighCut => self.effects.set_delay_high_cut(value),
+                    MasterParam::DelayWet => self.effects.set_delay_wet(value),
+                    MasterParam::FlangerRate => self.effects.set_flanger_rate(value),
+                    MasterParam::FlangerDepth => self.effects.set_flanger_depth(value),
+                    MasterParam::FlangerFeedback => self.effects.set_flanger_feedback(value),
+                    MasterParam::FlangerMix => self.effects.set_flanger_mix(value),
+                    MasterParam::Macro1 => self.macros.set_value(0, value),
+                    MasterParam::Macro2 => self.macros.set_value(1, value),
+                    MasterParam::Macro3 => self.macros.set_value(2, value),
+                    MasterParam::Macro4 => self.macros.set_value(3, value),
+                }
+            }
+        }
+    }
+
+    /// Applies a queued event. Events for a part that doesn't exist are
+    /// dropped, since anything holding the receiver's sender can queue them.
+    pub fn handle_event(&mut self, event: EngineEvent) {
+        if event.part().is_some_and(|part| part >= self.parts.len()) {
+            return;
+        }
+        match event {
+            #[cfg(feature = "midi")]
+            EngineEvent::NoteOn { channel, note, velocity } => self.note_on(channel, note, velocity),
+            #[cfg(feature = "midi")]
+            EngineEvent::NoteOff { channel, note } => self.note_off(channel, note),
+            EngineEvent::PartNoteOn { part, note, velocity } => self.parts[part].voice_manager.note_on(note, velocity),
+            EngineEvent::PartNoteOff { part, note } => self.parts[part].voice_manager.note_off(note),
+            #[cfg(feature = "midi")]
+            EngineEvent::ChannelPressure { channel, pressure } => {
+                for part in self.parts.iter_mut().filter(|p| p.midi_channel == channel) {
+                    part.voice_manager.set_pressure(pressure);
+                }
+            }
+            EngineEvent::PartPressure { part, pressure } => self.parts[part].voice_manager.set_pressure(pressure),
+            #[cfg(feature = "midi")]
+            EngineEvent::ModWheel { channel, value } => {
+                for part in self.parts.iter_mut().filter(|p| p.midi_channel == channel) {
+                    part.voice_manager.set_mod_wheel(value);
+                }
+            }
+            EngineEvent::PartModWheel { part, value } => self.parts[part].voice_manager.set_mod_wheel(value),
+            #[cfg(feature = "midi")]
+            EngineEvent::PitchBend { channel, bend } => {
+                for part in self.parts.iter_mut().filter(|p| p.midi_channel == channel) {
+                    part.voice_manager.set_pitch_bend(bend);
+                }
+            }
+            EngineEvent::PartPitchBend { part, bend } => self.parts[part].voice_manager.set_pitch_bend(bend),
+            EngineEvent::SetWaveform { part, waveform } => self.parts[part].voice_manager.set_waveform(waveform),
+            EngineEvent::SetWavetable { part, table } => self.parts[part].voice_manager.set_wavetable(table),
+            EngineEvent::SetSample { part, sample } => self.parts[part].voice_manager.set_sample(sample),
+            EngineEvent::SetSampleLoop { part, enabled } => self.parts[part].voice_manager.set_sample_loop(enabled),
+            EngineEvent::SetPhaseMode { part, mode } => self.parts[part].voice_manager.set_phase_mode(mode),
+            EngineEvent::SetStealPolicy { part, policy } => self.parts[part].voice_manager.set_steal_policy(policy),
+            EngineEvent::SetSameNotePolicy { part, policy } => self.parts[part].voice_manager.set_same_note_policy(policy),
+            EngineEvent::SetVoiceMode { part, mode } => self.parts[part].voice_manager.set_voice_mode(mode),
+            EngineEvent::SetLatch { part, enabled } => self.parts[part].voice_manager.set_latch(enabled),
+            EngineEvent::SetReleaseMode { part, mode } => self.parts[part].voice_manager.set_release_mode(mode),
+            EngineEvent::SetRetriggerMode { part, mode } => self.parts[part].voice_manager.set_retrigger_mode(mode),
+            EngineEvent::SetEnvelopeCurve { part, stage, curve } => self.parts[part].voice_manager.set_envelope_curve(stage, curve),
+            EngineEvent::SetKeyOffset { note, cents } => {
+                for part in &mut self.parts {
+                    part.voice_manager.set_key_offset(note, cents);
+                }
+            }
+            EngineEvent::SetKeyTuningEnabled { part, enabled } => self.parts[part].voice_manager.set_key_tuning_enabled(enabled),
+            EngineEvent::AllNotesOff => {
+                for part in &mut self.parts {
+                    part.voice_manager.all_notes_off();
+                }
+            }
+            EngineEvent::SetNotePriority { part, priority } => self.parts[part].voice_manager.set_note_priority(priority),
+            EngineEvent::SetUnisonVoices { part, voices } => self.parts[part].voice_manager.set_unison_voices(voices),
+            EngineEvent::SetUnisonStereo { part, mode } => self.parts[part].voice_manager.set_unison_stereo(mode),
+            EngineEvent::SetAutoGain { part, enabled } => self.parts[part].voice_manager.set_filter_auto_gain(enabled),
+            EngineEvent::SetFilterType { part, filter_type } => self.parts[part].voice_manager.set_filter_type(filter_type),
+            EngineEvent::SetFilterOversampling { part, oversampling } => {
+                self.parts[part].voice_manager.set_filter_oversampling(oversampling)
+            }
+            EngineEvent::SetCutoffLfoShape { part, shape } => self.parts[part].voice_manager.set_cutoff_lfo_shape(shape),
+            EngineEvent::SetLfoShape { part, lfo, shape } => self.parts[part].voice_manager.set_lfo_shape(lfo, shape),
+            EngineEvent::SetLfoTrigger { part, lfo, trigger } => self.parts[part].voice_manager.set_lfo_trigger(lfo, trigger),
+            EngineEvent::SetLfoSync { part, lfo, sync } => self.parts[part].voice_manager.set_lfo_sync(lfo, sync),
+            EngineEvent::SetMacroTarget { index, slot, target } => self.macros.set_target(index, slot, target),
+            EngineEvent::SetModRoute { part, slot, source, destination } => {
+                self.parts[part].voice_manager.set_mod_route(slot, source, destination)
+            }
+            EngineEvent::SetChorusMode(mode) => self.effects.set_chorus_mode(mode),
+            EngineEvent::SetChorusLfo(shape) => self.effects.set_chorus_lfo(shape),
+            EngineEvent::SetDelaySync { channel, sync } => self.effects.set_delay_sync(channel, sync),
+            EngineEvent::SetDelayPingPong(enabled) => self.effects.set_delay_ping_pong(enabled),
+            EngineEvent::SetFlangerThroughZero(enabled) => self.effects.set_flanger_through_zero(enabled),
+            EngineEvent::SetNoiseColor(color) => self.noise.set_color(color),
+            EngineEvent::SetScopeTap(tap) => self.set_scope_tap(tap),
+            #[cfg(feature = "midi")]
+            EngineEvent::ClockTempo(bpm) => {
+                self.clock_tempo = Some(bpm);
+                self.clock_age = 0;
+                self.update_tempo();
+            }
+            EngineEvent::SetPolyphony { adaptive, min_voices, max_voices } => {
+                self.polyphony.configure(adaptive, min_voices, max_voices);
+                self.set_voice_limit(self.polyphony.limit());
+            }
+            EngineEvent::SetAdvanced(config) => self.set_advanced(&config),
+        }
+    }
+
+    /// The tempo synced LFOs and delay times follow: MIDI clock when it is running, otherwise
+    /// the master tempo.
+    pub fn tempo(&self) -> f32 {
+        self.clock_tempo.unwrap_or(self.internal_tempo)
+    }
+
+    fn update_tempo(&mut self) {
+        let bpm = self.tempo();
+        for part in &mut self.parts {
+            part.voice_manager.set_tempo(bpm);
+        }
+        self.effects.set_delay_tempo(bpm);
+    }
+
+    /// Applies the tuning constants from the advanced config file.
+    pub fn set_advanced(&mut self, config: &AdvancedConfig) {
+        for part in &mut self.parts {
+            part.voice_manager.set_filter_tuning(&config.filter);
+        }
+        self.effects.set_chorus_tuning(&config.chorus);
+        self.effects.set_reverb_tuning(&config.reverb);
+    }
+
+    /// Loads a key tuning table into every part.
+    pub fn set_key_tuning(&mut self, tuning: &KeyTuning) {
+        for part in &mut self.parts {
+            part.voice_manager.set_key_tuning(tuning);
+        }
+    }
+
+    fn set_voice_limit(&mut self, limit: usize) {
+        for part in &mut self.parts {
+            part.voice_manager.set_voice_limit(limit);
+        }
+    }
+
+    /// Feeds the measured load of a callback lasting `duration` seconds to the
+    /// adaptive polyphony, which may change the voice limit for the next one.
+    pub fn record_load(&mut self, load: f32, duration: f32) {
+        let saturated = self.parts.iter().any(|p| p.voice_manager.is_saturated());
+        if let Some(limit) = self.polyphony.record(load, duration, saturated) {
+            self.set_voice_limit(limit);
+        }
+    }
+
+    fn set_scope_tap(&mut self, tap: ScopeTap) {
+        self.scope_tap = tap;
+        // The pre-filter signal costs an extra buffer per voice, so only render it when shown
+        for part in &mut self.parts {
+            part.voice_manager.set_pre_filter_tap(tap == ScopeTap::Voices);
+        }
+    }
+
+    /// Publishes meters and voice activity for the UI. Called by the audio
+    /// thread after each block.
+    pub fn publish_status(&self, status: &EngineStatus) {
+        let mut active_voices = 0;
+        for (index, part) in self.parts.iter().enumerate() {
+            status.set_part_meter(index, part.meter);
+            status.set_envelope_position(index, part.voice_manager.envelope_position());
+            active_voices += part.voice_manager.voices.iter().filter(|v| v.is_active()).count();
+        }
+        status.set_master_meter(self.master_meter.0, self.master_meter.1);
+        status.set_active_voices(active_voices);
+        status.set_voice_limit(self.polyphony.limit());
+        status.set_scope(&self.scope, self.scope_position);
+        status.set_tempo(self.tempo(), self.clock_tempo.is_some());
+    }
+
+    /// Routes a note-on to every part listening on `channel`.
+    #[cfg(feature = "midi")]
+    pub fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+        for part in self.parts.iter_mut().filter(|p| p.midi_channel == channel) {
+            part.voice_manager.note_on(note, velocity);
+        }
+    }
+
+    /// Routes a note-off to every part listening on `channel`.
+    #[cfg(feature = "midi")]
+    pub fn note_off(&mut self, channel: u8, note: u8) {
+        for part in self.parts.iter_mut().filter(|p| p.midi_channel == channel) {
+            part.voice_manager.note_off(note);
+        }
+    }
+
+    /// Fills `left` and `right` for an audio callback of any length. The engine
+    /// runs in fixed control blocks: before each one, queued events are handled
+    /// and changed parameters applied, so modulation moves on the same grid
+    /// whether the host asks for 17 frames or 2048. A block that runs past the
+    /// end of the callback is finished at the start of the next.
+    pub fn process(&mut self, left: &mut [f32], right: &mut [f32], params: &SynthParams, events: &Receiver<EngineEvent>) {
+        let mut written = 0;
+        while written < left.len() {
+            if self.control_position == CONTROL_BLOCK_SIZE {
+                while let Ok(event) = events.try_recv() {
+                    self.handle_event(event);
+                }
+                self.apply_params(params);
+                if self.clock_tempo.is_some() {
+                    self.clock_age += CONTROL_BLOCK_SIZE;
+                    if self.clock_age > self.clock_timeout {
+                        self.clock_tempo = None;
+                        self.update_tempo();
+                    }
+                }
+
+                let [mut block_left, mut block_right] = self.control_block;
+                self.render_block(&mut block_left, &mut block_right);
+                self.control_block = [block_left, block_right];
+                self.control_position = 0;
+            }
+
+            let count = (CONTROL_BLOCK_SIZE - self.control_position).min(left.len() - written);
+            let block = self.control_position..self.control_position + count;
+            left[written..written + count].copy_from_slice(&self.control_block[0][block.clone()]);
+            right[written..written + count].copy_from_slice(&self.control_block[1][block]);
+            self.control_position += count;
+            written += count;
+        }
+    }
+
+    /// Renders the mixed output of all parts into `left` and `right`, which must
+    /// have the same length.
+    pub fn render_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (left, right) in left.chunks_mut(MAX_BLOCK_SIZE).zip(right.chunks_mut(MAX_BLOCK_SIZE)) {
+            self.render_chunk(left, right);
+        }
+    }
+
+    fn render_chunk(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let len = left.len();
+        let [send_left, send_right] = &mut self.send_buffer;
+        let [dry_left, dry_right] = &mut self.dry_buffer;
+        let (send_left, send_right) = (&mut send_left[..len], &mut send_right[..len]);
+        let (dry_left, dry_right) = (&mut dry_left[..len], &mut dry_right[..len]);
+        send_left.fill(0.0);
+        send_right.fill(0.0);
+        dry_left.fill(0.0);
+        dry_right.fill(0.0);
+        let scope = &mut self.scope_buffer[..len];
+        scope.fill(0.0);
+
+        let any_solo = self.parts.iter().any(|p| p.solo);
+
+        // Parts share nothing while rendering voices, so with the `parallel`
+        // feature they are spread over rayon's worker threads. Mixing and the
+        // effects chain stay on the audio thread.
+        #[cfg(feature = "parallel")]
+        self.parts.par_iter_mut().for_each(|part| part.render(len));
+        #[cfg(not(feature = "parallel"))]
+        self.parts.iter_mut().for_each(|part| part.render(len));
+
+        for part in &mut self.parts {
+            let [part_left, part_right] = &part.buffer;
+            let audible = if any_solo { part.solo } else { !part.mute };
+            let (gain_left, gain_right) = if audible { part.pan_gains() } else { (0.0, 0.0) };
+
+            match self.scope_tap {
+                ScopeTap::Voices => {
+                    let pre_filter = part.voice_manager.pre_filter(len);
+                    for i in 0..len {
+                        scope[i] += pre_filter[i] * (gain_left + gain_right) * 0.5;
+                    }
+                }
+                ScopeTap::Filter => {
+                    for i in 0..len {
+                        scope[i] += (part_left[i] * gain_left + part_right[i] * gain_right) * 0.5;
+                    }
+                }
+                ScopeTap::Reverb | ScopeTap::Chorus => {}
+            }
+
+            for i in 0..len {
+                let left = part_left[i] * gain_left;
+                let right = part_right[i] * gain_right;
+                part.meter = (part.meter * self.meter_release).max(left.abs().max(right.abs()));
+
+                send_left[i] += left * part.fx_send;
+                send_right[i] += right * part.fx_send;
+                dry_left[i] += left * (1.0 - part.fx_send);
+                dry_right[i] += right * (1.0 - part.fx_send);
+            }
+        }
+
+        for i in 0..len {
+            let (fx_left, fx_right) = self.effects.process(send_left[i], send_right[i]);
+            match self.scope_tap {
+                ScopeTap::Reverb => {
+                    let (reverb_left, reverb_right) = self.effects.post_reverb();
+                    scope[i] = (reverb_left + reverb_right) * 0.5;
+                }
+                ScopeTap::Voices | ScopeTap::Filter | ScopeTap::Chorus => {}
+            }
+
+            let (mix_left, mix_right) = self.master_widener.process(fx_left + dry_left[i], fx_right + dry_right[i]);
+            let (noise_left, noise_right) = self.noise.next();
+            left[i] = (mix_left + noise_left) * self.master_level;
+            right[i] = (mix_right + noise_right) * self.master_level;
+            self.master_meter.0 = (self.master_meter.0 * self.meter_release).max(left[i].abs());
+            self.master_meter.1 = (self.master_meter.1 * self.meter_release).max(right[i].abs());
+            if self.scope_tap == ScopeTap::Chorus {
+                scope[i] = (left[i] + right[i]) * 0.5;
+            }
+        }
+
+        for &sample in scope.iter() {
+            self.scope[self.scope_position] = sample;
+            self.scope_position = (self.scope_position + 1) % SCOPE_SIZE;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_for_missing_parts_are_rejected() {
+        let (events, receiver) = event_queue();
+        let result = events.try_send(EngineEvent::PartNoteOn { part: NUM_PARTS, note: 60, velocity: 100 });
+        assert_eq!(result, Err(EventError::Invalid(ParamError::NoSuchPart(NUM_PARTS))));
+        assert!(receiver.is_empty());
+
+        // Queued past the sender, the engine drops them instead of panicking
+        let mut engine = MultiEngine::new(48000.0, 4);
+        engine.handle_event(EngineEvent::SetLatch { part: NUM_PARTS + 3, enabled: true });
+    }
+
+    #[test]
+    fn note_ons_leave_room_for_note_offs() {
+        let (events, _receiver) = event_queue();
+        let mut queued = 0;
+        while events.try_send(EngineEvent::PartNoteOn { part: 0, note: 60, velocity: 100 }).is_ok() {
+            queued += 1;
+        }
+        assert_eq!(queued, NOTE_ON_HIGH_WATER);
+        for _ in 0..EVENT_QUEUE_SIZE - NOTE_ON_HIGH_WATER {
+            assert_eq!(events.try_send(EngineEvent::PartNoteOff { part: 0, note: 60 }), Ok(()));
+        }
+    }
+}
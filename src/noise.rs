@@ -0,0 +1,96 @@
+/// Spectrum of the noise floor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoiseColor {
+    White,
+    /// -3 dB per octave, closer to the hiss of tape and analog circuits
+    Pink,
+}
+
+impl NoiseColor {
+    pub const ALL: [NoiseColor; 2] = [NoiseColor::White, NoiseColor::Pink];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoiseColor::White => "White",
+            NoiseColor::Pink => "Pink",
+        }
+    }
+}
+
+/// One channel of noise, white from xorshift32 or pinked by Paul Kellet's
+/// economy filter.
+struct NoiseChannel {
+    rng: u32,
+    pink: [f32; 3],
+}
+
+impl NoiseChannel {
+    fn new(seed: u32) -> Self {
+        Self {
+            // xorshift needs a non-zero state
+            rng: seed.max(1),
+            pink: [0.0; 3],
+        }
+    }
+
+    fn next_white(&mut self) -> f32 {
+        // xorshift32, uniform in -1.0..1.0
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn next(&mut self, color: NoiseColor) -> f32 {
+        let white = self.next_white();
+        match color {
+            NoiseColor::White => white,
+            NoiseColor::Pink => {
+                self.pink[0] = 0.99765 * self.pink[0] + white * 0.0990460;
+                self.pink[1] = 0.96300 * self.pink[1] + white * 0.2965164;
+                self.pink[2] = 0.57000 * self.pink[2] + white * 1.0526913;
+                // Scaled to roughly the same loudness as the white noise
+                (self.pink[0] + self.pink[1] + self.pink[2] + white * 0.1848) * 0.25
+            }
+        }
+    }
+}
+
+/// Vintage hiss added to the master bus. Silent at the default level of zero.
+pub struct NoiseFloor {
+    level: f32,
+    color: NoiseColor,
+    left: NoiseChannel,
+    right: NoiseChannel,
+}
+
+impl NoiseFloor {
+    pub fn new() -> Self {
+        Self {
+            level: 0.0,
+            color: NoiseColor::White,
+            // Different seeds keep the channels uncorrelated
+            left: NoiseChannel::new(0x9e37_79b9),
+            right: NoiseChannel::new(0x85eb_ca6b),
+        }
+    }
+
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level.max(0.0);
+    }
+
+    pub fn set_color(&mut self, color: NoiseColor) {
+        self.color = color;
+    }
+
+    /// The next noise sample for each channel (left, right).
+    pub fn next(&mut self) -> (f32, f32) {
+        if self.level == 0.0 {
+            return (0.0, 0.0);
+        }
+        (
+            self.left.next(self.color) * self.level,
+            self.right.next(self.color) * self.level,
+        )
+    }
+}
@@ -1,6 +1,20 @@
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::f32::consts::PI;
-use rand;
+use std::sync::Arc;
+use crate::sampler::SampleBuffer;
+use crate::wavetable::Wavetable;
+
+/// Pitch deviation at full drift amount, in cents
+const DRIFT_MAX_CENTS: f32 = 20.0;
+/// The drift walk is advanced once every this many samples
+const DRIFT_UPDATE_INTERVAL: u32 = 32;
+/// Correlation time of the drift walk, in seconds
+const DRIFT_TIME: f32 = 0.5;
+/// Typical excursion of the walk, as a fraction of full drift
+const DRIFT_SPREAD: f32 = 0.35;
+/// Frequency offsets of the six side saws of the supersaw at full detune,
+/// relative to the center saw, from Adam Szabo's analysis of the JP-8000
+const SUPERSAW_OFFSETS: [f32; 6] = [-0.11002313, -0.06288439, -0.01952356, 0.01991221, 0.06216538, 0.10745242];
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Waveform {
@@ -8,6 +22,12 @@ pub enum Waveform {
     Square,
     Sawtooth,
     Triangle,
+    /// Scans through the frames of a loaded wavetable, a sine until one is loaded
+    Wavetable,
+    /// Seven detuned saws in one oscillator, after the JP-8000
+    Supersaw,
+    /// Plays a loaded recording, pitched from its root note; silent until one is loaded
+    Sampler,
 }
 
 pub struct Oscillator {
@@ -16,7 +36,98 @@ pub struct Oscillator {
     sample_rate: f32,
     volume: AtomicU32,
     waveform: Waveform,
-    detune: f32,
+    /// Share of the square's cycle spent high, before modulation (0.0-1.0)
+    pulse_width: f32,
+    /// Depth of the pulse width modulation LFO, added to and subtracted from the width
+    pwm_amount: f32,
+    /// PWM LFO rate in Hz
+    pwm_rate: f32,
+    pwm_phase: f32,
+    /// Modulated pulse width for the current sample
+    width: f32,
+    /// Pulse width offset from the modulation matrix
+    width_modulation: f32,
+    /// Factor applied to the frequency by the modulation matrix, 1.0 when unmodulated
+    pitch_modulation: f32,
+    /// Phase a reset starts the cycle from (0.0-1.0)
+    start_phase: f64,
+    /// Amount of slow analog-style pitch drift (0.0-1.0)
+    drift_amount: f32,
+    drift: Drift,
+    /// Table read by the wavetable waveform, shared by every voice of a part
+    wavetable: Option<Arc<Wavetable>>,
+    /// Frame of the wavetable to play, from the first (0.0) to the last (1.0)
+    wave_position: f32,
+    /// Phases of the supersaw's side saws. The center saw uses the main phase.
+    supersaw_phases: [f64; 6],
+    /// Spread of the side saws (0.0-1.0)
+    supersaw_detune: f32,
+    /// Level of the side saws against the center one (0.0-1.0)
+    supersaw_mix: f32,
+    /// Recording played by the sampler waveform, shared by every voice of a part
+    sample: Option<Arc<SampleBuffer>>,
+    /// Playback position in the recording, in its own samples
+    sample_position: f64,
+    /// Note at which the recording plays at its original pitch
+    root_note: f32,
+    /// Where playback starts on a new note, as a fraction of the recording
+    sample_start: f32,
+    /// Loop region as fractions of the recording, used when looping is on
+    loop_start: f32,
+    loop_end: f32,
+    sample_loop: bool,
+}
+
+/// Slow pitch drift from a leaky random walk, smoothed by a one-pole filter.
+/// It runs at control rate and is seeded, so every voice drifts differently
+/// but repeatably.
+struct Drift {
+    rng: u32,
+    walk: f32,
+    smoothed: f32,
+    leak: f32,
+    step: f32,
+    counter: u32,
+    /// Frequency multiplier applied to the oscillator
+    ratio: f32,
+}
+
+impl Drift {
+    fn new(sample_rate: f32, seed: u32) -> Self {
+        let updates_per_second = sample_rate / DRIFT_UPDATE_INTERVAL as f32;
+        let leak = (-1.0 / (DRIFT_TIME * updates_per_second)).exp();
+        Self {
+            // xorshift needs a non-zero state
+            rng: seed.max(1),
+            walk: 0.0,
+            smoothed: 0.0,
+            leak,
+            // Keeps the walk's standard deviation near DRIFT_SPREAD
+            step: DRIFT_SPREAD * (2.0 * (1.0 - leak)).sqrt() * 3.0_f32.sqrt(),
+            counter: 0,
+            ratio: 1.0,
+        }
+    }
+
+    fn next_random(&mut self) -> f32 {
+        // xorshift32, uniform in -1.0..1.0
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    fn next_ratio(&mut self, amount: f32) -> f32 {
+        if self.counter == 0 {
+            self.counter = DRIFT_UPDATE_INTERVAL;
+            let noise = self.next_random();
+            self.walk = (self.walk * self.leak + noise * self.step).clamp(-1.0, 1.0);
+            self.smoothed += (self.walk - self.smoothed) * 0.05;
+            self.ratio = 2.0_f32.powf(self.smoothed * amount * DRIFT_MAX_CENTS / 1200.0);
+        }
+        self.counter -= 1;
+        self.ratio
+    }
 }
 
 impl Oscillator {
@@ -27,27 +138,59 @@ impl Oscillator {
             sample_rate,
             volume: AtomicU32::new(1.0f32.to_bits()),
             waveform: Waveform::Sawtooth,
-            detune: 0.001, // 0.1% detune
+            pulse_width: 0.5,
+            pwm_amount: 0.0,
+            pwm_rate: 1.0,
+            pwm_phase: 0.0,
+            width: 0.5,
+            width_modulation: 0.0,
+            pitch_modulation: 1.0,
+            start_phase: 0.0,
+            drift_amount: 0.0,
+            drift: Drift::new(sample_rate, 1),
+            wavetable: None,
+            wave_position: 0.0,
+            // Spread out so the side saws never start in phase with each other
+            supersaw_phases: [0.13, 0.71, 0.37, 0.89, 0.52, 0.26],
+            supersaw_detune: 0.5,
+            supersaw_mix: 0.5,
+            sample: None,
+            sample_position: 0.0,
+            root_note: 60.0,
+            sample_start: 0.0,
+            loop_start: 0.0,
+            loop_end: 1.0,
+            sample_loop: false,
         }
     }
 
+    /// Reseeds the drift walk so oscillators built at the same time drift apart.
+    pub fn seed_drift(&mut self, seed: u32) {
+        self.drift = Drift::new(self.sample_rate, seed);
+    }
+
     pub fn next_sample(&mut self) -> f32 {
         let frequency = f32::from_bits(self.frequency.load(Ordering::Relaxed));
         let volume = f32::from_bits(self.volume.load(Ordering::Relaxed));
         
-        // Apply slight detuning
-        let detuned_frequency = frequency * (1.0 + self.detune * (2.0 * rand::random::<f32>() - 1.0));
+        // Apply slow pitch drift
+        let detuned_frequency = frequency * self.pitch_modulation * self.drift.next_ratio(self.drift_amount);
         
         // More precise phase accumulation
         self.phase += detuned_frequency as f64 / self.sample_rate as f64;
         self.phase %= 1.0;
 
-        let raw_sample = match self.waveform {
-            Waveform::Sine => (self.phase as f32 * 2.0 * PI).sin(),
-            Waveform::Square => self.polyblep_square(self.phase as f32, detuned_frequency),
-            Waveform::Sawtooth => self.polyblep_saw(self.phase as f32, detuned_frequency),
-            Waveform::Triangle => self.polyblep_triangle(self.phase as f32, detuned_frequency),
-        };
+        if self.waveform == Waveform::Square {
+            self.update_pulse_width();
+        }
+        if self.waveform == Waveform::Supersaw {
+            self.advance_supersaw(detuned_frequency);
+        }
+        if self.waveform == Waveform::Sampler {
+            // Recordings are played back as they are, without the soft clip
+            return self.next_sampler_sample(detuned_frequency) * volume;
+        }
+        let raw_sample = self.shape(self.waveform, self.phase as f32, detuned_frequency);
 
         // Apply soft clipping for analog-like distortion
         let clipped_sample = self.soft_clip(raw_sample);
@@ -55,6 +198,130 @@ impl Oscillator {
         clipped_sample * volume
     }
 
+    /// Reads the recording and steps through it at a rate that plays the root
+    /// note at the original pitch, wrapping inside the loop when looping is on.
+    fn next_sampler_sample(&mut self, frequency: f32) -> f32 {
+        let sample = match &self.sample {
+            Some(sample) => sample,
+            None => return 0.0,
+        };
+        let output = sample.read(self.sample_position);
+
+        let root_frequency = 440.0 * 2.0_f32.powf((self.root_note - 69.0) / 12.0);
+        let step = (frequency / root_frequency * sample.sample_rate() / self.sample_rate) as f64;
+        self.sample_position += step;
+
+        if self.sample_loop {
+            let length = sample.len() as f64;
+            let loop_start = self.loop_start as f64 * length;
+            let loop_end = self.loop_end as f64 * length;
+            // A loop shorter than a sample would spin in place, so it is ignored
+            if loop_end - loop_start >= 1.0 && self.sample_position >= loop_end {
+                self.sample_position = loop_start + (self.sample_position - loop_end) % (loop_end - loop_start);
+            }
+        }
+        output
+    }
+
+    /// Moves playback back to the start offset, for a new note.
+    pub fn restart_sample(&mut self) {
+        if let Some(sample) = &self.sample {
+            self.sample_position = self.sample_start as f64 * sample.len() as f64;
+        }
+    }
+
+    /// Advances the PWM LFO and sets the width for this sample, kept away from
+    /// 0 and 1 so the pulse never disappears.
+    fn update_pulse_width(&mut self) {
+        self.pwm_phase += self.pwm_rate / self.sample_rate;
+        if self.pwm_phase >= 1.0 {
+            self.pwm_phase -= 1.0;
+        }
+        let modulation = (self.pwm_phase * 2.0 * PI).sin() * self.pwm_amount;
+        self.width = (self.pulse_width + modulation + self.width_modulation).clamp(0.02, 0.98);
+    }
+
+    fn shape(&self, waveform: Waveform, phase: f32, frequency: f32) -> f32 {
+        match waveform {
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Square => self.polyblep_square(phase, frequency),
+            Waveform::Sawtooth => self.polyblep_saw(phase, frequency),
+            Waveform::Triangle => self.polyblep_triangle(phase, frequency),
+            Waveform::Wavetable => match &self.wavetable {
+                Some(table) => table.sample(self.wave_position, phase, frequency / self.sample_rate),
+                None => (phase * 2.0 * PI).sin(),
+            },
+            Waveform::Supersaw => {
+                let sides = self.supersaw_phases.map(|p| p as f32);
+                self.supersaw(phase, sides, frequency)
+            }
+            // A recording has no single cycle, so the whole of it is spread over one
+            Waveform::Sampler => match &self.sample {
+                Some(sample) => sample.read(phase as f64 * (sample.len() - 1) as f64),
+                None => 0.0,
+            },
+        }
+    }
+
+    /// Frequency multiplier of each side saw. The detune control follows a
+    /// squared curve, so most of its travel covers the subtle settings.
+    fn supersaw_ratios(&self) -> [f32; 6] {
+        let amount = self.supersaw_detune * self.supersaw_detune;
+        SUPERSAW_OFFSETS.map(|offset| 1.0 + offset * amount)
+    }
+
+    fn advance_supersaw(&mut self, frequency: f32) {
+        let ratios = self.supersaw_ratios();
+        for (phase, ratio) in self.supersaw_phases.iter_mut().zip(ratios) {
+            *phase = (*phase + (frequency * ratio) as f64 / self.sample_rate as f64) % 1.0;
+        }
+    }
+
+    /// The center saw at `phase` mixed with the side saws at `sides`.
+    fn supersaw(&self, phase: f32, sides: [f32; 6], frequency: f32) -> f32 {
+        // Gain curves for the center and side saws measured on the JP-8000
+        let mix = self.supersaw_mix;
+        let center_gain = 1.0 - 0.55366 * mix;
+        let side_gain = -0.73764 * mix * mix + 1.2841 * mix + 0.044372;
+
+        let ratios = self.supersaw_ratios();
+        let side_sum: f32 = sides.iter()
+            .zip(ratios)
+            .map(|(&side, ratio)| self.polyblep_saw(side, frequency * ratio))
+            .sum();
+        let sum = self.polyblep_saw(phase, frequency) * center_gain + side_sum * side_gain;
+        // The saws are uncorrelated, so their levels add up as power. When their
+        // peaks line up the sum can still pass the soft clipper's fold-over
+        // point, so it is rounded off first.
+        (sum / (center_gain * center_gain + 6.0 * side_gain * side_gain).sqrt()).tanh()
+    }
+
+    /// Computes one cycle of the current waveform at `num_points` evenly spaced
+    /// phases, including the soft clip, for display in the UI. For the sampler
+    /// it shows the whole recording.
+    pub fn cycle_preview(&self, num_points: usize) -> Vec<f32> {
+        // Sized so the anti-aliasing smooths over one point, as if each point were a sample
+        let frequency = self.sample_rate / num_points as f32;
+        let ratios = self.supersaw_ratios();
+        (0..num_points)
+            .map(|i| {
+                let phase = i as f32 / num_points as f32;
+                let raw = if self.waveform == Waveform::Supersaw {
+                    // Side saws start together with the center one, so the beating is visible
+                    let sides = ratios.map(|ratio| (phase * ratio).rem_euclid(1.0));
+                    self.supersaw(phase, sides, frequency)
+                } else {
+                    self.shape(self.waveform, phase, frequency)
+                };
+                if self.waveform == Waveform::Sampler {
+                    raw
+                } else {
+                    self.soft_clip(raw)
+                }
+            })
+            .collect()
+    }
+
     fn polyblep(&self, t: f32, dt: f32) -> f32 {
         if t < dt {
             let t = t / dt;
@@ -69,8 +336,9 @@ impl Oscillator {
 
     fn polyblep_square(&self, t: f32, frequency: f32) -> f32 {
         let dt = frequency / self.sample_rate;
-        let naive = if t < 0.5 { 1.0 } else { -1.0 };
-        naive - self.polyblep(t, dt) + self.polyblep((t + 0.5) % 1.0, dt)
+        let naive = if t < self.width { 1.0 } else { -1.0 };
+        // Correct the rising edge at 0 and the falling edge at the pulse width
+        naive + self.polyblep(t, dt) - self.polyblep((t + 1.0 - self.width) % 1.0, dt)
     }
 
     fn polyblep_saw(&self, t: f32, frequency: f32) -> f32 {
@@ -114,10 +382,92 @@ impl Oscillator {
         self.volume.store(volume.to_bits(), Ordering::Relaxed);
     }
 
+    /// Restarts the cycle from the start phase, so oscillators reset together start in phase.
+    pub fn reset_phase(&mut self) {
+        self.phase = self.start_phase;
+    }
+
+    /// Sets where `reset_phase` starts the cycle, in degrees.
+    pub fn set_start_phase(&mut self, degrees: f32) {
+        self.start_phase = (degrees / 360.0).rem_euclid(1.0) as f64;
+    }
+
+    /// Jumps to `phase`, as a fraction of a cycle (0.0-1.0).
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase.rem_euclid(1.0) as f64;
+    }
+
+    pub fn set_pulse_width(&mut self, width: f32) {
+        self.pulse_width = width.clamp(0.02, 0.98);
+    }
+
+    /// Shifts the pitch by `semitones`, e.g. from an LFO.
+    pub fn set_pitch_modulation(&mut self, semitones: f32) {
+        self.pitch_modulation = 2.0_f32.powf(semitones / 12.0);
+    }
+
+    /// Shifts the pulse width by `offset`, e.g. from an LFO.
+    pub fn set_width_modulation(&mut self, offset: f32) {
+        self.width_modulation = offset;
+    }
+
+    pub fn set_pwm_amount(&mut self, amount: f32) {
+        self.pwm_amount = amount.clamp(0.0, 0.5);
+    }
+
+    pub fn set_pwm_rate(&mut self, rate: f32) {
+        self.pwm_rate = rate.max(0.0);
+    }
+
     pub fn set_waveform(&mut self, waveform: Waveform) {
         self.waveform = waveform;
     }
 
+    pub fn set_wavetable(&mut self, wavetable: Option<Arc<Wavetable>>) {
+        self.wavetable = wavetable;
+    }
+
+    pub fn set_wave_position(&mut self, position: f32) {
+        self.wave_position = position.clamp(0.0, 1.0);
+    }
+
+    pub fn set_supersaw_detune(&mut self, detune: f32) {
+        self.supersaw_detune = detune.clamp(0.0, 1.0);
+    }
+
+    pub fn set_supersaw_mix(&mut self, mix: f32) {
+        self.supersaw_mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_sample(&mut self, sample: Option<Arc<SampleBuffer>>) {
+        self.sample = sample;
+        self.restart_sample();
+    }
+
+    pub fn set_root_note(&mut self, note: f32) {
+        self.root_note = note.clamp(0.0, 127.0);
+    }
+
+    pub fn set_sample_start(&mut self, start: f32) {
+        self.sample_start = start.clamp(0.0, 1.0);
+    }
+
+    pub fn set_loop_start(&mut self, start: f32) {
+        self.loop_start = start.clamp(0.0, 1.0);
+    }
+
+    pub fn set_loop_end(&mut self, end: f32) {
+        self.loop_end = end.clamp(0.0, 1.0);
+    }
+
+    pub fn set_sample_loop(&mut self, enabled: bool) {
+        self.sample_loop = enabled;
+    }
+
+    pub fn set_drift(&mut self, amount: f32) {
+        self.drift_amount = amount.clamp(0.0, 1.0);
+    }
+
     pub fn note_to_frequency(note: u8) -> f32 {
         440.0 * 2.0_f32.powf((note as f32 - 69.0) / 12.0)
     }
@@ -0,0 +1,591 @@
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::ops::RangeInclusive;
+use thiserror::Error;
+use crate::macros::MACRO_COUNT;
+use crate::multi_engine::NUM_PARTS;
+
+/// An f32 stored as bits in an AtomicU32, so it can be shared without locking.
+pub struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    pub fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    pub fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Continuous parameters of a single part's patch and mixer strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartParam {
+    Volume,
+    Drift,
+    /// Largest random offsets a note gets when triggered: pitch in cents,
+    /// pan, and level in dB
+    HumanizePitch,
+    HumanizePan,
+    HumanizeLevel,
+    Octave,
+    Semitone,
+    FineTune,
+    StartPhase,
+    PulseWidth,
+    PwmAmount,
+    PwmRate,
+    WavePosition,
+    SupersawDetune,
+    SupersawMix,
+    SampleRoot,
+    SampleStart,
+    LoopStart,
+    LoopEnd,
+    FoldAmount,
+    FoldSymmetry,
+    /// Wait before the attack and time held at the peak, 0.0 skips the stage
+    EnvelopeDelay,
+    Attack,
+    Hold,
+    Decay,
+    Sustain,
+    Release,
+    /// How far velocity scales the envelope peak and shortens the attack (0.0-1.0)
+    VelocityToLevel,
+    VelocityToAttack,
+    FilterCutoff,
+    FilterResonance,
+    FilterDrive,
+    FilterSaturation,
+    /// Comb filter feedback (-0.98-0.98) and how far its tuning follows the keyboard
+    CombFeedback,
+    CombKeyTrack,
+    /// Cutoff LFO speed in Hz and sweep either way in octaves
+    CutoffLfoRate,
+    CutoffLfoDepth,
+    /// Modulation LFOs: LFO 1 is shared by the part's voices, LFO 2 runs on
+    /// each voice. Rate in Hz, depth (0.0-1.0), start phase in degrees, and
+    /// fade-in time in seconds
+    Lfo1Rate,
+    Lfo1Depth,
+    Lfo1Phase,
+    Lfo1FadeIn,
+    Lfo2Rate,
+    Lfo2Depth,
+    Lfo2Phase,
+    Lfo2FadeIn,
+    /// How far each modulation matrix slot moves its destination (-1.0-1.0)
+    ModAmount1,
+    ModAmount2,
+    ModAmount3,
+    ModAmount4,
+    UnisonDetune,
+    UnisonSpread,
+    Level,
+    Pan,
+    /// Haas widening of the part's mono sound (0.0-1.0)
+    Width,
+    FxSend,
+    /// Mixer switches, on at 0.5 and above
+    Mute,
+    Solo,
+}
+
+impl PartParam {
+    pub const ALL: [PartParam; 57] = [
+        PartParam::Volume,
+        PartParam::Drift,
+        PartParam::HumanizePitch,
+        PartParam::HumanizePan,
+        PartParam::HumanizeLevel,
+        PartParam::Octave,
+        PartParam::Semitone,
+        PartParam::FineTune,
+        PartParam::StartPhase,
+        PartParam::PulseWidth,
+        PartParam::PwmAmount,
+        PartParam::PwmRate,
+        PartParam::WavePosition,
+        PartParam::SupersawDetune,
+        PartParam::SupersawMix,
+        PartParam::SampleRoot,
+        PartParam::SampleStart,
+        PartParam::LoopStart,
+        PartParam::LoopEnd,
+        PartParam::FoldAmount,
+        PartParam::FoldSymmetry,
+        PartParam::EnvelopeDelay,
+        PartParam::Attack,
+        PartParam::Hold,
+        PartParam::Decay,
+        PartParam::Sustain,
+        PartParam::Release,
+        PartParam::VelocityToLevel,
+        PartParam::VelocityToAttack,
+        PartParam::FilterCutoff,
+        PartParam::FilterResonance,
+        PartParam::FilterDrive,
+        PartParam::FilterSaturation,
+        PartParam::CombFeedback,
+        PartParam::CombKeyTrack,
+        PartParam::CutoffLfoRate,
+        PartParam::CutoffLfoDepth,
+        PartParam::Lfo1Rate,
+        PartParam::Lfo1Depth,
+        PartParam::Lfo1Phase,
+        PartParam::Lfo1FadeIn,
+        PartParam::Lfo2Rate,
+        PartParam::Lfo2Depth,
+        PartParam::Lfo2Phase,
+        PartParam::Lfo2FadeIn,
+        PartParam::ModAmount1,
+        PartParam::ModAmount2,
+        PartParam::ModAmount3,
+        PartParam::ModAmount4,
+        PartParam::UnisonDetune,
+        PartParam::UnisonSpread,
+        PartParam::Level,
+        PartParam::Pan,
+        PartParam::Width,
+        PartParam::FxSend,
+        PartParam::Mute,
+        PartParam::Solo,
+    ];
+
+    pub fn default_value(self) -> f32 {
+        match self {
+            PartParam::Volume => 0.5,
+            PartParam::Drift => 0.1,
+            PartParam::HumanizePitch => 0.0,
+            PartParam::HumanizePan => 0.0,
+            PartParam::HumanizeLevel => 0.0,
+            PartParam::Octave => 0.0,
+            PartParam::Semitone => 0.0,
+            PartParam::FineTune => 0.0,
+            PartParam::StartPhase => 0.0,
+            PartParam::PulseWidth => 0.5,
+            PartParam::PwmAmount => 0.0,
+            PartParam::PwmRate => 1.0,
+            PartParam::WavePosition => 0.0,
+            PartParam::SupersawDetune => 0.5,
+            PartParam::SupersawMix => 0.5,
+            PartParam::SampleRoot => 60.0,
+            PartParam::SampleStart => 0.0,
+            PartParam::LoopStart => 0.0,
+            PartParam::LoopEnd => 1.0,
+            PartParam::FoldAmount => 0.0,
+            PartParam::FoldSymmetry => 0.0,
+            PartParam::EnvelopeDelay => 0.0,
+            PartParam::Attack => 0.1,
+            PartParam::Hold => 0.0,
+            PartParam::Decay => 0.1,
+            PartParam::Sustain => 0.7,
+            PartParam::Release => 0.2,
+            PartParam::VelocityToLevel => 1.0,
+            PartParam::VelocityToAttack => 0.0,
+            PartParam::FilterCutoff => 15000.0,
+            PartParam::FilterResonance => 0.0,
+            PartParam::FilterDrive => 1.0,
+            PartParam::FilterSaturation => 1.0,
+            PartParam::CombFeedback => 0.7,
+            PartParam::CombKeyTrack => 0.0,
+            PartParam::CutoffLfoRate => 2.0,
+            PartParam::CutoffLfoDepth => 0.0,
+            PartParam::Lfo1Rate => 2.0,
+            PartParam::Lfo1Depth => 1.0,
+            PartParam::Lfo1Phase => 0.0,
+            PartParam::Lfo1FadeIn => 0.0,
+            PartParam::Lfo2Rate => 2.0,
+            PartParam::Lfo2Depth => 1.0,
+            PartParam::Lfo2Phase => 0.0,
+            PartParam::Lfo2FadeIn => 0.0,
+            PartParam::ModAmount1 => 0.0,
+            PartParam::ModAmount2 => 0.0,
+            PartParam::ModAmount3 => 0.0,
+            PartParam::ModAmount4 => 0.0,
+            PartParam::UnisonDetune => 15.0,
+            PartParam::UnisonSpread => 0.5,
+            PartParam::Level => 1.0,
+            PartParam::Pan => 0.0,
+            PartParam::Width => 0.0,
+            PartParam::FxSend => 1.0,
+            PartParam::Mute | PartParam::Solo => 0.0,
+        }
+    }
+
+    /// The range every value must fall in before it reaches the DSP code.
+    pub fn range(self) -> RangeInclusive<f32> {
+        match self {
+            PartParam::Volume => 0.0..=1.0,
+            PartParam::Drift => 0.0..=1.0,
+            PartParam::HumanizePitch => 0.0..=50.0,
+            PartParam::HumanizePan => 0.0..=1.0,
+            PartParam::HumanizeLevel => 0.0..=6.0,
+            PartParam::Octave => -3.0..=3.0,
+            PartParam::Semitone => -12.0..=12.0,
+            PartParam::FineTune => -100.0..=100.0,
+            PartParam::StartPhase => 0.0..=360.0,
+            PartParam::PulseWidth => 0.05..=0.95,
+            PartParam::PwmAmount => 0.0..=0.45,
+            PartParam::PwmRate => 0.05..=10.0,
+            PartParam::WavePosition => 0.0..=1.0,
+            PartParam::SupersawDetune => 0.0..=1.0,
+            PartParam::SupersawMix => 0.0..=1.0,
+            PartParam::SampleRoot => 0.0..=127.0,
+            PartParam::SampleStart => 0.0..=1.0,
+            PartParam::LoopStart => 0.0..=1.0,
+            PartParam::LoopEnd => 0.0..=1.0,
+            PartParam::FoldAmount => 0.0..=1.0,
+            PartParam::FoldSymmetry => -1.0..=1.0,
+            PartParam::EnvelopeDelay => 0.0..=2.0,
+            PartParam::Attack => 0.01..=2.0,
+            PartParam::Hold => 0.0..=2.0,
+            PartParam::Decay => 0.01..=2.0,
+            PartParam::Sustain => 0.0..=1.0,
+            PartParam::Release => 0.01..=2.0,
+            PartParam::VelocityToLevel => 0.0..=1.0,
+            PartParam::VelocityToAttack => 0.0..=1.0,
+            PartParam::FilterCutoff => 20.0..=20000.0,
+            PartParam::FilterResonance => 0.0..=4.0,
+            PartParam::FilterDrive => 0.1..=5.0,
+            PartParam::FilterSaturation => 0.0..=2.0,
+            PartParam::CombFeedback => -0.98..=0.98,
+            PartParam::CombKeyTrack => 0.0..=1.0,
+            PartParam::CutoffLfoRate => 0.05..=20.0,
+            PartParam::CutoffLfoDepth => 0.0..=4.0,
+            PartParam::Lfo1Rate => 0.05..=20.0,
+            PartParam::Lfo1Depth => 0.0..=1.0,
+            PartParam::Lfo1Phase => 0.0..=360.0,
+            PartParam::Lfo1FadeIn => 0.0..=5.0,
+            PartParam::Lfo2Rate => 0.05..=20.0,
+            PartParam::Lfo2Depth => 0.0..=1.0,
+            PartParam::Lfo2Phase => 0.0..=360.0,
+            PartParam::Lfo2FadeIn => 0.0..=5.0,
+            PartParam::ModAmount1 => -1.0..=1.0,
+            PartParam::ModAmount2 => -1.0..=1.0,
+            PartParam::ModAmount3 => -1.0..=1.0,
+            PartParam::ModAmount4 => -1.0..=1.0,
+            PartParam::UnisonDetune => 0.0..=50.0,
+            PartParam::UnisonSpread => 0.0..=1.0,
+            PartParam::Level => 0.0..=2.0,
+            PartParam::Pan => -1.0..=1.0,
+            PartParam::Width => 0.0..=1.0,
+            PartParam::FxSend => 0.0..=1.0,
+            PartParam::Mute | PartParam::Solo => 0.0..=1.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PartParam::Volume => "Volume",
+            PartParam::Drift => "Drift",
+            PartParam::HumanizePitch => "Humanize Pitch",
+            PartParam::HumanizePan => "Humanize Pan",
+            PartParam::HumanizeLevel => "Humanize Level",
+            PartParam::Octave => "Octave",
+            PartParam::Semitone => "Semitone",
+            PartParam::FineTune => "Fine Tune",
+            PartParam::StartPhase => "Start Phase",
+            PartParam::PulseWidth => "Pulse Width",
+            PartParam::PwmAmount => "PWM Amount",
+            PartParam::PwmRate => "PWM Rate",
+            PartParam::WavePosition => "Wave Position",
+            PartParam::SupersawDetune => "Supersaw Detune",
+            PartParam::SupersawMix => "Supersaw Mix",
+            PartParam::SampleRoot => "Sample Root",
+            PartParam::SampleStart => "Sample Start",
+            PartParam::LoopStart => "Loop Start",
+            PartParam::LoopEnd => "Loop End",
+            PartParam::FoldAmount => "Fold Amount",
+            PartParam::FoldSymmetry => "Fold Symmetry",
+            PartParam::EnvelopeDelay => "Envelope Delay",
+            PartParam::Attack => "Attack",
+            PartParam::Hold => "Hold",
+            PartParam::Decay => "Decay",
+            PartParam::Sustain => "Sustain",
+            PartParam::Release => "Release",
+            PartParam::VelocityToLevel => "Velocity to Level",
+            PartParam::VelocityToAttack => "Velocity to Attack",
+            PartParam::FilterCutoff => "Filter Cutoff",
+            PartParam::FilterResonance => "Filter Resonance",
+            PartParam::FilterDrive => "Filter Drive",
+            PartParam::FilterSaturation => "Filter Saturation",
+            PartParam::CombFeedback => "Comb Feedback",
+            PartParam::CombKeyTrack => "Comb Key Tracking",
+            PartParam::CutoffLfoRate => "Cutoff LFO Rate",
+            PartParam::CutoffLfoDepth => "Cutoff LFO Depth",
+            PartParam::Lfo1Rate => "LFO 1 Rate",
+            PartParam::Lfo1Depth => "LFO 1 Depth",
+            PartParam::Lfo1Phase => "LFO 1 Phase",
+            PartParam::Lfo1FadeIn => "LFO 1 Fade In",
+            PartParam::Lfo2Rate => "LFO 2 Rate",
+            PartParam::Lfo2Depth => "LFO 2 Depth",
+            PartParam::Lfo2Phase => "LFO 2 Phase",
+            PartParam::Lfo2FadeIn => "LFO 2 Fade In",
+            PartParam::ModAmount1 => "Mod 1 Amount",
+            PartParam::ModAmount2 => "Mod 2 Amount",
+            PartParam::ModAmount3 => "Mod 3 Amount",
+            PartParam::ModAmount4 => "Mod 4 Amount",
+            PartParam::UnisonDetune => "Unison Detune",
+            PartParam::UnisonSpread => "Unison Spread",
+            PartParam::Level => "Level",
+            PartParam::Pan => "Pan",
+            PartParam::Width => "Width",
+            PartParam::FxSend => "FX Send",
+            PartParam::Mute => "Mute",
+            PartParam::Solo => "Solo",
+        }
+    }
+
+    /// Unit suffix for display, empty for unitless parameters.
+    pub fn unit(self) -> &'static str {
+        match self {
+            PartParam::EnvelopeDelay | PartParam::Attack | PartParam::Hold | PartParam::Decay | PartParam::Release => " s",
+            PartParam::FilterCutoff | PartParam::PwmRate | PartParam::CutoffLfoRate | PartParam::Lfo1Rate | PartParam::Lfo2Rate => " Hz",
+            PartParam::Lfo1FadeIn | PartParam::Lfo2FadeIn => " s",
+            PartParam::CutoffLfoDepth => " oct",
+            PartParam::UnisonDetune | PartParam::FineTune | PartParam::HumanizePitch => " ct",
+            PartParam::HumanizeLevel => " dB",
+            PartParam::StartPhase | PartParam::Lfo1Phase | PartParam::Lfo2Phase => "°",
+            _ => "",
+        }
+    }
+}
+
+/// Continuous parameters of the master bus and shared effects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MasterParam {
+    Level,
+    /// Haas widening of the whole mix (0.0-1.0)
+    Width,
+    ReverbDecay,
+    ReverbWet,
+    ChorusRate,
+    ChorusDepth,
+    NoiseLevel,
+    /// Internal tempo in BPM, overridden while MIDI clock is coming in
+    Tempo,
+    /// Delay times of the left and right repeats in seconds
+    DelayTimeLeft,
+    DelayTimeRight,
+    DelayFeedback,
+    /// Corners of the filters in the delay's feedback loop, in Hz
+    DelayLowCut,
+    DelayHighCut,
+    DelayWet,
+    /// Flanger sweep rate in Hz
+    FlangerRate,
+    FlangerDepth,
+    /// Negative values invert the fed back signal
+    FlangerFeedback,
+    FlangerMix,
+    /// Macro knob positions (0.0-1.0)
+    Macro1,
+    Macro2,
+    Macro3,
+    Macro4,
+}
+
+impl MasterParam {
+    pub const ALL: [MasterParam; 22] = [
+        MasterParam::Level,
+        MasterParam::Width,
+        MasterParam::ReverbDecay,
+        MasterParam::ReverbWet,
+        MasterParam::ChorusRate,
+        MasterParam::ChorusDepth,
+        MasterParam::NoiseLevel,
+        MasterParam::Tempo,
+        MasterParam::DelayTimeLeft,
+        MasterParam::DelayTimeRight,
+        MasterParam::DelayFeedback,
+        MasterParam::DelayLowCut,
+        MasterParam::DelayHighCut,
+        MasterParam::DelayWet,
+        MasterParam::FlangerRate,
+        MasterParam::FlangerDepth,
+        MasterParam::FlangerFeedback,
+        MasterParam::FlangerMix,
+        MasterParam::Macro1,
+        MasterParam::Macro2,
+        MasterParam::Macro3,
+        MasterParam::Macro4,
+    ];
+
+    /// The macro knobs, in order
+    pub const MACROS: [MasterParam; MACRO_COUNT] = [MasterParam::Macro1, MasterParam::Macro2, MasterParam::Macro3, MasterParam::Macro4];
+
+    pub fn default_value(self) -> f32 {
+        match self {
+            MasterParam::Level => 1.0,
+            MasterParam::Width => 0.0,
+            MasterParam::ReverbDecay => 0.5,
+            MasterParam::ReverbWet => 0.5,
+            MasterParam::ChorusRate => 0.5,
+            MasterParam::ChorusDepth => 0.3,
+            MasterParam::NoiseLevel => 0.0,
+            MasterParam::Tempo => 120.0,
+            MasterParam::DelayTimeLeft => 0.375,
+            MasterParam::DelayTimeRight => 0.5,
+            MasterParam::DelayFeedback => 0.35,
+            MasterParam::DelayLowCut => 100.0,
+            MasterParam::DelayHighCut => 6000.0,
+            MasterParam::DelayWet => 0.0,
+            MasterParam::FlangerRate => 0.2,
+            MasterParam::FlangerDepth => 0.7,
+            MasterParam::FlangerFeedback => 0.5,
+            MasterParam::FlangerMix => 0.0,
+            MasterParam::Macro1 | MasterParam::Macro2 | MasterParam::Macro3 | MasterParam::Macro4 => 0.0,
+        }
+    }
+
+    /// The range every value must fall in before it reaches the DSP code.
+    pub fn range(self) -> RangeInclusive<f32> {
+        match self {
+            MasterParam::Level => 0.0..=2.0,
+            MasterParam::Width => 0.0..=1.0,
+            MasterParam::ReverbDecay => 0.0..=0.99,
+            MasterParam::ReverbWet => 0.0..=1.0,
+            MasterParam::ChorusRate => 0.1..=10.0,
+            MasterParam::ChorusDepth => 0.0..=1.0,
+            MasterParam::NoiseLevel => 0.0..=0.02,
+            MasterParam::Tempo => 20.0..=300.0,
+            MasterParam::DelayTimeLeft | MasterParam::DelayTimeRight => 0.01..=2.0,
+            MasterParam::DelayFeedback => 0.0..=0.95,
+            MasterParam::DelayLowCut => 20.0..=2000.0,
+            MasterParam::DelayHighCut => 500.0..=20000.0,
+            MasterParam::DelayWet => 0.0..=1.0,
+            MasterParam::FlangerRate => 0.02..=5.0,
+            MasterParam::FlangerDepth => 0.0..=1.0,
+            MasterParam::FlangerFeedback => -0.95..=0.95,
+            MasterParam::FlangerMix => 0.0..=1.0,
+            MasterParam::Macro1 | MasterParam::Macro2 | MasterParam::Macro3 | MasterParam::Macro4 => 0.0..=1.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MasterParam::Level => "Master Level",
+            MasterParam::Width => "Master Width",
+            MasterParam::ReverbDecay => "Reverb Decay",
+            MasterParam::ReverbWet => "Reverb Wet/Dry",
+            MasterParam::ChorusRate => "Chorus Rate",
+            MasterParam::ChorusDepth => "Chorus Depth",
+            MasterParam::NoiseLevel => "Noise Floor",
+            MasterParam::Tempo => "Tempo",
+            MasterParam::DelayTimeLeft => "Delay Time L",
+            MasterParam::DelayTimeRight => "Delay Time R",
+            MasterParam::DelayFeedback => "Delay Feedback",
+            MasterParam::DelayLowCut => "Delay Low Cut",
+            MasterParam::DelayHighCut => "Delay High Cut",
+            MasterParam::DelayWet => "Delay Wet/Dry",
+            MasterParam::FlangerRate => "Flanger Rate",
+            MasterParam::FlangerDepth => "Flanger Depth",
+            MasterParam::FlangerFeedback => "Flanger Feedback",
+            MasterParam::FlangerMix => "Flanger Mix",
+            MasterParam::Macro1 => "Macro 1",
+            MasterParam::Macro2 => "Macro 2",
+            MasterParam::Macro3 => "Macro 3",
+            MasterParam::Macro4 => "Macro 4",
+        }
+    }
+
+    /// Unit suffix for display, empty for unitless parameters.
+    pub fn unit(self) -> &'static str {
+        match self {
+            MasterParam::ChorusRate | MasterParam::FlangerRate => " Hz",
+            MasterParam::Tempo => " BPM",
+            MasterParam::DelayTimeLeft | MasterParam::DelayTimeRight => " s",
+            MasterParam::DelayLowCut | MasterParam::DelayHighCut => " Hz",
+            _ => "",
+        }
+    }
+}
+
+/// Why a parameter value was rejected.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ParamError {
+    /// The value was NaN or infinite
+    #[error("{name} must be a finite number, got {value}")]
+    NotFinite { name: &'static str, value: f32 },
+    /// The value was outside the parameter's declared range
+    #[error("{name} must be between {min} and {max}, got {value}")]
+    OutOfRange { name: &'static str, value: f32, min: f32, max: f32 },
+    /// The part index does not exist
+    #[error("part {} does not exist", .0 + 1)]
+    NoSuchPart(usize),
+}
+
+/// Checks that `value` is finite and within `range`, naming `name` in the error.
+pub(crate) fn validate(name: &'static str, range: RangeInclusive<f32>, value: f32) -> Result<f32, ParamError> {
+    if !value.is_finite() {
+        return Err(ParamError::NotFinite { name, value });
+    }
+    if !range.contains(&value) {
+        return Err(ParamError::OutOfRange { name, value, min: *range.start(), max: *range.end() });
+    }
+    Ok(value)
+}
+
+/// Shared store of every continuous synth parameter.
+///
+/// The UI and MIDI write values freely and the audio thread reads them wait-free once per
+/// callback, so slider drags never contend with rendering for the engine lock.
+/// Every value is checked against the parameter's declared range on the way in,
+/// so the DSP code never sees NaNs or out-of-range settings.
+pub struct SynthParams {
+    parts: Vec<Vec<AtomicF32>>,
+    master: Vec<AtomicF32>,
+    /// MIDI channel (0-15) each part listens on, read by the engine to route
+    /// notes and by the MIDI thread to route mixer controllers
+    channels: Vec<AtomicU8>,
+}
+
+impl SynthParams {
+    pub fn new() -> Self {
+        Self {
+            parts: (0..NUM_PARTS)
+                .map(|_| PartParam::ALL.iter().map(|p| AtomicF32::new(p.default_value())).collect())
+                .collect(),
+            master: MasterParam::ALL.iter().map(|p| AtomicF32::new(p.default_value())).collect(),
+            channels: (0..NUM_PARTS).map(|i| AtomicU8::new(i as u8)).collect(),
+        }
+    }
+
+    pub fn part(&self, part: usize, param: PartParam) -> f32 {
+        self.parts[part][param as usize].load()
+    }
+
+    /// Sets a part parameter, rejecting anything outside the declared range
+    /// instead of applying it. Every writer (UI controls, MIDI) goes through
+    /// here and reports what was rejected.
+    pub fn try_set_part(&self, part: usize, param: PartParam, value: f32) -> Result<(), ParamError> {
+        let slot = self.parts.get(part).ok_or(ParamError::NoSuchPart(part))?;
+        let value = validate(param.name(), param.range(), value)?;
+        slot[param as usize].store(value);
+        Ok(())
+    }
+
+    pub fn master(&self, param: MasterParam) -> f32 {
+        self.master[param as usize].load()
+    }
+
+    /// Sets a master parameter, rejecting invalid values like `try_set_part`.
+    pub fn try_set_master(&self, param: MasterParam, value: f32) -> Result<(), ParamError> {
+        let value = validate(param.name(), param.range(), value)?;
+        self.master[param as usize].store(value);
+        Ok(())
+    }
+
+    pub fn channel(&self, part: usize) -> u8 {
+        self.channels[part].load(Ordering::Relaxed)
+    }
+
+    pub fn set_channel(&self, part: usize, channel: u8) {
+        self.channels[part].store(channel.min(15), Ordering::Relaxed);
+    }
+}
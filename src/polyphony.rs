@@ -0,0 +1,100 @@
+/// Voices allocated for each part. The polyphony limit decides how many of
+/// them notes may use.
+pub const MAX_VOICES_PER_PART: usize = 32;
+
+/// Seconds of callbacks looked at before each adjustment
+const ADAPT_INTERVAL: f32 = 0.5;
+/// Peak load above which the limit is lowered
+const HIGH_LOAD: f32 = 0.75;
+/// Peak load below which the limit may be raised
+const LOW_LOAD: f32 = 0.45;
+
+/// Per-part voice limit, fixed or adapted to the measured DSP load.
+///
+/// In adaptive mode the limit follows the worst callback of each half-second
+/// window: it drops by a quarter when the peak load gets near a dropout, and
+/// grows by one voice at a time while the load is low and notes are being
+/// stolen for lack of voices. A callback that overruns its buffer lowers the
+/// limit straight away instead of waiting for the window to end.
+#[derive(Debug, Clone)]
+pub struct AdaptivePolyphony {
+    adaptive: bool,
+    min_voices: usize,
+    max_voices: usize,
+    limit: usize,
+    /// Highest load seen in the current window
+    window_peak: f32,
+    /// Seconds of audio covered by the current window
+    window_time: f32,
+    /// Whether some part ran out of voices during the current window
+    window_saturated: bool,
+}
+
+impl AdaptivePolyphony {
+    pub fn new() -> Self {
+        Self {
+            adaptive: false,
+            min_voices: 2,
+            max_voices: 8,
+            limit: 8,
+            window_peak: 0.0,
+            window_time: 0.0,
+            window_saturated: false,
+        }
+    }
+
+    /// Voices each part may currently use.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Sets the bounds the limit moves between. With adaptation off the limit
+    /// is pinned to `max_voices`; switching it on starts from there too.
+    pub fn configure(&mut self, adaptive: bool, min_voices: usize, max_voices: usize) {
+        let max_voices = max_voices.clamp(1, MAX_VOICES_PER_PART);
+        self.adaptive = adaptive;
+        self.min_voices = min_voices.clamp(1, max_voices);
+        self.max_voices = max_voices;
+        self.limit = max_voices;
+        self.reset_window();
+    }
+
+    /// Records one callback that took `load` of its `duration` seconds to
+    /// render, with `saturated` set when a part had every allowed voice busy.
+    /// Returns the new limit when it changed.
+    pub fn record(&mut self, load: f32, duration: f32, saturated: bool) -> Option<usize> {
+        if !self.adaptive {
+            return None;
+        }
+        self.window_peak = self.window_peak.max(load);
+        self.window_time += duration;
+        self.window_saturated |= saturated;
+
+        let overrun = load >= 1.0;
+        if !overrun && self.window_time < ADAPT_INTERVAL {
+            return None;
+        }
+
+        let limit = if self.window_peak > HIGH_LOAD {
+            self.limit - (self.limit / 4).max(1)
+        } else if self.window_peak < LOW_LOAD && self.window_saturated {
+            self.limit + 1
+        } else {
+            self.limit
+        }
+        .clamp(self.min_voices, self.max_voices);
+        self.reset_window();
+
+        if limit == self.limit {
+            return None;
+        }
+        self.limit = limit;
+        Some(limit)
+    }
+
+    fn reset_window(&mut self) {
+        self.window_peak = 0.0;
+        self.window_time = 0.0;
+        self.window_saturated = false;
+    }
+}
@@ -0,0 +1,150 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::SupportedBufferSize;
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// Everything the synth could play through or listen to on this machine.
+#[derive(Serialize)]
+struct ProbeReport {
+    audio_hosts: Vec<HostReport>,
+    /// MIDI inputs by the index `--midi-in` takes, absent without the `midi` feature
+    #[serde(skip_serializing_if = "Option::is_none")]
+    midi_inputs: Option<PortList>,
+}
+
+#[derive(Serialize)]
+struct HostReport {
+    name: String,
+    /// Whether RustWave opens its output on this host
+    default: bool,
+    default_output: Option<String>,
+    output_devices: Vec<DeviceReport>,
+    /// Why the host or its device list could not be opened
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeviceReport {
+    name: String,
+    configs: Vec<ConfigReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// One supported output configuration: a sample format and channel count
+/// over a range of sample rates.
+#[derive(Serialize)]
+struct ConfigReport {
+    channels: u16,
+    sample_format: String,
+    min_sample_rate: u32,
+    max_sample_rate: u32,
+    /// Buffer sizes in frames, when the host reports them
+    min_buffer_size: Option<u32>,
+    max_buffer_size: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct PortList {
+    ports: Vec<PortReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PortReport {
+    index: usize,
+    name: String,
+}
+
+/// Whether `--probe` was given on the command line.
+pub fn requested() -> bool {
+    std::env::args().any(|arg| arg == "--probe")
+}
+
+/// Prints a JSON report of every audio host, its output devices and their
+/// supported configs, and every MIDI input. Devices that fail to answer are
+/// listed with the error instead of ending the probe.
+pub fn run() -> Result<()> {
+    let report = ProbeReport {
+        audio_hosts: cpal::available_hosts().into_iter().map(probe_host).collect(),
+        midi_inputs: probe_midi(),
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn probe_host(id: cpal::HostId) -> HostReport {
+    let mut report = HostReport {
+        name: id.name().to_string(),
+        default: id == cpal::default_host().id(),
+        default_output: None,
+        output_devices: Vec::new(),
+        error: None,
+    };
+    let host = match cpal::host_from_id(id) {
+        Ok(host) => host,
+        Err(err) => {
+            report.error = Some(err.to_string());
+            return report;
+        }
+    };
+    report.default_output = host.default_output_device().and_then(|device| device.name().ok());
+    match host.output_devices() {
+        Ok(devices) => report.output_devices = devices.map(|device| probe_device(&device)).collect(),
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    report
+}
+
+fn probe_device(device: &cpal::Device) -> DeviceReport {
+    let mut report = DeviceReport {
+        name: device.name().unwrap_or_else(|err| format!("<{}>", err)),
+        configs: Vec::new(),
+        error: None,
+    };
+    match device.supported_output_configs() {
+        Ok(configs) => {
+            report.configs = configs
+                .map(|config| {
+                    let (min_buffer_size, max_buffer_size) = match *config.buffer_size() {
+                        SupportedBufferSize::Range { min, max } => (Some(min), Some(max)),
+                        SupportedBufferSize::Unknown => (None, None),
+                    };
+                    ConfigReport {
+                        channels: config.channels(),
+                        sample_format: config.sample_format().to_string(),
+                        min_sample_rate: config.min_sample_rate().0,
+                        max_sample_rate: config.max_sample_rate().0,
+                        min_buffer_size,
+                        max_buffer_size,
+                    }
+                })
+                .collect();
+        }
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    report
+}
+
+#[cfg(feature = "midi")]
+fn probe_midi() -> Option<PortList> {
+    let mut list = PortList { ports: Vec::new(), error: None };
+    match midir::MidiInput::new("RustWave probe") {
+        Ok(midi_in) => {
+            for (index, port) in midi_in.ports().iter().enumerate() {
+                let name = midi_in.port_name(port).unwrap_or_else(|err| format!("<{}>", err));
+                list.ports.push(PortReport { index, name });
+            }
+        }
+        Err(err) => list.error = Some(err.to_string()),
+    }
+    Some(list)
+}
+
+#[cfg(not(feature = "midi"))]
+fn probe_midi() -> Option<PortList> {
+    None
+}
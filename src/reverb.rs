@@ -1,5 +1,11 @@
 use std::f32::consts::PI;
 use reverb::Reverb as SecondReverb;
+use crate::advanced::ReverbTuning;
+
+/// Longest delay the tuning file can ask for. Every line is allocated at this
+/// length up front, so retuning only moves the read points and never
+/// allocates on the audio thread.
+const MAX_DELAY_MS: f32 = 500.0;
 
 pub struct Reverb {
     early_reflections: EarlyReflections,
@@ -9,11 +15,13 @@ pub struct Reverb {
     wet: f32,
     dry: f32,
     second_reverb: SecondReverb,
+    sample_rate: f32,
 }
 
 struct EarlyReflections {
     delay_line: DelayLine,
-    taps: Vec<(usize, f32)>,
+    /// Delay in samples and gain of each tap
+    taps: [(usize, f32); 4],
 }
 
 struct LateReflections {
@@ -36,6 +44,7 @@ struct Equalizer {
 struct DelayLine {
     buffer: Vec<f32>,
     write_pos: usize,
+    /// Length in use, up to the buffer's length
     size: usize,
 }
 
@@ -55,10 +64,10 @@ struct Biquad {
 
 
 impl LateReflections {
-    fn new(sample_rate: f32, num_channels: usize) -> Self {
-        let delay_times_ms = [29.0, 37.0, 43.0, 53.0];
+    fn new(sample_rate: f32, num_channels: usize, delay_times_ms: &[f32]) -> Self {
+        let capacity = ms_to_samples(MAX_DELAY_MS, sample_rate);
         let delay_lines = delay_times_ms.iter()
-            .map(|&ms| DelayLine::new((ms * sample_rate / 1000.0) as usize))
+            .map(|&ms| DelayLine::with_capacity(capacity, ms_to_samples(ms.clamp(1.0, MAX_DELAY_MS), sample_rate)))
             .collect();
 
         let feedback_matrix = Self::create_feedback_matrix(num_channels);
@@ -76,6 +85,13 @@ impl LateReflections {
         }
     }
 
+    /// Changes the line lengths within their allocated capacity.
+    fn set_delay_times(&mut self, sample_rate: f32, delay_times_ms: &[f32]) {
+        for (delay_line, &ms) in self.delay_lines.iter_mut().zip(delay_times_ms) {
+            delay_line.set_size(ms_to_samples(ms.clamp(1.0, MAX_DELAY_MS), sample_rate));
+        }
+    }
+
     fn create_feedback_matrix(size: usize) -> Vec<Vec<f32>> {
         let mut matrix = vec![vec![0.0; size]; size];
         for i in 0..size {
@@ -128,6 +144,7 @@ impl LateReflections {
 impl Reverb {
     pub fn new(sample_rate: f32) -> Self {
         let num_channels = 4;
+        let tuning = ReverbTuning::default();
         let mut second_reverb = SecondReverb::new();
 
         second_reverb.bandwidth(0.8);  // Increase bandwidth to soften the sound
@@ -136,13 +153,14 @@ impl Reverb {
         second_reverb.diffusion(0.7, 0.7, 0.7, 0.7);  // Set diffusion to smooth out distinct echoes
 
         Self {
-            early_reflections: EarlyReflections::new(sample_rate),
-            late_reflections: LateReflections::new(sample_rate, num_channels),
+            early_reflections: EarlyReflections::new(sample_rate, &tuning.early_taps_ms, tuning.early_tap_decay),
+            late_reflections: LateReflections::new(sample_rate, num_channels, &tuning.late_delays_ms),
             modulation: Modulation::new(sample_rate, num_channels),
             eq: Equalizer::new(sample_rate),
             wet: 0.7,
             dry: 0.3,
             second_reverb: SecondReverb::new(),
+            sample_rate,
         }
     }
 
@@ -184,32 +202,33 @@ impl Reverb {
         self.dry = 1.0 - self.wet;
     }
 
+    /// Moves the reflection taps and resizes the late lines, which restarts
+    /// the reverb tail. The lines keep their buffers, so this never allocates.
+    pub fn set_tuning(&mut self, tuning: &ReverbTuning) {
+        self.early_reflections.set_taps(self.sample_rate, &tuning.early_taps_ms, tuning.early_tap_decay);
+        self.late_reflections.set_delay_times(self.sample_rate, &tuning.late_delays_ms);
+    }
+
     pub fn get_wet(&self) -> f32 {
         self.wet
     }
 }
 
 impl EarlyReflections {
-    fn new(sample_rate: f32) -> Self {
-        // Calculate delay times in samples
-        let delay_times = vec![
-            (0.007 * sample_rate) as usize,
-            (0.012243 * sample_rate) as usize,
-            (0.0154443 * sample_rate) as usize,
-            (0.023405 * sample_rate) as usize,
-        ];
-
-        let max_delay = *delay_times.iter().max().unwrap_or(&0);
-        let delay_line = DelayLine::new(max_delay + 1);
-
-        let taps = delay_times.into_iter()
-            .enumerate()
-            .map(|(i, delay)| (delay, 0.7f32.powf(i as f32)))
-            .collect();
+    fn new(sample_rate: f32, tap_times_ms: &[f32; 4], tap_decay: f32) -> Self {
+        let size = ms_to_samples(MAX_DELAY_MS, sample_rate) + 1;
+        let mut early = Self {
+            delay_line: DelayLine::with_capacity(size, size),
+            taps: [(0, 0.0); 4],
+        };
+        early.set_taps(sample_rate, tap_times_ms, tap_decay);
+        early
+    }
 
-        Self {
-            delay_line,
-            taps,
+    fn set_taps(&mut self, sample_rate: f32, tap_times_ms: &[f32; 4], tap_decay: f32) {
+        for (i, (tap, &ms)) in self.taps.iter_mut().zip(tap_times_ms).enumerate() {
+            let delay = ms_to_samples(ms.clamp(0.0, MAX_DELAY_MS), sample_rate);
+            *tap = (delay, tap_decay.clamp(0.0, 1.0).powf(i as f32));
         }
     }
 
@@ -223,15 +242,23 @@ impl EarlyReflections {
 }
 
 impl DelayLine {
-    fn new(size: usize) -> Self {
-        let size = size.max(1);  // Ensure size is at least 1
+    /// A line of `size` samples that can later grow up to `capacity`.
+    fn with_capacity(capacity: usize, size: usize) -> Self {
+        let capacity = capacity.max(1);  // Ensure size is at least 1
         Self {
-            buffer: vec![0.0; size],
+            buffer: vec![0.0; capacity],
             write_pos: 0,
-            size,
+            size: size.clamp(1, capacity),
         }
     }
 
+    /// Changes the length in use and clears the line.
+    fn set_size(&mut self, size: usize) {
+        self.size = size.clamp(1, self.buffer.len());
+        self.buffer.fill(0.0);
+        self.write_pos = 0;
+    }
+
     fn read(&self, delay: usize) -> f32 {
         let delay = delay.min(self.size - 1);
         let read_pos = (self.size + self.write_pos - delay) % self.size;
@@ -246,6 +273,10 @@ impl DelayLine {
 
 
 
+fn ms_to_samples(ms: f32, sample_rate: f32) -> usize {
+    (ms * sample_rate / 1000.0) as usize
+}
+
 impl Modulation {
     fn new(sample_rate: f32, num_channels: usize) -> Self {
         let lfos = (0..num_channels)
@@ -0,0 +1,157 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::advanced::{self, ConfigWatch};
+use crate::error::Result;
+
+/// Prefix of the marker files that exist while the synth runs, one per
+/// process and named after its PID. Finding one whose process is gone at
+/// startup means that run never got to shut down.
+const RUN_MARKER_PREFIX: &str = "running-";
+
+/// Why the synth started in safe mode, skipping the advanced tuning file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SafeModeReason {
+    /// `--safe-mode` was given on the command line
+    Requested,
+    /// The previous run crashed or was killed
+    Crashed,
+    /// The tuning file could not be read or parsed
+    BadConfig(String),
+}
+
+impl fmt::Display for SafeModeReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafeModeReason::Requested => write!(f, "requested with --safe-mode"),
+            SafeModeReason::Crashed => write!(f, "the last run did not shut down cleanly"),
+            SafeModeReason::BadConfig(err) => write!(f, "{} is broken: {}", advanced::CONFIG_FILE, err),
+        }
+    }
+}
+
+/// Decides whether to start in safe mode. Must run before the marker for
+/// this run is created. Markers left by runs that crashed are removed once
+/// reported, while those of instances still running are left alone.
+pub fn check() -> Option<SafeModeReason> {
+    if std::env::args().any(|arg| arg == "--safe-mode") {
+        return Some(SafeModeReason::Requested);
+    }
+    if remove_stale_markers() {
+        return Some(SafeModeReason::Crashed);
+    }
+    if Path::new(advanced::CONFIG_FILE).exists() {
+        if let Err(err) = ConfigWatch::new(advanced::CONFIG_FILE).load() {
+            return Some(SafeModeReason::BadConfig(err.to_string()));
+        }
+    }
+    None
+}
+
+/// Per-user directory the run markers live in.
+fn state_dir() -> PathBuf {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("rustwave")
+}
+
+/// Removes the markers of runs whose process no longer exists and returns
+/// whether there were any.
+fn remove_stale_markers() -> bool {
+    let Ok(entries) = fs::read_dir(state_dir()) else {
+        return false;
+    };
+    let mut found = false;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name.to_str().and_then(|name| name.strip_prefix(RUN_MARKER_PREFIX)) else {
+            continue;
+        };
+        if pid.parse().map_or(true, |pid| !process_alive(pid)) {
+            found = true;
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+    found
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks that the process exists. EPERM means it does but
+    // belongs to someone else.
+    let exists = unsafe { libc::kill(pid, 0) == 0 };
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Without a portable way to ask, every leftover marker counts as a crash.
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    false
+}
+
+/// Holds this run's marker for the life of the process and removes it on a
+/// clean exit, or when the process is stopped with Ctrl+C or SIGTERM. A
+/// panic leaves it behind so the next start is in safe mode.
+pub struct RunMarker {
+    path: Option<PathBuf>,
+}
+
+impl RunMarker {
+    pub fn create() -> Self {
+        let dir = state_dir();
+        let pid = std::process::id();
+        let path = dir.join(format!("{}{}", RUN_MARKER_PREFIX, pid));
+        let created = fs::create_dir_all(&dir).and_then(|()| fs::write(&path, pid.to_string()));
+        if let Err(err) = created {
+            eprintln!("Could not create {}: {}", path.display(), err);
+            return RunMarker { path: None };
+        }
+
+        let on_signal = path.clone();
+        let handler = ctrlc::set_handler(move || {
+            let _ = fs::remove_file(&on_signal);
+            std::process::exit(130);
+        });
+        if let Err(err) = handler {
+            eprintln!("Could not install the Ctrl+C handler: {}", err);
+        }
+        RunMarker { path: Some(path) }
+    }
+}
+
+impl Drop for RunMarker {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            if !std::thread::panicking() {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Moves a file that may have caused the problem out of the way, renaming it
+/// with a `.bak` suffix, and returns the backup's path.
+pub fn back_up(path: &Path) -> Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    fs::rename(path, &backup)?;
+    Ok(backup)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn own_process_is_alive() {
+        assert!(process_alive(std::process::id()));
+        // Beyond any real PID
+        assert!(!process_alive(u32::MAX));
+    }
+}
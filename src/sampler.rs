@@ -0,0 +1,59 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::error::{Result, RustWaveError};
+use crate::wavetable::read_mono;
+
+/// A recording played back by the sampler waveform, shared by every voice of a part.
+pub struct SampleBuffer {
+    samples: Vec<f32>,
+    /// Rate the recording was made at, so it plays at its own pitch on the root note
+    sample_rate: f32,
+}
+
+impl SampleBuffer {
+    /// Loads a .wav file of any sample format, mixed down to mono.
+    pub fn load(path: &Path) -> Result<Self> {
+        let (samples, sample_rate) = read_mono(path)?;
+        if samples.len() < 2 {
+            return Err(RustWaveError::EmptySample);
+        }
+        Ok(Self { samples, sample_rate: sample_rate as f32 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Length of the recording in seconds.
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / self.sample_rate
+    }
+
+    /// Reads the recording at a fractional sample `position`, interpolating
+    /// linearly. Positions past either end read silence.
+    pub fn read(&self, position: f64) -> f32 {
+        if position < 0.0 {
+            return 0.0;
+        }
+        let index = position as usize;
+        if index + 1 >= self.samples.len() {
+            return if index + 1 == self.samples.len() { self.samples[index] } else { 0.0 };
+        }
+        let mix = (position - index as f64) as f32;
+        self.samples[index] + (self.samples[index + 1] - self.samples[index]) * mix
+    }
+}
+
+impl fmt::Debug for SampleBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SampleBuffer")
+            .field("len", &self.samples.len())
+            .field("sample_rate", &self.sample_rate)
+            .finish()
+    }
+}
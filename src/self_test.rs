@@ -0,0 +1,133 @@
+use crate::filter::LadderFilter;
+#[cfg(feature = "chorus")]
+use crate::chorus::Chorus;
+use crate::effects::{ChorusMode, Effects};
+use crate::voice_manager::{UnisonStereo, VoiceManager};
+
+/// Largest sample magnitude any stage is allowed to produce from a unit impulse
+const MAX_PEAK: f32 = 4.0;
+/// Smallest share of the stereo energy a mono fold-down of phase-locked unison
+/// must keep while its copies are still close to in phase
+const MIN_MONO_FOLD: f32 = 0.95;
+
+/// Renders `length` samples of a stage's response to a unit impulse.
+fn impulse_response(length: usize, mut process: impl FnMut(f32) -> f32) -> Vec<f32> {
+    (0..length)
+        .map(|i| process(if i == 0 { 1.0 } else { 0.0 }))
+        .collect()
+}
+
+/// Checks that a response is finite, bounded, and has decayed below
+/// `tail_limit` over its last `tail_len` samples.
+fn check_response(name: &str, response: &[f32], tail_len: usize, tail_limit: f32) -> Result<(), String> {
+    if let Some(index) = response.iter().position(|s| !s.is_finite()) {
+        return Err(format!("{}: non-finite sample at {}", name, index));
+    }
+
+    let peak = response.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak > MAX_PEAK {
+        return Err(format!("{}: peak {:.3} exceeds {:.1}", name, peak, MAX_PEAK));
+    }
+
+    let tail_start = response.len().saturating_sub(tail_len);
+    let tail_peak = response[tail_start..].iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if tail_peak > tail_limit {
+        return Err(format!("{}: tail still at {:.5} (expected below {:.5})", name, tail_peak, tail_limit));
+    }
+
+    Ok(())
+}
+
+/// Checks that summing `left` and `right` to mono keeps at least `min_ratio`
+/// of their energy, i.e. the channels do not cancel each other out.
+fn check_mono_fold(name: &str, left: &[f32], right: &[f32], min_ratio: f32) -> Result<(), String> {
+    let mono: f32 = left.iter().zip(right).map(|(l, r)| ((l + r) * 0.5).powi(2)).sum();
+    let stereo: f32 = left.iter().zip(right).map(|(l, r)| (l * l + r * r) * 0.5).sum();
+    if stereo <= 0.0 {
+        return Err(format!("{}: no output", name));
+    }
+    let ratio = mono / stereo;
+    if ratio < min_ratio {
+        return Err(format!("{}: mono fold-down keeps {:.2} of the energy (expected at least {:.2})", name, ratio, min_ratio));
+    }
+    Ok(())
+}
+
+/// Renders test impulses through the filter, chorus, master effects and a full voice,
+/// and checks the output is finite, bounded and decays as expected. Guards
+/// against bad parameter defaults or miscompiled DSP on unusual platforms.
+///
+/// Failures are logged to stderr; returns whether every check passed.
+pub fn run(sample_rate: f32) -> bool {
+    let second = sample_rate as usize;
+    let tail = second / 10;
+    let mut results = Vec::new();
+
+    let mut filter = LadderFilter::new(sample_rate);
+    filter.set_cutoff(1000.0);
+    filter.set_resonance(3.5);
+    let response = impulse_response(second, |x| filter.process(x));
+    results.push(check_response("ladder filter", &response, tail, 1e-3));
+
+    #[cfg(feature = "chorus")]
+    {
+        let mut chorus = Chorus::new(sample_rate);
+        chorus.set_mode(ChorusMode::IV);
+        let response = impulse_response(second, |x| chorus.process(x, x).0);
+        results.push(check_response("chorus", &response, tail, 1e-3));
+    }
+
+    // The full master chain, with whichever effects are compiled in
+    let mut effects = Effects::new(sample_rate);
+    effects.set_reverb_decay(0.5);
+    effects.set_reverb_wet(1.0);
+    effects.set_chorus_mode(ChorusMode::Off);
+    let response = impulse_response(4 * second, |x| effects.process(x, x).0);
+    results.push(check_response("effects", &response, tail, 1e-2));
+
+    // Hold a note briefly, then leave enough time for the default release to finish
+    let mut voices = VoiceManager::new(sample_rate, 1);
+    let held = second / 4;
+    let mut left = vec![0.0; held + 2 * second];
+    let mut right = vec![0.0; held + 2 * second];
+    voices.note_on(69, 127);
+    voices.render_block(&mut left[..held], &mut right[..held]);
+    voices.note_off(69);
+    voices.render_block(&mut left[held..], &mut right[held..]);
+    results.push(check_response("voice", &left, tail, 1e-3));
+
+    // Phase-locked unison must restart its copies in phase even after the
+    // voices' oscillators have run out of phase with each other. The check
+    // covers a tenth of the beat between the outer copies; detune pulls them
+    // apart after that, so a longer window would only measure its own length.
+    let mut unison = VoiceManager::new(sample_rate, 4);
+    unison.set_unison_voices(4);
+    unison.set_unison_spread(1.0);
+    unison.set_unison_stereo(UnisonStereo::PhaseLocked);
+    let scramble = second / 50;
+    let detune = 10.0;
+    let beat = 440.0 * (2.0f32.powf(2.0 * detune / 1200.0) - 1.0);
+    let window = (sample_rate / beat / 10.0) as usize;
+    let mut left = vec![0.0; scramble + window];
+    let mut right = vec![0.0; scramble + window];
+    unison.set_unison_detune(1000.0);
+    unison.note_on(40, 127);
+    unison.render_block(&mut left[..scramble], &mut right[..scramble]);
+    unison.note_off(40);
+    unison.set_unison_detune(detune);
+    unison.note_on(69, 127);
+    unison.render_block(&mut left[scramble..], &mut right[scramble..]);
+    results.push(check_mono_fold("phase-locked unison", &left[scramble..], &right[scramble..], MIN_MONO_FOLD));
+
+    let mut passed = true;
+    for result in results {
+        if let Err(message) = result {
+            eprintln!("DSP self-test failed: {}", message);
+            passed = false;
+        }
+    }
+    if passed {
+        println!("DSP self-test passed");
+    }
+    passed
+}
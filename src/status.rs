@@ -0,0 +1,173 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::envelope::EnvelopeStage;
+use crate::params::AtomicF32;
+use crate::multi_engine::NUM_PARTS;
+
+/// Meters below this level are treated as silence when deciding whether the UI
+/// needs to keep animating.
+const SILENCE_THRESHOLD: f32 = 1e-4;
+
+/// Number of samples kept for the oscilloscope
+pub const SCOPE_SIZE: usize = 2048;
+
+/// State published by the audio thread after every block for the UI to display:
+/// meters, voice activity and DSP load. Reads and writes are wait-free, so the UI never
+/// has to lock the engine to draw them.
+pub struct EngineStatus {
+    part_meters: Vec<AtomicF32>,
+    master_meter: [AtomicF32; 2],
+    active_voices: AtomicUsize,
+    /// Voices each part may use under the polyphony limit
+    voice_limit: AtomicUsize,
+    /// Smoothed render time as a fraction of the buffer duration
+    dsp_load: AtomicF32,
+    /// Highest unsmoothed load seen since the last reset
+    dsp_peak: AtomicF32,
+    /// Callbacks that took longer to render than their buffer lasts, each a likely dropout
+    overruns: AtomicUsize,
+    /// Ring buffer of the signal at the selected scope tap
+    scope: Vec<AtomicF32>,
+    /// Index the next scope sample will be written to
+    scope_position: AtomicUsize,
+    /// Stage of each part's most recent envelope, as an index into
+    /// `EnvelopeStage::ALL`, and how far through it
+    envelope_stages: Vec<AtomicUsize>,
+    envelope_progress: Vec<AtomicF32>,
+    /// Tempo synced LFOs follow, and whether it comes from MIDI clock
+    tempo: AtomicF32,
+    midi_clock: AtomicBool,
+}
+
+impl EngineStatus {
+    const IDLE_STAGE: usize = EnvelopeStage::ALL.len() - 1;
+
+    pub fn new() -> Self {
+        Self {
+            part_meters: (0..NUM_PARTS).map(|_| AtomicF32::new(0.0)).collect(),
+            master_meter: [AtomicF32::new(0.0), AtomicF32::new(0.0)],
+            active_voices: AtomicUsize::new(0),
+            voice_limit: AtomicUsize::new(0),
+            dsp_load: AtomicF32::new(0.0),
+            dsp_peak: AtomicF32::new(0.0),
+            overruns: AtomicUsize::new(0),
+            scope: (0..SCOPE_SIZE).map(|_| AtomicF32::new(0.0)).collect(),
+            scope_position: AtomicUsize::new(0),
+            envelope_stages: (0..NUM_PARTS).map(|_| AtomicUsize::new(Self::IDLE_STAGE)).collect(),
+            envelope_progress: (0..NUM_PARTS).map(|_| AtomicF32::new(0.0)).collect(),
+            tempo: AtomicF32::new(0.0),
+            midi_clock: AtomicBool::new(false),
+        }
+    }
+
+    pub fn part_meter(&self, part: usize) -> f32 {
+        self.part_meters[part].load()
+    }
+
+    pub fn set_part_meter(&self, part: usize, level: f32) {
+        self.part_meters[part].store(level);
+    }
+
+    /// Peak levels of the master output (left, right)
+    pub fn master_meter(&self) -> (f32, f32) {
+        (self.master_meter[0].load(), self.master_meter[1].load())
+    }
+
+    pub fn set_master_meter(&self, left: f32, right: f32) {
+        self.master_meter[0].store(left);
+        self.master_meter[1].store(right);
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.active_voices.load(Ordering::Relaxed)
+    }
+
+    pub fn set_active_voices(&self, count: usize) {
+        self.active_voices.store(count, Ordering::Relaxed);
+    }
+
+    pub fn voice_limit(&self) -> usize {
+        self.voice_limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_voice_limit(&self, limit: usize) {
+        self.voice_limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn dsp_load(&self) -> f32 {
+        self.dsp_load.load()
+    }
+
+    pub fn dsp_peak(&self) -> f32 {
+        self.dsp_peak.load()
+    }
+
+    /// Records the load of one callback: render time divided by the time the
+    /// buffer takes to play. Values approaching 1.0 mean the audio is about to glitch.
+    pub fn record_dsp_load(&self, load: f32) {
+        let smoothed = self.dsp_load.load();
+        self.dsp_load.store(smoothed + (load - smoothed) * 0.1);
+        if load > self.dsp_peak.load() {
+            self.dsp_peak.store(load);
+        }
+        if load >= 1.0 {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn overruns(&self) -> usize {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_dsp_peak(&self) {
+        self.dsp_peak.store(0.0);
+    }
+
+    /// Copies the engine's scope ring buffer, whose next write index is `position`.
+    pub fn set_scope(&self, samples: &[f32], position: usize) {
+        for (slot, &sample) in self.scope.iter().zip(samples) {
+            slot.store(sample);
+        }
+        self.scope_position.store(position, Ordering::Relaxed);
+    }
+
+    /// The scope samples in time order, oldest first.
+    pub fn scope(&self) -> Vec<f32> {
+        let position = self.scope_position.load(Ordering::Relaxed);
+        (0..SCOPE_SIZE)
+            .map(|i| self.scope[(position + i) % SCOPE_SIZE].load())
+            .collect()
+    }
+
+    /// Where the part's most recently played envelope is, `None` when idle.
+    pub fn envelope_position(&self, part: usize) -> Option<(EnvelopeStage, f32)> {
+        match EnvelopeStage::ALL[self.envelope_stages[part].load(Ordering::Relaxed)] {
+            EnvelopeStage::Idle => None,
+            stage => Some((stage, self.envelope_progress[part].load())),
+        }
+    }
+
+    pub fn set_envelope_position(&self, part: usize, position: Option<(EnvelopeStage, f32)>) {
+        let (stage, progress) = position.unwrap_or((EnvelopeStage::Idle, 0.0));
+        let index = EnvelopeStage::ALL.iter().position(|&s| s == stage).unwrap_or(Self::IDLE_STAGE);
+        self.envelope_progress[part].store(progress);
+        self.envelope_stages[part].store(index, Ordering::Relaxed);
+    }
+
+    /// The tempo in BPM, and whether it is following MIDI clock.
+    pub fn tempo(&self) -> (f32, bool) {
+        (self.tempo.load(), self.midi_clock.load(Ordering::Relaxed))
+    }
+
+    pub fn set_tempo(&self, bpm: f32, midi_clock: bool) {
+        self.tempo.store(bpm);
+        self.midi_clock.store(midi_clock, Ordering::Relaxed);
+    }
+
+    /// Whether anything is sounding or still decaying on the meters.
+    pub fn is_active(&self) -> bool {
+        let (left, right) = self.master_meter();
+        self.active_voices() > 0
+            || left.max(right) > SILENCE_THRESHOLD
+            || self.part_meters.iter().any(|m| m.load() > SILENCE_THRESHOLD)
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rand::Rng;
+
+use crate::multi_engine::{EngineEvent, EventError, EventSender, NUM_PARTS};
+use crate::status::EngineStatus;
+
+/// Notes per second when `--stress` is given without a density
+const DEFAULT_DENSITY: f32 = 50.0;
+/// How often the storm thread wakes up to send events
+const TICK: Duration = Duration::from_millis(5);
+/// Shortest and longest time a storm note is held
+const MIN_HOLD: Duration = Duration::from_millis(20);
+const MAX_HOLD: Duration = Duration::from_millis(800);
+
+/// Reads `--stress` or `--stress=<notes per second>` from the command line.
+pub fn density_from_args() -> Option<f32> {
+    std::env::args().find_map(|arg| {
+        if arg == "--stress" {
+            Some(DEFAULT_DENSITY)
+        } else {
+            arg.strip_prefix("--stress=").and_then(|density| density.parse().ok())
+        }
+    })
+}
+
+/// Starts a developer load test: a thread that sends random notes at about
+/// `density` notes per second to every part through the engine event queue,
+/// and once a second prints the DSP load, dropouts and events the full queue
+/// turned away.
+pub fn spawn(events: EventSender, status: Arc<EngineStatus>, density: f32) {
+    println!("Stress test: about {:.0} random notes per second", density);
+    thread::spawn(move || {
+        let mut rng = rand::thread_rng();
+        // Notes still sounding, with the time their note-off is due
+        let mut held: Vec<(usize, u8, Instant)> = Vec::new();
+        let mut due = 0.0;
+        let mut sent = 0;
+        let mut rejected = 0;
+        let mut last_report = Instant::now();
+        let start_overruns = status.overruns();
+
+        loop {
+            let now = Instant::now();
+
+            let mut send = |event| match events.try_send(event) {
+                Ok(()) => {
+                    sent += 1;
+                    true
+                }
+                Err(EventError::QueueFull) => {
+                    rejected += 1;
+                    false
+                }
+                // The engine has shut down; storm parts always exist
+                Err(_) => false,
+            };
+
+            // Note-offs that fail are retried on the next tick
+            held.retain(|&(part, note, off_at)| {
+                off_at > now || !send(EngineEvent::PartNoteOff { part, note })
+            });
+
+            due += density * TICK.as_secs_f32();
+            while due >= 1.0 {
+                due -= 1.0;
+                let part = rng.gen_range(0..NUM_PARTS);
+                let note = rng.gen_range(24..=108);
+                let velocity = rng.gen_range(1..=127);
+                if send(EngineEvent::PartNoteOn { part, note, velocity }) {
+                    let hold = rng.gen_range(MIN_HOLD..=MAX_HOLD);
+                    held.push((part, note, now + hold));
+                }
+            }
+
+            if now.duration_since(last_report) >= Duration::from_secs(1) {
+                println!(
+                    "stress: {} events sent, {} rejected by the full queue, {} voices, DSP {:.0}% (peak {:.0}%), {} overruns",
+                    sent,
+                    rejected,
+                    status.active_voices(),
+                    status.dsp_load() * 100.0,
+                    status.dsp_peak() * 100.0,
+                    status.overruns() - start_overruns,
+                );
+                sent = 0;
+                rejected = 0;
+                last_report = now;
+            }
+
+            thread::sleep(TICK);
+        }
+    });
+}
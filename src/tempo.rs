@@ -0,0 +1,89 @@
+/// A note length that tempo-synced rates are set in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteDivision {
+    FourBars,
+    TwoBars,
+    Whole,
+    HalfDotted,
+    Half,
+    HalfTriplet,
+    QuarterDotted,
+    Quarter,
+    QuarterTriplet,
+    EighthDotted,
+    Eighth,
+    EighthTriplet,
+    SixteenthDotted,
+    Sixteenth,
+    SixteenthTriplet,
+    ThirtySecond,
+}
+
+impl NoteDivision {
+    pub const ALL: [NoteDivision; 16] = [
+        NoteDivision::FourBars,
+        NoteDivision::TwoBars,
+        NoteDivision::Whole,
+        NoteDivision::HalfDotted,
+        NoteDivision::Half,
+        NoteDivision::HalfTriplet,
+        NoteDivision::QuarterDotted,
+        NoteDivision::Quarter,
+        NoteDivision::QuarterTriplet,
+        NoteDivision::EighthDotted,
+        NoteDivision::Eighth,
+        NoteDivision::EighthTriplet,
+        NoteDivision::SixteenthDotted,
+        NoteDivision::Sixteenth,
+        NoteDivision::SixteenthTriplet,
+        NoteDivision::ThirtySecond,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NoteDivision::FourBars => "4/1",
+            NoteDivision::TwoBars => "2/1",
+            NoteDivision::Whole => "1/1",
+            NoteDivision::HalfDotted => "1/2D",
+            NoteDivision::Half => "1/2",
+            NoteDivision::HalfTriplet => "1/2T",
+            NoteDivision::QuarterDotted => "1/4D",
+            NoteDivision::Quarter => "1/4",
+            NoteDivision::QuarterTriplet => "1/4T",
+            NoteDivision::EighthDotted => "1/8D",
+            NoteDivision::Eighth => "1/8",
+            NoteDivision::EighthTriplet => "1/8T",
+            NoteDivision::SixteenthDotted => "1/16D",
+            NoteDivision::Sixteenth => "1/16",
+            NoteDivision::SixteenthTriplet => "1/16T",
+            NoteDivision::ThirtySecond => "1/32",
+        }
+    }
+
+    /// Length in quarter notes (beats).
+    pub fn beats(self) -> f32 {
+        match self {
+            NoteDivision::FourBars => 16.0,
+            NoteDivision::TwoBars => 8.0,
+            NoteDivision::Whole => 4.0,
+            NoteDivision::HalfDotted => 3.0,
+            NoteDivision::Half => 2.0,
+            NoteDivision::HalfTriplet => 4.0 / 3.0,
+            NoteDivision::QuarterDotted => 1.5,
+            NoteDivision::Quarter => 1.0,
+            NoteDivision::QuarterTriplet => 2.0 / 3.0,
+            NoteDivision::EighthDotted => 0.75,
+            NoteDivision::Eighth => 0.5,
+            NoteDivision::EighthTriplet => 1.0 / 3.0,
+            NoteDivision::SixteenthDotted => 0.375,
+            NoteDivision::Sixteenth => 0.25,
+            NoteDivision::SixteenthTriplet => 1.0 / 6.0,
+            NoteDivision::ThirtySecond => 0.125,
+        }
+    }
+
+    /// How many times per second the division repeats at `bpm`.
+    pub fn hz(self, bpm: f32) -> f32 {
+        bpm / 60.0 / self.beats()
+    }
+}
@@ -1,121 +1,642 @@
-use eframe::egui::{self, Color32, Rect, Stroke, Vec2, Key};
+use eframe::egui::{self, Align2, Color32, FontId, Pos2, Rect, Stroke, Vec2, Key};
+use std::path::Path;
 use std::sync::Arc;
-use std::collections::HashSet;
-use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::Duration;
 use crate::oscillator::{Oscillator, Waveform};
-use crate::voice_manager::VoiceManager;
-use crate::chorus::ChorusMode;
+use crate::multi_engine::{EngineEvent, EventSender, ScopeTap, NUM_PARTS};
+use crate::params::{SynthParams, PartParam, MasterParam};
+use crate::polyphony::MAX_VOICES_PER_PART;
+use crate::effects::{ChorusLfo, ChorusMode};
+use crate::envelope::{Envelope, EnvelopeCurve, EnvelopeStage, ReleaseMode, RetriggerMode};
+use crate::filter::{FilterType, Oversampling};
+use crate::key_tuning::{self, KeyTuning, MAX_KEY_OFFSET};
+use crate::lfo::{LfoShape, LfoTrigger};
+use crate::macros::{MacroTarget, MACRO_CCS, MACRO_COUNT, MACRO_TARGETS};
+use crate::tempo::NoteDivision;
+use crate::modulation::{ModDestination, ModSource, MOD_SLOTS};
+use crate::noise::NoiseColor;
+use crate::voice_manager::{NotePriority, PhaseMode, SameNotePolicy, StealPolicy, UnisonStereo, VoiceMode, MAX_UNISON_VOICES};
+use crate::status::EngineStatus;
+use crate::advanced;
+use crate::safe_mode::{self, SafeModeReason};
+use crate::sampler::SampleBuffer;
+use crate::wavetable::{self, Wavetable};
 
 const OCTAVES: usize = 3;
 const WHITE_KEY_INDICES: [usize; 7] = [0, 2, 4, 5, 7, 9, 11];
 const BLACK_KEY_INDICES: [usize; 5] = [1, 3, 6, 8, 10];
+/// Left edges of the black keys within an octave, in white key widths
+const BLACK_KEY_OFFSETS: [f32; 5] = [0.75, 1.75, 3.75, 4.75, 5.75];
+/// Keys on the on-screen keyboard
+const KEYBOARD_KEYS: usize = OCTAVES * 12;
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+const PREVIEW_POINTS: usize = 128;
+/// Points drawn along each stage of the envelope preview
+const ENVELOPE_SEGMENT_POINTS: usize = 24;
+/// Samples shown across the oscilloscope
+const SCOPE_VIEW: usize = 1024;
+/// Color of a frozen scope capture, set apart from the live trace
+const SCOPE_CAPTURE_COLOR: Color32 = Color32::from_rgb(230, 150, 60);
+/// Computer-keyboard velocity tiers: Ctrl plays soft, Shift plays an accent
+const VELOCITY_SOFT: u8 = 40;
+const VELOCITY_NORMAL: u8 = 100;
+const VELOCITY_ACCENT: u8 = 127;
+/// Smallest pressure change worth sending to the engine
+const PRESSURE_STEP: f32 = 1.0 / 127.0;
+/// How often the UI checks for new audio activity while nothing is sounding
+const IDLE_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// Pixels the pointer must move past a key's edge before a drag switches notes
+const KEY_HYSTERESIS: f32 = 3.0;
+
+/// Scientific pitch name of a MIDI note, e.g. 60 -> "C4".
+fn note_name(note: u8) -> String {
+    format!("{}{}", NOTE_NAMES[note as usize % 12], note as i32 / 12 - 1)
+}
+
+/// Note name with its exact frequency, e.g. "A4 (440.00 Hz)".
+fn note_info(note: u8) -> String {
+    format!("{} ({:.2} Hz)", note_name(note), Oscillator::note_to_frequency(note))
+}
+
+/// On-screen key under `pos` (0 is the lowest C) for a keyboard of `key_count`
+/// keys, in whole octaves, drawn across `rect`.
+///
+/// Positions up to KEY_HYSTERESIS past the edges clamp to the edge keys, so a
+/// drag that overshoots a little keeps playing; anything further out is
+/// `None`. While `previous` is still within KEY_HYSTERESIS of the pointer it
+/// is kept, so a pointer resting on a boundary doesn't flutter between keys.
+fn key_at(rect: Rect, pos: Pos2, key_count: usize, previous: Option<usize>) -> Option<usize> {
+    let octaves = key_count / 12;
+    if octaves == 0 || rect.width() <= 0.0 || rect.height() <= 0.0 || !rect.expand(KEY_HYSTERESIS).contains(pos) {
+        return None;
+    }
+    let offsets = [
+        Vec2::ZERO,
+        Vec2::new(-KEY_HYSTERESIS, 0.0),
+        Vec2::new(KEY_HYSTERESIS, 0.0),
+        Vec2::new(0.0, -KEY_HYSTERESIS),
+        Vec2::new(0.0, KEY_HYSTERESIS),
+    ];
+    let held = previous.filter(|&key| offsets.iter().any(|&offset| key_under(rect, pos + offset, octaves) == key));
+    held.or(Some(key_under(rect, pos, octaves)))
+}
+
+/// Key under `pos` once it is clamped into `rect`, black keys taking priority
+/// over the white keys they overlap.
+fn key_under(rect: Rect, pos: Pos2, octaves: usize) -> usize {
+    let octave_width = rect.width() / octaves as f32;
+    let white_key_width = octave_width / 7.0;
+    let black_key_width = white_key_width * 0.6;
+    let black_key_height = rect.height() * 0.6;
+
+    let rel_pos = pos - rect.min;
+    // Stay just inside the right edge so it falls on the last key rather
+    // than an octave past the keyboard
+    let x = rel_pos.x.clamp(0.0, rect.width() * (1.0 - f32::EPSILON));
+    let y = rel_pos.y.clamp(0.0, rect.height());
+
+    let octave = ((x / octave_width) as usize).min(octaves - 1);
+    let x_in_octave = x - octave as f32 * octave_width;
+    let black_key = BLACK_KEY_INDICES.iter().zip(BLACK_KEY_OFFSETS).find_map(|(&key_index, offset)| {
+        let left = offset * white_key_width;
+        (y < black_key_height && x_in_octave >= left && x_in_octave < left + black_key_width).then_some(key_index)
+    });
+    let key_index = black_key.unwrap_or_else(|| WHITE_KEY_INDICES[((x_in_octave / white_key_width) as usize).min(6)]);
+    octave * 12 + key_index
+}
+
+/// Stores a part parameter set from a control. A rejected value is reported
+/// and leaves the parameter as it was.
+fn set_part_param(params: &SynthParams, part: usize, param: PartParam, value: f32) {
+    if let Err(err) = params.try_set_part(part, param, value) {
+        eprintln!("Ignored a change to part {}: {}", part + 1, err);
+    }
+}
+
+/// Stores a master parameter set from a control, like `set_part_param`.
+fn set_master_param(params: &SynthParams, param: MasterParam, value: f32) {
+    if let Err(err) = params.try_set_master(param, value) {
+        eprintln!("Ignored a master change: {}", err);
+    }
+}
+
+/// Queues a discrete setting for the engine, reporting one the queue turned
+/// away. Callers only update their copy of the setting when this returns
+/// true, so the UI never shows a change the engine didn't get.
+fn send_event(events: &EventSender, event: EngineEvent) -> bool {
+    match events.try_send(event) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("Ignored a setting change: {}", err);
+            false
+        }
+    }
+}
+
+/// Discrete settings shown in the UI for one part of the engine. Continuous
+/// values live in the shared `SynthParams` store.
+struct PartControls {
+    waveform: Waveform,
+    /// Table sent to the engine, kept for the waveform preview
+    wavetable: Option<Arc<Wavetable>>,
+    wavetable_path: String,
+    /// Result of the last wavetable load, shown under the path
+    wavetable_status: String,
+    /// Recording sent to the engine, kept for the waveform preview
+    sample: Option<Arc<SampleBuffer>>,
+    sample_path: String,
+    /// Result of the last sample load, shown under the path
+    sample_status: String,
+    sample_loop: bool,
+    phase_mode: PhaseMode,
+    voice_mode: VoiceMode,
+    latch: bool,
+    release_mode: ReleaseMode,
+    retrigger_mode: RetriggerMode,
+    attack_curve: EnvelopeCurve,
+    decay_curve: EnvelopeCurve,
+    release_curve: EnvelopeCurve,
+    /// Whether the part plays the key tuning table or equal temperament
+    key_tuning: bool,
+    note_priority: NotePriority,
+    steal_policy: StealPolicy,
+    same_note_policy: SameNotePolicy,
+    unison_voices: usize,
+    unison_stereo: UnisonStereo,
+    auto_gain: bool,
+    filter_type: FilterType,
+    oversampling: Oversampling,
+    cutoff_lfo_shape: LfoShape,
+    /// Shapes of LFO 1 and LFO 2
+    lfo_shapes: [LfoShape; 2],
+    lfo_triggers: [LfoTrigger; 2],
+    /// Note lengths LFO 1 and LFO 2 are locked to, `None` when free-running
+    lfo_syncs: [Option<NoteDivision>; 2],
+    mod_routes: [(ModSource, ModDestination); MOD_SLOTS],
+}
+
+impl PartControls {
+    fn new() -> Self {
+        Self {
+            waveform: Waveform::Sawtooth,
+            wavetable: None,
+            wavetable_path: String::new(),
+            wavetable_status: String::new(),
+            sample: None,
+            sample_path: String::new(),
+            sample_status: String::new(),
+            sample_loop: false,
+            phase_mode: PhaseMode::Free,
+            voice_mode: VoiceMode::Poly,
+            latch: false,
+            release_mode: ReleaseMode::Natural,
+            retrigger_mode: RetriggerMode::Current,
+            attack_curve: EnvelopeCurve::Linear,
+            decay_curve: EnvelopeCurve::Linear,
+            release_curve: EnvelopeCurve::Exponential,
+            key_tuning: true,
+            note_priority: NotePriority::Last,
+            steal_policy: StealPolicy::Oldest,
+            same_note_policy: SameNotePolicy::Ignore,
+            unison_voices: 1,
+            unison_stereo: UnisonStereo::Alternating,
+            auto_gain: false,
+            filter_type: FilterType::Ladder,
+            oversampling: Oversampling::Off,
+            cutoff_lfo_shape: LfoShape::Sine,
+            lfo_shapes: [LfoShape::Sine; 2],
+            lfo_triggers: [LfoTrigger::Free, LfoTrigger::Retrigger],
+            lfo_syncs: [None; 2],
+            mod_routes: [(ModSource::Lfo1, ModDestination::Off); MOD_SLOTS],
+        }
+    }
+}
 
 pub struct SynthUI {
     current_octave: i32,
     key_states: [bool; 128],
-    volume: f32,
-    waveform: Waveform,
-    attack: f32,
-    decay: f32,
-    sustain: f32,
-    release: f32,
-    filter_cutoff: f32,
-    filter_resonance: f32,
-    filter_drive: f32,
-    filter_saturation: f32,
+    selected_part: usize,
+    parts: Vec<PartControls>,
     active_mouse_note: Option<u8>,
-    voice_manager: Arc<Mutex<VoiceManager>>,
-    chorus_rate: f32,
-    chorus_depth: f32,
+    status: Arc<EngineStatus>,
+    params: Arc<SynthParams>,
+    events: EventSender,
     chorus_mode: ChorusMode,
-    reverb_decay: f32,
-    reverb_wet: f32,
-    pressed_keys: HashSet<Key>,
+    chorus_lfo: ChorusLfo,
+    noise_color: NoiseColor,
+    /// Note lengths the delay's left and right times are locked to, `None` when free
+    delay_syncs: [Option<NoteDivision>; 2],
+    delay_ping_pong: bool,
+    flanger_through_zero: bool,
+    /// What each macro knob drives, mirrored from the engine
+    macro_targets: [[Option<MacroTarget>; MACRO_TARGETS]; MACRO_COUNT],
+    /// Computer keys held down, with the note each one started. Note-offs use
+    /// the stored note, so changing octave while holding keys never strands a voice.
+    pressed_keys: HashMap<Key, u8>,
+    /// Waveform, its settings and the table and sample the preview was computed for
+    preview_key: Option<(Waveform, [f32; 3], usize, usize)>,
+    preview_points: Vec<f32>,
+    scope_tap: ScopeTap,
+    /// A frozen scope view and the tap it came from, drawn under the live trace
+    /// for A/B comparison until cleared
+    scope_capture: Option<(ScopeTap, Vec<f32>)>,
+    /// Voices per part: fixed at the maximum, or adapted to the DSP load
+    /// between the two bounds
+    adaptive_polyphony: bool,
+    min_voices: usize,
+    max_voices: usize,
+    /// Repaint rate in Hz while meters and voices are active
+    refresh_rate: f32,
+    /// Simulate aftertouch by building pressure while computer keys are held
+    key_pressure_enabled: bool,
+    /// Seconds a key has to be held to reach full pressure
+    key_pressure_rise: f32,
+    key_hold_time: f32,
+    key_pressure: f32,
+    /// On-screen mod wheel and pitch bend positions
+    mod_wheel: f32,
+    pitch_bend: f32,
+    /// Why the synth started in safe mode, shown in a banner until dismissed
+    safe_mode: Option<SafeModeReason>,
+    /// Result of backing up the tuning file from the safe mode banner
+    backup_status: String,
+    /// Per-key offsets as sent to the engine, drawn on the keyboard
+    key_tuning: KeyTuning,
+    /// Key the tuning editor works on, the one played last
+    tuning_key: u8,
+    /// Result of the last key tuning save
+    key_tuning_status: String,
 }
 
 impl SynthUI {
-    pub fn new(voice_manager: Arc<Mutex<VoiceManager>>) -> Self {
+    pub fn new(
+        status: Arc<EngineStatus>,
+        params: Arc<SynthParams>,
+        events: EventSender,
+        key_tuning: KeyTuning,
+        safe_mode: Option<SafeModeReason>,
+    ) -> Self {
         Self {
-            voice_manager,
+            status,
+            params,
+            events,
             current_octave: 4,
             key_states: [false; 128],
-            volume: 0.5,
-            waveform: Waveform::Sawtooth,
-            attack: 0.1,
-            decay: 0.1,
-            sustain: 0.7,
-            release: 0.2,
-            filter_cutoff: 15000.0,
-            filter_resonance: 0.0,
-            filter_drive: 1.0,
-            filter_saturation: 1.0,
+            selected_part: 0,
+            parts: (0..NUM_PARTS).map(|_| PartControls::new()).collect(),
             active_mouse_note: None,
-            chorus_rate: 0.5,
-            chorus_depth: 0.3,
             chorus_mode: ChorusMode::Off,
-            reverb_decay: 0.5,
-            reverb_wet: 0.5,
-            pressed_keys: HashSet::new(),
+            chorus_lfo: ChorusLfo::Sine,
+            noise_color: NoiseColor::White,
+            delay_syncs: [None; 2],
+            delay_ping_pong: false,
+            flanger_through_zero: false,
+            macro_targets: [[None; MACRO_TARGETS]; MACRO_COUNT],
+            pressed_keys: HashMap::new(),
+            preview_key: None,
+            preview_points: Vec::new(),
+            scope_tap: ScopeTap::Chorus,
+            scope_capture: None,
+            adaptive_polyphony: false,
+            min_voices: 2,
+            max_voices: 8,
+            refresh_rate: 60.0,
+            key_pressure_enabled: false,
+            key_pressure_rise: 1.5,
+            key_hold_time: 0.0,
+            key_pressure: 0.0,
+            mod_wheel: 0.0,
+            pitch_bend: 0.0,
+            safe_mode,
+            backup_status: String::new(),
+            key_tuning,
+            tuning_key: 60,
+            key_tuning_status: String::new(),
         }
     }
 
 
     fn draw_effects_controls(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
-            ui.group(|ui| {
-                ui.vertical(|ui| {
-                    ui.label("Reverb Decay");
-                    if ui.add(egui::Slider::new(&mut self.reverb_decay, 0.0..=0.99)).changed() {
-                        self.voice_manager.lock().set_reverb_decay(self.reverb_decay);
-                    }
+            // Only show controls for effects compiled into the engine
+            if cfg!(feature = "flanger") {
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Flanger");
+                        let sliders = [
+                            (MasterParam::FlangerRate, "Rate", true),
+                            (MasterParam::FlangerDepth, "Depth", false),
+                            (MasterParam::FlangerFeedback, "Feedback", false),
+                            (MasterParam::FlangerMix, "Mix", false),
+                        ];
+                        for (param, text, logarithmic) in sliders {
+                            let mut value = self.params.master(param);
+                            let slider = egui::Slider::new(&mut value, param.range())
+                                .suffix(param.unit())
+                                .logarithmic(logarithmic)
+                                .text(text);
+                            if ui.add(slider).changed() {
+                                set_master_param(&self.params, param, value);
+                            }
+                        }
+                        let mut through_zero = self.flanger_through_zero;
+                        if ui.checkbox(&mut through_zero, "Through-Zero")
+                            .on_hover_text("Delay the dry signal too and sweep the wet one through it, cancelling as they line up")
+                            .changed()
+                            && send_event(&self.events, EngineEvent::SetFlangerThroughZero(through_zero))
+                        {
+                            self.flanger_through_zero = through_zero;
+                        }
+                    });
                 });
-            });
+            }
 
-            ui.group(|ui| {
-                ui.vertical(|ui| {
-                    ui.label("Reverb Wet/Dry");
-                    if ui.add(egui::Slider::new(&mut self.reverb_wet, 0.0..=1.0)).changed() {
-                        self.voice_manager.lock().set_reverb_wet(self.reverb_wet);
-                    }
+            if cfg!(feature = "delay") {
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Delay");
+                        let times = [(MasterParam::DelayTimeLeft, "Left"), (MasterParam::DelayTimeRight, "Right")];
+                        for (channel, (param, text)) in times.into_iter().enumerate() {
+                            ui.horizontal(|ui| {
+                                let mut time = self.params.master(param);
+                                let slider = egui::Slider::new(&mut time, param.range())
+                                    .suffix(param.unit())
+                                    .logarithmic(true)
+                                    .text(text);
+                                // A synced side takes its time from the tempo
+                                if ui.add_enabled(self.delay_syncs[channel].is_none(), slider).changed() {
+                                    set_master_param(&self.params, param, time);
+                                }
+                                let sync_name = |sync: Option<NoteDivision>| sync.map_or("Free", NoteDivision::name);
+                                egui::ComboBox::from_id_source(format!("delay_sync{}", channel))
+                                    .selected_text(sync_name(self.delay_syncs[channel]))
+                                    .show_ui(ui, |ui| {
+                                        let options = std::iter::once(None).chain(NoteDivision::ALL.into_iter().map(Some));
+                                        for sync in options {
+                                            if ui.selectable_label(self.delay_syncs[channel] == sync, sync_name(sync)).clicked()
+                                                && send_event(&self.events, EngineEvent::SetDelaySync { channel, sync })
+                                            {
+                                                self.delay_syncs[channel] = sync;
+                                            }
+                                        }
+                                    });
+                            });
+                        }
+                        let sliders = [
+                            (MasterParam::DelayFeedback, "Feedback", false),
+                            (MasterParam::DelayLowCut, "Low Cut", true),
+                            (MasterParam::DelayHighCut, "High Cut", true),
+                            (MasterParam::DelayWet, "Wet/Dry", false),
+                        ];
+                        for (param, text, logarithmic) in sliders {
+                            let mut value = self.params.master(param);
+                            let slider = egui::Slider::new(&mut value, param.range())
+                                .suffix(param.unit())
+                                .logarithmic(logarithmic)
+                                .text(text);
+                            if ui.add(slider).changed() {
+                                set_master_param(&self.params, param, value);
+                            }
+                        }
+                        let mut ping_pong = self.delay_ping_pong;
+                        if ui.checkbox(&mut ping_pong, "Ping-Pong")
+                            .on_hover_text("Start the repeats on the left and bounce them between the sides")
+                            .changed()
+                            && send_event(&self.events, EngineEvent::SetDelayPingPong(ping_pong))
+                        {
+                            self.delay_ping_pong = ping_pong;
+                        }
+                    });
                 });
-            });
+            }
 
+            if cfg!(feature = "reverb") {
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Reverb Decay");
+                        let mut reverb_decay = self.params.master(MasterParam::ReverbDecay);
+                        if ui.add(egui::Slider::new(&mut reverb_decay, MasterParam::ReverbDecay.range())).changed() {
+                            set_master_param(&self.params, MasterParam::ReverbDecay, reverb_decay);
+                        }
+                    });
+                });
 
-            ui.group(|ui| {
-                ui.vertical(|ui| {
-                    ui.label("Chorus Mode");
-                    for mode in [ChorusMode::Off, ChorusMode::I, ChorusMode::II, ChorusMode::III, ChorusMode::IV].iter() {
-                        if ui.radio_value(&mut self.chorus_mode, *mode, format!("{:?}", mode)).clicked() {
-                            self.voice_manager.lock().set_chorus_mode(self.chorus_mode);
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Reverb Wet/Dry");
+                        let mut reverb_wet = self.params.master(MasterParam::ReverbWet);
+                        if ui.add(egui::Slider::new(&mut reverb_wet, MasterParam::ReverbWet.range())).changed() {
+                            set_master_param(&self.params, MasterParam::ReverbWet, reverb_wet);
                         }
-                    }
+                    });
                 });
-            });
+            }
 
-            ui.group(|ui| {
-                ui.vertical(|ui| {
-                    ui.label("Chorus Rate");
-                    if ui.add(egui::Slider::new(&mut self.chorus_rate, 0.1..=10.0).logarithmic(true)).changed() {
-                        self.voice_manager.lock().set_chorus_rate(self.chorus_rate);
-                    }
+            if cfg!(feature = "chorus") {
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Chorus Mode");
+                        for mode in [ChorusMode::Off, ChorusMode::I, ChorusMode::II, ChorusMode::III, ChorusMode::IV].iter() {
+                            if ui.radio(self.chorus_mode == *mode, format!("{:?}", mode)).clicked()
+                                && send_event(&self.events, EngineEvent::SetChorusMode(*mode))
+                            {
+                                self.chorus_mode = *mode;
+                            }
+                        }
+                    });
                 });
-            });
 
-            ui.group(|ui| {
-                ui.vertical(|ui| {
-                    ui.label("Chorus Depth");
-                    if ui.add(egui::Slider::new(&mut self.chorus_depth, 0.0..=1.0)).changed() {
-                        self.voice_manager.lock().set_chorus_depth(self.chorus_depth);
-                    }
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Chorus LFO");
+                        for shape in ChorusLfo::ALL {
+                            if ui.radio(self.chorus_lfo == shape, shape.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetChorusLfo(shape))
+                            {
+                                self.chorus_lfo = shape;
+                            }
+                        }
+                    });
+                });
+
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Chorus Rate");
+                        let mut chorus_rate = self.params.master(MasterParam::ChorusRate);
+                        if ui.add(egui::Slider::new(&mut chorus_rate, MasterParam::ChorusRate.range()).suffix(MasterParam::ChorusRate.unit()).logarithmic(true)).changed() {
+                            set_master_param(&self.params, MasterParam::ChorusRate, chorus_rate);
+                        }
+                    });
+                });
+
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Chorus Depth");
+                        let mut chorus_depth = self.params.master(MasterParam::ChorusDepth);
+                        if ui.add(egui::Slider::new(&mut chorus_depth, MasterParam::ChorusDepth.range())).changed() {
+                            set_master_param(&self.params, MasterParam::ChorusDepth, chorus_depth);
+                        }
+                    });
+                });
+            }
+        });
+    }
+
+
+    fn draw_mixer(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Mixer", |ui| {
+            let master_meter = self.status.master_meter();
+
+            ui.horizontal(|ui| {
+                for part in 0..NUM_PARTS {
+                    ui.group(|ui| {
+                        ui.vertical(|ui| {
+                            ui.label(format!("Part {} (Ch {})", part + 1, self.params.channel(part) + 1));
+                            ui.add(egui::ProgressBar::new(self.status.part_meter(part).min(1.0)).desired_width(100.0));
+                            let mut level = self.params.part(part, PartParam::Level);
+                            if ui.add(egui::Slider::new(&mut level, PartParam::Level.range()).text("Level")).changed() {
+                                set_part_param(&self.params, part, PartParam::Level, level);
+                            }
+                            let mut pan = self.params.part(part, PartParam::Pan);
+                            if ui.add(egui::Slider::new(&mut pan, PartParam::Pan.range()).text("Pan")).changed() {
+                                set_part_param(&self.params, part, PartParam::Pan, pan);
+                            }
+                            let mut width = self.params.part(part, PartParam::Width);
+                            if ui.add(egui::Slider::new(&mut width, PartParam::Width.range()).text("Width"))
+                                .on_hover_text("Haas widening that stays mono-compatible")
+                                .changed()
+                            {
+                                set_part_param(&self.params, part, PartParam::Width, width);
+                            }
+                            let mut fx_send = self.params.part(part, PartParam::FxSend);
+                            if ui.add(egui::Slider::new(&mut fx_send, PartParam::FxSend.range()).text("FX Send")).changed() {
+                                set_part_param(&self.params, part, PartParam::FxSend, fx_send);
+                            }
+                            ui.horizontal(|ui| {
+                                for (param, text) in [(PartParam::Mute, "M"), (PartParam::Solo, "S")] {
+                                    let mut on = self.params.part(part, param) >= 0.5;
+                                    if ui.toggle_value(&mut on, text).changed() {
+                                        set_part_param(&self.params, part, param, if on { 1.0 } else { 0.0 });
+                                    }
+                                }
+                            });
+                        });
+                    });
+                }
+
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label("Master");
+                        ui.add(egui::ProgressBar::new(master_meter.0.min(1.0)).desired_width(100.0));
+                        ui.add(egui::ProgressBar::new(master_meter.1.min(1.0)).desired_width(100.0));
+                        let mut level = self.params.master(MasterParam::Level);
+                        if ui.add(egui::Slider::new(&mut level, MasterParam::Level.range()).text("Level")).changed() {
+                            set_master_param(&self.params, MasterParam::Level, level);
+                        }
+                        let mut width = self.params.master(MasterParam::Width);
+                        if ui.add(egui::Slider::new(&mut width, MasterParam::Width.range()).text("Width"))
+                            .on_hover_text("Haas widening that stays mono-compatible")
+                            .changed()
+                        {
+                            set_master_param(&self.params, MasterParam::Width, width);
+                        }
+                        let (tempo, midi_clock) = self.status.tempo();
+                        if midi_clock {
+                            ui.label(format!("Tempo: {:.1} BPM (MIDI clock)", tempo));
+                        } else {
+                            let mut tempo = self.params.master(MasterParam::Tempo);
+                            let slider = egui::Slider::new(&mut tempo, MasterParam::Tempo.range())
+                                .suffix(MasterParam::Tempo.unit())
+                                .text("Tempo");
+                            if ui.add(slider).on_hover_text("Followed by synced LFOs until MIDI clock arrives").changed() {
+                                set_master_param(&self.params, MasterParam::Tempo, tempo);
+                            }
+                        }
+                        let mut noise_level = self.params.master(MasterParam::NoiseLevel);
+                        if ui.add(egui::Slider::new(&mut noise_level, MasterParam::NoiseLevel.range()).text("Noise")).changed() {
+                            set_master_param(&self.params, MasterParam::NoiseLevel, noise_level);
+                        }
+                        ui.horizontal(|ui| {
+                            for color in NoiseColor::ALL {
+                                if ui.selectable_label(self.noise_color == color, color.name()).clicked()
+                                    && send_event(&self.events, EngineEvent::SetNoiseColor(color))
+                                {
+                                    self.noise_color = color;
+                                }
+                            }
+                        });
+                    });
                 });
             });
         });
     }
 
 
+    fn draw_scope(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Oscilloscope", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Source");
+                for tap in ScopeTap::ALL {
+                    if ui.selectable_label(self.scope_tap == tap, tap.name()).clicked()
+                        && send_event(&self.events, EngineEvent::SetScopeTap(tap))
+                    {
+                        self.scope_tap = tap;
+                    }
+                }
+            });
+
+            let samples = self.status.scope();
+            // Start the view on a rising zero crossing so periodic signals stand still
+            let search = samples.len() - SCOPE_VIEW;
+            let start = (1..search)
+                .find(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+                .unwrap_or(search);
+            let view = &samples[start..start + SCOPE_VIEW];
+
+            ui.horizontal(|ui| {
+                if ui.button("Freeze").on_hover_text("Keep this waveform on screen to compare against").clicked() {
+                    self.scope_capture = Some((self.scope_tap, view.to_vec()));
+                }
+                if ui.add_enabled(self.scope_capture.is_some(), egui::Button::new("Clear")).clicked() {
+                    self.scope_capture = None;
+                }
+                if let Some((tap, _)) = &self.scope_capture {
+                    ui.colored_label(SCOPE_CAPTURE_COLOR, format!("Comparing with frozen {}", tap.name()));
+                }
+            });
+
+            let (rect, _) = ui.allocate_exact_size(Vec2::new(ui.available_width(), 120.0), egui::Sense::hover());
+            let painter = ui.painter();
+            painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+            painter.line_segment(
+                [rect.left_center(), rect.right_center()],
+                Stroke::new(1.0, Color32::from_gray(60)),
+            );
+
+            let trace = |samples: &[f32], color: Color32| {
+                let points: Vec<egui::Pos2> = samples.iter()
+                    .enumerate()
+                    .map(|(i, &sample)| {
+                        let x = rect.left() + rect.width() * i as f32 / (SCOPE_VIEW - 1) as f32;
+                        let y = rect.center().y - sample.clamp(-1.0, 1.0) * rect.height() * 0.45;
+                        egui::pos2(x, y)
+                    })
+                    .collect();
+                egui::Shape::line(points, Stroke::new(1.0, color))
+            };
+            // Both traces start on a rising zero crossing, so they line up
+            if let Some((_, capture)) = &self.scope_capture {
+                painter.add(trace(capture, SCOPE_CAPTURE_COLOR));
+            }
+            painter.add(trace(view, Color32::LIGHT_GREEN));
+        });
+    }
+
     pub fn update(&mut self, ctx: &egui::Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical(|ui| {
+                self.draw_safe_mode_banner(ui);
                 self.draw_header(ui);
                 ui.add_space(10.0);
                 self.draw_controls(ui);
@@ -124,12 +645,57 @@ impl SynthUI {
                 ui.add_space(10.0);
                 self.draw_filter_controls(ui);
                 ui.add_space(10.0);
+                self.draw_modulation_controls(ui);
+                ui.add_space(10.0);
+                self.draw_macros(ui);
+                ui.add_space(10.0);
                 self.draw_effects_controls(ui);
                 ui.add_space(10.0);
+                self.draw_mixer(ui);
+                ui.add_space(10.0);
+                self.draw_scope(ui);
+                ui.add_space(10.0);
                 self.draw_keyboard(ui);
                 self.handle_keyboard_input(ctx);
             });
         });
+
+        self.schedule_repaint(ctx);
+    }
+
+    /// Keeps meters animating at the configured rate while the audio thread
+    /// reports activity, and falls back to a slow poll when everything is idle
+    /// so new notes are still picked up without redrawing continuously.
+    fn schedule_repaint(&self, ctx: &egui::Context) {
+        if self.status.is_active() {
+            ctx.request_repaint_after(Duration::from_secs_f32(1.0 / self.refresh_rate));
+        } else {
+            ctx.request_repaint_after(IDLE_REFRESH_INTERVAL);
+        }
+    }
+
+    /// Explains why the synth started in safe mode and offers to move the tuning
+    /// file aside, so the next start doesn't trip over it again.
+    fn draw_safe_mode_banner(&mut self, ui: &mut egui::Ui) {
+        let reason = match &self.safe_mode {
+            Some(reason) => reason.to_string(),
+            None => return,
+        };
+        ui.horizontal(|ui| {
+            ui.colored_label(Color32::from_rgb(230, 180, 60), format!("Safe mode, using built-in defaults: {}", reason));
+            let config = Path::new(advanced::CONFIG_FILE);
+            if config.exists() && ui.button(format!("Back up {}", advanced::CONFIG_FILE)).clicked() {
+                self.backup_status = match safe_mode::back_up(config) {
+                    Ok(backup) => format!("Moved to {}", backup.display()),
+                    Err(err) => err.to_string(),
+                };
+            }
+            ui.label(&self.backup_status);
+            if ui.button("Dismiss").clicked() {
+                self.safe_mode = None;
+            }
+        });
+        ui.add_space(10.0);
     }
 
     fn draw_header(&mut self, ui: &mut egui::Ui) {
@@ -143,54 +709,530 @@ impl SynthUI {
             if ui.button("+").clicked() {
                 self.current_octave = (self.current_octave + 1).min(8);
             }
+            ui.add_space(20.0);
+            ui.label("Part:");
+            for part in 0..NUM_PARTS {
+                ui.selectable_value(&mut self.selected_part, part, format!("{}", part + 1));
+            }
+            ui.add_space(10.0);
+            let part = self.selected_part;
+            let mut channel = self.params.channel(part) + 1;
+            ui.label("MIDI Ch");
+            if ui.add(egui::DragValue::new(&mut channel).clamp_range(1..=16)).changed() {
+                self.params.set_channel(part, channel - 1);
+            }
+            let controls = &mut self.parts[part];
+            let mut latch = controls.latch;
+            if ui.toggle_value(&mut latch, "Latch")
+                .on_hover_text("Notes sustain until pressed again")
+                .changed()
+                && send_event(&self.events, EngineEvent::SetLatch { part, enabled: latch })
+            {
+                controls.latch = latch;
+            }
+            if ui.button("Panic").on_hover_text("Release every note on every part").clicked() {
+                send_event(&self.events, EngineEvent::AllNotesOff);
+            }
+            ui.add_space(20.0);
+            ui.label(format!("Voices: {}", self.status.active_voices()));
+            self.draw_polyphony(ui);
+            ui.add_space(10.0);
+            self.draw_dsp_load(ui);
+            ui.add_space(10.0);
+            ui.label("UI Rate");
+            ui.add(egui::DragValue::new(&mut self.refresh_rate).clamp_range(10.0..=144.0).suffix(" Hz"));
+        });
+    }
+
+    fn draw_polyphony(&mut self, ui: &mut egui::Ui) {
+        let limit = self.status.voice_limit();
+        let title = if self.adaptive_polyphony {
+            format!("Poly: {} (auto)", limit)
+        } else {
+            format!("Poly: {}", limit)
+        };
+        ui.menu_button(title, |ui| {
+            let (mut adaptive, mut min_voices, mut max_voices) = (self.adaptive_polyphony, self.min_voices, self.max_voices);
+            let mut changed = ui.checkbox(&mut adaptive, "Adaptive")
+                .on_hover_text("Lower the voices per part when the DSP load nears a dropout, and raise them while there is headroom")
+                .changed();
+            ui.add_enabled_ui(adaptive, |ui| {
+                changed |= ui.add(egui::Slider::new(&mut min_voices, 1..=MAX_VOICES_PER_PART).text("Min voices")).changed();
+            });
+            changed |= ui.add(egui::Slider::new(&mut max_voices, 1..=MAX_VOICES_PER_PART).text("Max voices")).changed();
+            min_voices = min_voices.min(max_voices);
+            if changed && send_event(&self.events, EngineEvent::SetPolyphony { adaptive, min_voices, max_voices }) {
+                self.adaptive_polyphony = adaptive;
+                self.min_voices = min_voices;
+                self.max_voices = max_voices;
+            }
         });
     }
 
+    fn draw_dsp_load(&self, ui: &mut egui::Ui) {
+        let load = self.status.dsp_load();
+        let peak = self.status.dsp_peak();
+        let color = if peak >= 0.8 {
+            Color32::RED
+        } else if peak >= 0.5 {
+            Color32::YELLOW
+        } else {
+            ui.visuals().text_color()
+        };
+        let label = egui::RichText::new(format!("DSP: {:.0}% (peak {:.0}%)", load * 100.0, peak * 100.0)).color(color);
+        if ui.add(egui::Label::new(label).sense(egui::Sense::click()))
+            .on_hover_text("Render time per audio callback relative to the buffer length. Click to reset the peak.")
+            .clicked()
+        {
+            self.status.reset_dsp_peak();
+        }
+    }
+
     fn draw_controls(&mut self, ui: &mut egui::Ui) {
+        let part = self.selected_part;
         ui.horizontal(|ui| {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Volume");
-                    if ui.add(egui::Slider::new(&mut self.volume, 0.0..=1.0)).changed() {
-                        let mut vm = self.voice_manager.lock();
-                        for voice in &mut vm.voices {
-                            voice.oscillator.set_volume(self.volume);
+                    let params = &self.params;
+                    let mut volume = params.part(part, PartParam::Volume);
+                    if ui.add(egui::Slider::new(&mut volume, PartParam::Volume.range())).changed() {
+                        set_part_param(params, part, PartParam::Volume, volume);
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Drift");
+                    let params = &self.params;
+                    let mut drift = params.part(part, PartParam::Drift);
+                    if ui.add(egui::Slider::new(&mut drift, PartParam::Drift.range())).changed() {
+                        set_part_param(params, part, PartParam::Drift, drift);
+                    }
+                    ui.label("Humanize").on_hover_text("Random offsets each note gets when it starts");
+                    for (param, text) in [
+                        (PartParam::HumanizePitch, "Pitch ±"),
+                        (PartParam::HumanizePan, "Pan ±"),
+                        (PartParam::HumanizeLevel, "Level ±"),
+                    ] {
+                        let mut value = params.part(part, param);
+                        if ui.add(egui::Slider::new(&mut value, param.range()).suffix(param.unit()).text(text)).changed() {
+                            set_part_param(params, part, param, value);
                         }
                     }
                 });
             });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Tuning");
+                    let params = &self.params;
+                    for (param, text, step) in [
+                        (PartParam::Octave, "Octave", 1.0),
+                        (PartParam::Semitone, "Semitone", 1.0),
+                        (PartParam::FineTune, "Fine", 0.0),
+                    ] {
+                        let mut value = params.part(part, param);
+                        let slider = egui::Slider::new(&mut value, param.range()).step_by(step).suffix(param.unit()).text(text);
+                        if ui.add(slider).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                    let controls = &mut self.parts[part];
+                    let mut key_tuning = controls.key_tuning;
+                    if ui.checkbox(&mut key_tuning, "Key tuning")
+                        .on_hover_text("Apply the per-key offsets edited under the keyboard")
+                        .changed()
+                        && send_event(&self.events, EngineEvent::SetKeyTuningEnabled { part, enabled: key_tuning })
+                    {
+                        controls.key_tuning = key_tuning;
+                    }
+                });
+            });
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Waveform");
-                    for waveform in [Waveform::Sine, Waveform::Square, Waveform::Sawtooth, Waveform::Triangle].iter() {
-                        if ui.selectable_value(&mut self.waveform, *waveform, format!("{:?}", waveform)).clicked() {
-                            let mut vm = self.voice_manager.lock();
-                            for voice in &mut vm.voices {
-                                voice.oscillator.set_waveform(self.waveform);
+                    let controls = &mut self.parts[part];
+                    for waveform in [Waveform::Sine, Waveform::Square, Waveform::Sawtooth, Waveform::Triangle, Waveform::Wavetable, Waveform::Supersaw, Waveform::Sampler].iter() {
+                        if ui.selectable_label(controls.waveform == *waveform, format!("{:?}", waveform)).clicked()
+                            && send_event(&self.events, EngineEvent::SetWaveform { part, waveform: *waveform })
+                        {
+                            controls.waveform = *waveform;
+                        }
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Wavetable");
+                    let controls = &mut self.parts[part];
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut controls.wavetable_path).hint_text("table name or .wav path").desired_width(120.0));
+                        if ui.button("Load").clicked() {
+                            // Loaded here so the audio thread only receives the finished table
+                            match Wavetable::load(&wavetable::resolve_path(controls.wavetable_path.trim())) {
+                                Ok(table) => {
+                                    let table = Arc::new(table);
+                                    let status = format!("{} frames", table.frames());
+                                    if send_event(&self.events, EngineEvent::SetWavetable { part, table: Arc::clone(&table) }) {
+                                        controls.wavetable_status = status;
+                                        controls.wavetable = Some(table);
+                                    } else {
+                                        controls.wavetable_status = "The engine is busy, try again".to_string();
+                                    }
+                                }
+                                Err(err) => controls.wavetable_status = err.to_string(),
                             }
                         }
+                    });
+                    if !controls.wavetable_status.is_empty() {
+                        ui.label(&controls.wavetable_status);
+                    }
+                    let params = &self.params;
+                    let mut position = params.part(part, PartParam::WavePosition);
+                    let slider = egui::Slider::new(&mut position, PartParam::WavePosition.range()).text("Position");
+                    if ui.add_enabled(controls.waveform == Waveform::Wavetable, slider).changed() {
+                        set_part_param(params, part, PartParam::WavePosition, position);
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Sampler");
+                    let controls = &mut self.parts[part];
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut controls.sample_path).hint_text("sample.wav").desired_width(120.0));
+                        if ui.button("Load").clicked() {
+                            // Loaded here so the audio thread only receives the finished recording
+                            match SampleBuffer::load(Path::new(controls.sample_path.trim())) {
+                                Ok(sample) => {
+                                    let sample = Arc::new(sample);
+                                    let status = format!("{:.2} s at {} Hz", sample.duration(), sample.sample_rate());
+                                    if send_event(&self.events, EngineEvent::SetSample { part, sample: Arc::clone(&sample) }) {
+                                        controls.sample_status = status;
+                                        controls.sample = Some(sample);
+                                    } else {
+                                        controls.sample_status = "The engine is busy, try again".to_string();
+                                    }
+                                }
+                                Err(err) => controls.sample_status = err.to_string(),
+                            }
+                        }
+                    });
+                    if !controls.sample_status.is_empty() {
+                        ui.label(&controls.sample_status);
+                    }
+
+                    let enabled = controls.waveform == Waveform::Sampler;
+                    let params = &self.params;
+                    let mut root = params.part(part, PartParam::SampleRoot);
+                    let slider = egui::Slider::new(&mut root, PartParam::SampleRoot.range())
+                        .step_by(1.0)
+                        .custom_formatter(|note, _| note_name(note as u8))
+                        .text("Root");
+                    if ui.add_enabled(enabled, slider).changed() {
+                        set_part_param(params, part, PartParam::SampleRoot, root);
+                    }
+                    for (param, text) in [
+                        (PartParam::SampleStart, "Start"),
+                        (PartParam::LoopStart, "Loop Start"),
+                        (PartParam::LoopEnd, "Loop End"),
+                    ] {
+                        let mut value = params.part(part, param);
+                        let slider = egui::Slider::new(&mut value, param.range()).text(text);
+                        if ui.add_enabled(enabled, slider).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                    let mut sample_loop = controls.sample_loop;
+                    if ui.add_enabled(enabled, egui::Checkbox::new(&mut sample_loop, "Loop")).changed()
+                        && send_event(&self.events, EngineEvent::SetSampleLoop { part, enabled: sample_loop })
+                    {
+                        controls.sample_loop = sample_loop;
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Wavefolder");
+                    let params = &self.params;
+                    for (param, text) in [
+                        (PartParam::FoldAmount, "Amount"),
+                        (PartParam::FoldSymmetry, "Symmetry"),
+                    ] {
+                        let mut value = params.part(part, param);
+                        if ui.add(egui::Slider::new(&mut value, param.range()).text(text)).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Supersaw");
+                    let enabled = self.parts[part].waveform == Waveform::Supersaw;
+                    let params = &self.params;
+                    for (param, text) in [
+                        (PartParam::SupersawDetune, "Detune"),
+                        (PartParam::SupersawMix, "Mix"),
+                    ] {
+                        let mut value = params.part(part, param);
+                        let slider = egui::Slider::new(&mut value, param.range()).text(text);
+                        if ui.add_enabled(enabled, slider).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Pulse");
+                    // Pulse width only shapes the square wave
+                    let enabled = self.parts[part].waveform == Waveform::Square;
+                    let params = &self.params;
+                    for (param, text) in [
+                        (PartParam::PulseWidth, "Width"),
+                        (PartParam::PwmAmount, "PWM"),
+                        (PartParam::PwmRate, "Rate"),
+                    ] {
+                        let mut value = params.part(part, param);
+                        let slider = egui::Slider::new(&mut value, param.range()).suffix(param.unit()).text(text);
+                        if ui.add_enabled(enabled, slider).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Phase");
+                    let controls = &mut self.parts[part];
+                    for mode in PhaseMode::ALL {
+                        if ui.selectable_label(controls.phase_mode == mode, mode.name()).clicked()
+                            && send_event(&self.events, EngineEvent::SetPhaseMode { part, mode })
+                        {
+                            controls.phase_mode = mode;
+                        }
+                    }
+                    let params = &self.params;
+                    let mut start_phase = params.part(part, PartParam::StartPhase);
+                    if ui.add_enabled(controls.phase_mode == PhaseMode::Reset, egui::Slider::new(&mut start_phase, PartParam::StartPhase.range()).suffix(PartParam::StartPhase.unit())).changed() {
+                        set_part_param(params, part, PartParam::StartPhase, start_phase);
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Preview");
+                    self.draw_waveform_preview(ui);
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Voice Mode");
+                    let controls = &mut self.parts[part];
+                    ui.horizontal(|ui| {
+                        for mode in VoiceMode::ALL {
+                            if ui.selectable_label(controls.voice_mode == mode, mode.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetVoiceMode { part, mode })
+                            {
+                                controls.voice_mode = mode;
+                            }
+                        }
+                    });
+                    if controls.voice_mode.is_mono() {
+                        ui.horizontal(|ui| {
+                            ui.label("Priority");
+                            for priority in NotePriority::ALL {
+                                if ui.selectable_label(controls.note_priority == priority, priority.name()).clicked()
+                                    && send_event(&self.events, EngineEvent::SetNotePriority { part, priority })
+                                {
+                                    controls.note_priority = priority;
+                                }
+                            }
+                        });
+                    }
+                    ui.label("Voice Steal");
+                    let mut steal_policy = controls.steal_policy;
+                    egui::ComboBox::from_id_source("steal_policy")
+                        .selected_text(steal_policy.name())
+                        .show_ui(ui, |ui| {
+                            for policy in StealPolicy::ALL {
+                                ui.selectable_value(&mut steal_policy, policy, policy.name());
+                            }
+                        });
+                    if steal_policy != controls.steal_policy
+                        && send_event(&self.events, EngineEvent::SetStealPolicy { part, policy: steal_policy })
+                    {
+                        controls.steal_policy = steal_policy;
+                    }
+                    ui.label("Same Note");
+                    let mut same_note_policy = controls.same_note_policy;
+                    egui::ComboBox::from_id_source("same_note_policy")
+                        .selected_text(same_note_policy.name())
+                        .show_ui(ui, |ui| {
+                            for policy in SameNotePolicy::ALL {
+                                ui.selectable_value(&mut same_note_policy, policy, policy.name());
+                            }
+                        });
+                    if same_note_policy != controls.same_note_policy
+                        && send_event(&self.events, EngineEvent::SetSameNotePolicy { part, policy: same_note_policy })
+                    {
+                        controls.same_note_policy = same_note_policy;
+                    }
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Unison");
+                    let controls = &mut self.parts[part];
+                    let mut voices = controls.unison_voices;
+                    if ui.add(egui::Slider::new(&mut voices, 1..=MAX_UNISON_VOICES).text("Voices")).changed()
+                        && send_event(&self.events, EngineEvent::SetUnisonVoices { part, voices })
+                    {
+                        controls.unison_voices = voices;
+                    }
+                    let params = &self.params;
+                    let mut detune = params.part(part, PartParam::UnisonDetune);
+                    if ui.add(egui::Slider::new(&mut detune, PartParam::UnisonDetune.range()).suffix(PartParam::UnisonDetune.unit()).text("Detune")).changed() {
+                        set_part_param(params, part, PartParam::UnisonDetune, detune);
+                    }
+                    let mut spread = params.part(part, PartParam::UnisonSpread);
+                    if ui.add(egui::Slider::new(&mut spread, PartParam::UnisonSpread.range()).text("Spread")).changed() {
+                        set_part_param(params, part, PartParam::UnisonSpread, spread);
+                    }
+                    ui.horizontal(|ui| {
+                        for mode in UnisonStereo::ALL {
+                            if ui.selectable_label(controls.unison_stereo == mode, mode.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetUnisonStereo { part, mode })
+                            {
+                                controls.unison_stereo = mode;
+                            }
+                        }
+                    });
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Key Pressure");
+                    ui.checkbox(&mut self.key_pressure_enabled, "Hold to swell");
+                    ui.add(egui::Slider::new(&mut self.key_pressure_rise, 0.1..=5.0).suffix(" s").logarithmic(true).text("Rise"));
+                    ui.add(egui::ProgressBar::new(self.key_pressure).desired_width(100.0));
+                });
+            });
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Wheels").on_hover_text("On-screen mod wheel and pitch bend for the selected part. Bend moves the pitch two semitones either way, and both are sources in the mod matrix");
+                    let mut mod_wheel = self.mod_wheel;
+                    if ui.add(egui::Slider::new(&mut mod_wheel, 0.0..=1.0).text("Mod")).changed()
+                        && send_event(&self.events, EngineEvent::PartModWheel { part, value: mod_wheel })
+                    {
+                        self.mod_wheel = mod_wheel;
+                    }
+                    let mut pitch_bend = self.pitch_bend;
+                    let bend = ui.add(egui::Slider::new(&mut pitch_bend, -1.0..=1.0).text("Bend"));
+                    // Springs back to the centre like a bend wheel
+                    if bend.drag_released() {
+                        pitch_bend = 0.0;
+                    }
+                    if (bend.changed() || bend.drag_released())
+                        && send_event(&self.events, EngineEvent::PartPitchBend { part, bend: pitch_bend })
+                    {
+                        self.pitch_bend = pitch_bend;
                     }
                 });
             });
         });
     }
 
+    fn draw_waveform_preview(&mut self, ui: &mut egui::Ui) {
+        let part = self.selected_part;
+        let waveform = self.parts[part].waveform;
+        let wavetable = self.parts[part].wavetable.clone();
+        let sample = self.parts[part].sample.clone();
+        let settings = [
+            self.params.part(part, PartParam::WavePosition),
+            self.params.part(part, PartParam::SupersawDetune),
+            self.params.part(part, PartParam::SupersawMix),
+        ];
+        // Only recompute the cycle when the oscillator settings it depends on change
+        let table_id = wavetable.as_ref().map_or(0, |table| Arc::as_ptr(table) as usize);
+        let sample_id = sample.as_ref().map_or(0, |sample| Arc::as_ptr(sample) as usize);
+        let key = (waveform, settings, table_id, sample_id);
+        if self.preview_key != Some(key) {
+            // One cycle per "second" at a sample rate of PREVIEW_POINTS gives one point per sample
+            let mut preview = Oscillator::new(PREVIEW_POINTS as f32, 1.0);
+            preview.set_waveform(waveform);
+            preview.set_wavetable(wavetable);
+            preview.set_sample(sample);
+            preview.set_wave_position(settings[0]);
+            preview.set_supersaw_detune(settings[1]);
+            preview.set_supersaw_mix(settings[2]);
+            self.preview_points = preview.cycle_preview(PREVIEW_POINTS);
+            self.preview_key = Some(key);
+        }
+
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(160.0, 80.0), egui::Sense::hover());
+        let painter = ui.painter();
+        painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+        painter.line_segment(
+            [rect.left_center(), rect.right_center()],
+            Stroke::new(1.0, Color32::from_gray(60)),
+        );
+
+        let points: Vec<egui::Pos2> = self.preview_points.iter()
+            .enumerate()
+            .map(|(i, &sample)| {
+                let x = rect.left() + rect.width() * i as f32 / (PREVIEW_POINTS - 1) as f32;
+                let y = rect.center().y - sample.clamp(-1.0, 1.0) * rect.height() * 0.45;
+                egui::pos2(x, y)
+            })
+            .collect();
+        painter.add(egui::Shape::line(points, Stroke::new(1.5, Color32::LIGHT_GREEN)));
+    }
+
     fn draw_envelope_controls(&mut self, ui: &mut egui::Ui) {
+        let part = self.selected_part;
+        let params = &self.params;
         ui.horizontal(|ui| {
-            let mut attack = self.attack;
-            let mut decay = self.decay;
-            let mut sustain = self.sustain;
-            let mut release = self.release;
-    
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Delay");
+                    let mut delay = params.part(part, PartParam::EnvelopeDelay);
+                    if ui.add(egui::Slider::new(&mut delay, PartParam::EnvelopeDelay.range()).suffix(PartParam::EnvelopeDelay.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::EnvelopeDelay, delay);
+                    }
+                });
+            });
+
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Attack");
-                    if ui.add(egui::Slider::new(&mut attack, 0.01..=2.0).logarithmic(true)).changed() {
-                        self.attack = attack;
-                        let mut vm = self.voice_manager.lock();
-                        for voice in &mut vm.voices {
-                            voice.envelope.set_attack(self.attack);
+                    let mut attack = params.part(part, PartParam::Attack);
+                    if ui.add(egui::Slider::new(&mut attack, PartParam::Attack.range()).suffix(PartParam::Attack.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::Attack, attack);
+                    }
+                    Self::draw_curve_selector(ui, &mut self.parts[part].attack_curve, &self.events, part, EnvelopeStage::Attack);
+                    ui.horizontal(|ui| {
+                        ui.label("Retrigger:");
+                        let controls = &mut self.parts[part];
+                        for mode in RetriggerMode::ALL {
+                            if ui.selectable_label(controls.retrigger_mode == mode, mode.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetRetriggerMode { part, mode })
+                            {
+                                controls.retrigger_mode = mode;
+                            }
                         }
+                    });
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Hold");
+                    let mut hold = params.part(part, PartParam::Hold);
+                    if ui.add(egui::Slider::new(&mut hold, PartParam::Hold.range()).suffix(PartParam::Hold.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::Hold, hold);
                     }
                 });
             });
@@ -198,25 +1240,20 @@ impl SynthUI {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Decay");
-                    if ui.add(egui::Slider::new(&mut decay, 0.01..=2.0).logarithmic(true)).changed() {
-                        self.decay = decay;
-                        let mut vm = self.voice_manager.lock();
-                        for voice in &mut vm.voices {
-                            voice.envelope.set_decay(self.decay);
-                        }
+                    let mut decay = params.part(part, PartParam::Decay);
+                    if ui.add(egui::Slider::new(&mut decay, PartParam::Decay.range()).suffix(PartParam::Decay.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::Decay, decay);
                     }
+                    Self::draw_curve_selector(ui, &mut self.parts[part].decay_curve, &self.events, part, EnvelopeStage::Decay);
                 });
             });
     
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Sustain");
-                    if ui.add(egui::Slider::new(&mut sustain, 0.0..=1.0)).changed() {
-                        self.sustain = sustain;
-                        let mut vm = self.voice_manager.lock();
-                        for voice in &mut vm.voices {
-                            voice.envelope.set_sustain(self.sustain);
-                        }
+                    let mut sustain = params.part(part, PartParam::Sustain);
+                    if ui.add(egui::Slider::new(&mut sustain, PartParam::Sustain.range())).changed() {
+                        set_part_param(params, part, PartParam::Sustain, sustain);
                     }
                 });
             });
@@ -224,27 +1261,148 @@ impl SynthUI {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Release");
-                    if ui.add(egui::Slider::new(&mut release, 0.01..=2.0).logarithmic(true)).changed() {
-                        self.release = release;
-                        let mut vm = self.voice_manager.lock();
-                        for voice in &mut vm.voices {
-                            voice.envelope.set_release(self.release);
+                    let mut release = params.part(part, PartParam::Release);
+                    if ui.add(egui::Slider::new(&mut release, PartParam::Release.range()).suffix(PartParam::Release.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::Release, release);
+                    }
+                    ui.horizontal(|ui| {
+                        let controls = &mut self.parts[part];
+                        for mode in ReleaseMode::ALL {
+                            if ui.selectable_label(controls.release_mode == mode, mode.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetReleaseMode { part, mode })
+                            {
+                                controls.release_mode = mode;
+                            }
                         }
+                    });
+                    Self::draw_curve_selector(ui, &mut self.parts[part].release_curve, &self.events, part, EnvelopeStage::Release);
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Velocity");
+                    let mut level = params.part(part, PartParam::VelocityToLevel);
+                    if ui.add(egui::Slider::new(&mut level, PartParam::VelocityToLevel.range()).text("Level")).changed() {
+                        set_part_param(params, part, PartParam::VelocityToLevel, level);
+                    }
+                    let mut attack = params.part(part, PartParam::VelocityToAttack);
+                    if ui.add(egui::Slider::new(&mut attack, PartParam::VelocityToAttack.range()).text("Attack")).changed() {
+                        set_part_param(params, part, PartParam::VelocityToAttack, attack);
                     }
                 });
             });
+
+            Self::draw_envelope_preview(ui, params, &self.status, &self.parts[part], part);
+        });
+    }
+
+    /// Draws the selected part's envelope as set, with a playhead following
+    /// the most recently played note through it.
+    fn draw_envelope_preview(ui: &mut egui::Ui, params: &SynthParams, status: &EngineStatus, controls: &PartControls, part: usize) {
+        let mut envelope = Envelope::new(1.0);
+        envelope.set_delay(params.part(part, PartParam::EnvelopeDelay));
+        envelope.set_attack(params.part(part, PartParam::Attack));
+        envelope.set_hold(params.part(part, PartParam::Hold));
+        envelope.set_decay(params.part(part, PartParam::Decay));
+        envelope.set_sustain(params.part(part, PartParam::Sustain));
+        envelope.set_release(params.part(part, PartParam::Release));
+        envelope.set_release_mode(controls.release_mode);
+        envelope.set_curve(EnvelopeStage::Attack, controls.attack_curve);
+        envelope.set_curve(EnvelopeStage::Decay, controls.decay_curve);
+        envelope.set_curve(EnvelopeStage::Release, controls.release_curve);
+
+        // The sustain is drawn a quarter as long as the rest, so it stays visible
+        let others: f32 = envelope.outline(0.0).iter().map(|segment| segment.duration).sum();
+        let outline = envelope.outline((others * 0.25).max(0.05));
+        let total: f32 = outline.iter().map(|segment| segment.duration).sum();
+
+        let (rect, _) = ui.allocate_exact_size(Vec2::new(200.0, 80.0), egui::Sense::hover());
+        let painter = ui.painter();
+        painter.rect_filled(rect, 2.0, Color32::from_gray(20));
+        let to_screen = |time: f32, level: f32| {
+            egui::pos2(
+                rect.left() + rect.width() * time / total,
+                rect.bottom() - level.clamp(0.0, 1.0) * rect.height() * 0.9,
+            )
+        };
+
+        let playhead = status.envelope_position(part);
+        let mut points = Vec::with_capacity(outline.len() * ENVELOPE_SEGMENT_POINTS);
+        let mut start = 0.0;
+        for segment in &outline {
+            let level_at = |progress: f32| {
+                segment.start_level + (segment.end_level - segment.start_level) * segment.curve.shape(progress)
+            };
+            for i in 0..=ENVELOPE_SEGMENT_POINTS {
+                let progress = i as f32 / ENVELOPE_SEGMENT_POINTS as f32;
+                points.push(to_screen(start + segment.duration * progress, level_at(progress)));
+            }
+            if let Some((stage, progress)) = playhead {
+                // The sustain has no length of its own, so the note sits mid-way along it
+                let progress = if stage == EnvelopeStage::Sustain { 0.5 } else { progress };
+                if stage == segment.stage {
+                    let head = to_screen(start + segment.duration * progress, level_at(progress));
+                    painter.line_segment([egui::pos2(head.x, rect.top()), egui::pos2(head.x, rect.bottom())], Stroke::new(1.0, Color32::from_gray(90)));
+                    painter.circle_filled(head, 3.0, Color32::YELLOW);
+                }
+            }
+            start += segment.duration;
+        }
+        painter.add(egui::Shape::line(points, Stroke::new(1.5, Color32::LIGHT_GREEN)));
+    }
+
+    /// Row of curve shapes for one envelope segment.
+    fn draw_curve_selector(ui: &mut egui::Ui, current: &mut EnvelopeCurve, events: &EventSender, part: usize, stage: EnvelopeStage) {
+        ui.horizontal(|ui| {
+            for curve in EnvelopeCurve::ALL {
+                if ui.selectable_label(*current == curve, curve.name()).clicked()
+                    && send_event(events, EngineEvent::SetEnvelopeCurve { part, stage, curve })
+                {
+                    *current = curve;
+                }
+            }
         });
     }
 
 
 
     fn draw_filter_controls(&mut self, ui: &mut egui::Ui) {
+        let part = self.selected_part;
+        let params = &self.params;
         ui.horizontal(|ui| {
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Filter Type");
+                    let controls = &mut self.parts[part];
+                    ui.horizontal(|ui| {
+                        for filter_type in FilterType::ALL {
+                            if ui.selectable_label(controls.filter_type == filter_type, filter_type.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetFilterType { part, filter_type })
+                            {
+                                controls.filter_type = filter_type;
+                            }
+                        }
+                    });
+                    // The comb is tuned by the cutoff, with its peaks at every harmonic of it
+                    ui.add_enabled_ui(controls.filter_type == FilterType::Comb, |ui| {
+                        for (param, text) in [(PartParam::CombFeedback, "Feedback"), (PartParam::CombKeyTrack, "Key Track")] {
+                            let mut value = params.part(part, param);
+                            if ui.add(egui::Slider::new(&mut value, param.range()).text(text)).changed() {
+                                set_part_param(params, part, param, value);
+                            }
+                        }
+                    }).response.on_hover_text("Negative feedback gives a hollow, odd-harmonic tone. Full key tracking plays the cutoff frequency at C4 and follows the keyboard from there.");
+                });
+            });
+
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Filter Cutoff");
-                    if ui.add(egui::Slider::new(&mut self.filter_cutoff, 20.0..=20000.0).logarithmic(true)).changed() {
-                        self.voice_manager.lock().set_filter_cutoff(self.filter_cutoff);
+                    let mut filter_cutoff = params.part(part, PartParam::FilterCutoff);
+                    if ui.add(egui::Slider::new(&mut filter_cutoff, PartParam::FilterCutoff.range()).suffix(PartParam::FilterCutoff.unit()).logarithmic(true)).changed() {
+                        set_part_param(params, part, PartParam::FilterCutoff, filter_cutoff);
                     }
                 });
             });
@@ -252,8 +1410,9 @@ impl SynthUI {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Filter Resonance");
-                    if ui.add(egui::Slider::new(&mut self.filter_resonance, 0.0..=4.0)).changed() {
-                        self.voice_manager.lock().set_filter_resonance(self.filter_resonance);
+                    let mut filter_resonance = params.part(part, PartParam::FilterResonance);
+                    if ui.add(egui::Slider::new(&mut filter_resonance, PartParam::FilterResonance.range())).changed() {
+                        set_part_param(params, part, PartParam::FilterResonance, filter_resonance);
                     }
                 });
             });
@@ -261,8 +1420,9 @@ impl SynthUI {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Filter Drive");
-                    if ui.add(egui::Slider::new(&mut self.filter_drive, 0.1..=5.0)).changed() {
-                        self.voice_manager.lock().set_filter_drive(self.filter_drive);
+                    let mut filter_drive = params.part(part, PartParam::FilterDrive);
+                    if ui.add(egui::Slider::new(&mut filter_drive, PartParam::FilterDrive.range())).changed() {
+                        set_part_param(params, part, PartParam::FilterDrive, filter_drive);
                     }
                 });
             });
@@ -270,8 +1430,229 @@ impl SynthUI {
             ui.group(|ui| {
                 ui.vertical(|ui| {
                     ui.label("Filter Saturation");
-                    if ui.add(egui::Slider::new(&mut self.filter_saturation, 0.00..=2.00)).changed() {
-                        self.voice_manager.lock().set_filter_saturation(self.filter_saturation);
+                    let mut filter_saturation = params.part(part, PartParam::FilterSaturation);
+                    if ui.add(egui::Slider::new(&mut filter_saturation, PartParam::FilterSaturation.range())).changed() {
+                        set_part_param(params, part, PartParam::FilterSaturation, filter_saturation);
+                    }
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Cutoff LFO");
+                    for (param, text) in [(PartParam::CutoffLfoRate, "Rate"), (PartParam::CutoffLfoDepth, "Depth")] {
+                        let mut value = params.part(part, param);
+                        let slider = egui::Slider::new(&mut value, param.range())
+                            .suffix(param.unit())
+                            .logarithmic(param == PartParam::CutoffLfoRate)
+                            .text(text);
+                        if ui.add(slider).changed() {
+                            set_part_param(params, part, param, value);
+                        }
+                    }
+                    let controls = &mut self.parts[part];
+                    egui::ComboBox::from_id_source("cutoff_lfo_shape")
+                        .selected_text(controls.cutoff_lfo_shape.name())
+                        .show_ui(ui, |ui| {
+                            for shape in LfoShape::ALL {
+                                if ui.selectable_label(controls.cutoff_lfo_shape == shape, shape.name()).clicked()
+                                    && send_event(&self.events, EngineEvent::SetCutoffLfoShape { part, shape })
+                                {
+                                    controls.cutoff_lfo_shape = shape;
+                                }
+                            }
+                        });
+                });
+            });
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Level");
+                    let controls = &mut self.parts[part];
+                    let mut auto_gain = controls.auto_gain;
+                    if ui.checkbox(&mut auto_gain, "Auto Gain")
+                        .on_hover_text("Keep loudness steady as resonance and drive change")
+                        .changed()
+                        && send_event(&self.events, EngineEvent::SetAutoGain { part, enabled: auto_gain })
+                    {
+                        controls.auto_gain = auto_gain;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Oversampling").on_hover_text(
+                            "Run the filter faster internally, so bright resonant settings stay stable and clean at more CPU cost",
+                        );
+                        for oversampling in Oversampling::ALL {
+                            if ui.selectable_label(controls.oversampling == oversampling, oversampling.name()).clicked()
+                                && send_event(&self.events, EngineEvent::SetFilterOversampling { part, oversampling })
+                            {
+                                controls.oversampling = oversampling;
+                            }
+                        }
+                    });
+                });
+            });
+        });
+    }
+
+    fn draw_macros(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Macros", |ui| {
+            ui.horizontal(|ui| {
+                for (index, param) in MasterParam::MACROS.into_iter().enumerate() {
+                    ui.group(|ui| {
+                        ui.vertical(|ui| {
+                            let mut value = self.params.master(param);
+                            if ui.add(egui::Slider::new(&mut value, param.range()).text(param.name()))
+                                .on_hover_text(format!("Also turned by MIDI CC {}", MACRO_CCS[index]))
+                                .changed()
+                            {
+                                set_master_param(&self.params, param, value);
+                            }
+                            for slot in 0..MACRO_TARGETS {
+                                let mut target = self.macro_targets[index][slot];
+                                ui.horizontal(|ui| {
+                                    egui::ComboBox::from_id_source(format!("macro{}_target{}", index + 1, slot + 1))
+                                        .selected_text(target.map_or("None", |t| t.param.name()))
+                                        .show_ui(ui, |ui| {
+                                            ui.selectable_value(&mut target, None, "None");
+                                            // A new target starts on the selected part at half range
+                                            let part = target.map_or(self.selected_part, |t| t.part);
+                                            let amount = target.map_or(0.5, |t| t.amount);
+                                            for param in PartParam::ALL {
+                                                ui.selectable_value(&mut target, Some(MacroTarget { part, param, amount }), param.name());
+                                            }
+                                        });
+                                    if let Some(target) = &mut target {
+                                        egui::ComboBox::from_id_source(format!("macro{}_part{}", index + 1, slot + 1))
+                                            .width(60.0)
+                                            .selected_text(format!("Part {}", target.part + 1))
+                                            .show_ui(ui, |ui| {
+                                                for part in 0..NUM_PARTS {
+                                                    ui.selectable_value(&mut target.part, part, format!("Part {}", part + 1));
+                                                }
+                                            });
+                                        ui.add(egui::Slider::new(&mut target.amount, -1.0..=1.0))
+                                            .on_hover_text("Fraction of the parameter's range the full turn adds, negative to turn it down");
+                                    }
+                                });
+                                if target != self.macro_targets[index][slot]
+                                    && send_event(&self.events, EngineEvent::SetMacroTarget { index, slot, target })
+                                {
+                                    self.macro_targets[index][slot] = target;
+                                }
+                            }
+                        });
+                    });
+                }
+            });
+        });
+    }
+
+    fn draw_modulation_controls(&mut self, ui: &mut egui::Ui) {
+        let part = self.selected_part;
+        let params = &self.params;
+        ui.horizontal(|ui| {
+
+            for (index, lfo) in ModSource::LFOS.into_iter().enumerate() {
+                let (lfo_params, hint) = match lfo {
+                    ModSource::Lfo1 => (
+                        [PartParam::Lfo1Rate, PartParam::Lfo1Depth, PartParam::Lfo1Phase, PartParam::Lfo1FadeIn],
+                        "Shared by every voice of the part. Free-running by default, fading in again with each phrase.",
+                    ),
+                    _ => (
+                        [PartParam::Lfo2Rate, PartParam::Lfo2Depth, PartParam::Lfo2Phase, PartParam::Lfo2FadeIn],
+                        "One per voice. By default restarted at its phase and faded in with every note.",
+                    ),
+                };
+                ui.group(|ui| {
+                    ui.vertical(|ui| {
+                        ui.label(lfo.name()).on_hover_text(hint);
+                        let controls = &mut self.parts[part];
+                        for (param, text) in lfo_params.into_iter().zip(["Rate", "Depth", "Phase", "Fade In"]) {
+                            let mut value = params.part(part, param);
+                            let slider = egui::Slider::new(&mut value, param.range())
+                                .suffix(param.unit())
+                                .logarithmic(param == lfo_params[0])
+                                .text(text);
+                            // A synced LFO takes its rate from the tempo
+                            let free = param != lfo_params[0] || controls.lfo_syncs[index].is_none();
+                            if ui.add_enabled(free, slider).changed() {
+                                set_part_param(params, part, param, value);
+                            }
+                        }
+                        let sync_name = |sync: Option<NoteDivision>| sync.map_or("Free", NoteDivision::name);
+                        egui::ComboBox::from_id_source(format!("lfo{}_sync", index + 1))
+                            .selected_text(sync_name(controls.lfo_syncs[index]))
+                            .show_ui(ui, |ui| {
+                                let options = std::iter::once(None).chain(NoteDivision::ALL.into_iter().map(Some));
+                                for sync in options {
+                                    if ui.selectable_label(controls.lfo_syncs[index] == sync, sync_name(sync)).clicked()
+                                        && send_event(&self.events, EngineEvent::SetLfoSync { part, lfo, sync })
+                                    {
+                                        controls.lfo_syncs[index] = sync;
+                                    }
+                                }
+                            })
+                            .response
+                            .on_hover_text("Lock the rate to a note length at the master tempo or incoming MIDI clock");
+                        egui::ComboBox::from_id_source(format!("lfo{}_shape", index + 1))
+                            .selected_text(controls.lfo_shapes[index].name())
+                            .show_ui(ui, |ui| {
+                                for shape in LfoShape::ALL {
+                                    if ui.selectable_label(controls.lfo_shapes[index] == shape, shape.name()).clicked()
+                                        && send_event(&self.events, EngineEvent::SetLfoShape { part, lfo, shape })
+                                    {
+                                        controls.lfo_shapes[index] = shape;
+                                    }
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            for trigger in LfoTrigger::ALL {
+                                if ui.selectable_label(controls.lfo_triggers[index] == trigger, trigger.name()).clicked()
+                                    && send_event(&self.events, EngineEvent::SetLfoTrigger { part, lfo, trigger })
+                                {
+                                    controls.lfo_triggers[index] = trigger;
+                                }
+                            }
+                        })
+                        .response
+                        .on_hover_text("Retrigger starts the cycle over at the start phase with every note. One Shot plays a single cycle from there and holds its last level, like a simple envelope.");
+                    });
+                });
+            }
+
+            ui.group(|ui| {
+                ui.vertical(|ui| {
+                    ui.label("Mod Matrix");
+                    let amounts = [PartParam::ModAmount1, PartParam::ModAmount2, PartParam::ModAmount3, PartParam::ModAmount4];
+                    for (slot, amount_param) in amounts.into_iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            let controls = &mut self.parts[part];
+                            let (mut source, mut destination) = controls.mod_routes[slot];
+                            egui::ComboBox::from_id_source(format!("mod{}_source", slot + 1))
+                                .selected_text(source.name())
+                                .show_ui(ui, |ui| {
+                                    for option in ModSource::ALL {
+                                        ui.selectable_value(&mut source, option, option.name());
+                                    }
+                                });
+                            ui.label("→");
+                            egui::ComboBox::from_id_source(format!("mod{}_destination", slot + 1))
+                                .selected_text(destination.name())
+                                .show_ui(ui, |ui| {
+                                    for option in ModDestination::ALL {
+                                        ui.selectable_value(&mut destination, option, option.name());
+                                    }
+                                });
+                            if (source, destination) != controls.mod_routes[slot]
+                                && send_event(&self.events, EngineEvent::SetModRoute { part, slot, source, destination })
+                            {
+                                controls.mod_routes[slot] = (source, destination);
+                            }
+                            let mut amount = params.part(part, amount_param);
+                            if ui.add(egui::Slider::new(&mut amount, amount_param.range())).changed() {
+                                set_part_param(params, part, amount_param, amount);
+                            }
+                        });
                     }
                 });
             });
@@ -306,6 +1687,10 @@ impl SynthUI {
                     };
                     painter.rect_filled(key_rect, 0.0, color);
                     painter.rect_stroke(key_rect, 0.0, Stroke::new(1.0, Color32::BLACK));
+                    if self.key_states[note as usize] {
+                        Self::draw_key_label(painter, key_rect, note, Color32::BLACK);
+                    }
+                    self.draw_key_offset(painter, key_rect, note, Color32::DARK_RED);
                 }
             }
         }
@@ -314,14 +1699,7 @@ impl SynthUI {
         for visual_octave in 0..OCTAVES {
             for (i, &key_index) in BLACK_KEY_INDICES.iter().enumerate() {
                 if let Some(note) = self.calculate_midi_note(visual_octave as i32, key_index) {
-                    let x = match i {
-                        0 => white_key_width * 0.75,
-                        1 => white_key_width * 1.75,
-                        2 => white_key_width * 3.75,
-                        3 => white_key_width * 4.75,
-                        4 => white_key_width * 5.75,
-                        _ => unreachable!(),
-                    };
+                    let x = BLACK_KEY_OFFSETS[i] * white_key_width;
                     let key_rect = Rect::from_min_size(
                         rect.min + Vec2::new(x + visual_octave as f32 * 7.0 * white_key_width, 0.0),
                         Vec2::new(black_key_width, black_key_height),
@@ -333,52 +1711,97 @@ impl SynthUI {
                     };
                     painter.rect_filled(key_rect, 0.0, color);
                     painter.rect_stroke(key_rect, 0.0, Stroke::new(1.0, Color32::WHITE));
+                    if self.key_states[note as usize] {
+                        Self::draw_key_label(painter, key_rect, note, Color32::BLACK);
+                    }
+                    self.draw_key_offset(painter, key_rect, note, Color32::LIGHT_RED);
                 }
             }
         }
-    }
 
+        let hovered = response.hover_pos().and_then(|pos| key_at(rect, pos, KEYBOARD_KEYS, None));
+        if let Some(note) = hovered.and_then(|key| self.key_note(key)) {
+            response.on_hover_text_at_pointer(note_info(note));
+        }
 
+        let playing: Vec<String> = (0..128u8)
+            .filter(|&note| self.key_states[note as usize])
+            .map(note_info)
+            .collect();
+        if playing.is_empty() {
+            ui.label("Playing: -");
+        } else {
+            ui.label(format!("Playing: {}", playing.join(", ")));
+        }
+        ui.weak("Hold Shift for accented notes, Ctrl for soft notes");
+        self.draw_key_tuning_editor(ui);
+    }
 
-    fn get_note_from_pointer(&self, pos: egui::Pos2, rect: Rect) -> Option<u8> {
-        let rel_pos = pos - rect.min;
-        let octave_width = rect.width() / (OCTAVES as f32);
-        let x_in_keyboard = rel_pos.x;
-        let y = rel_pos.y;
+    /// Writes a retuned key's offset at the top of the key.
+    fn draw_key_offset(&self, painter: &egui::Painter, key_rect: Rect, note: u8, color: Color32) {
+        let cents = self.key_tuning.offset(note);
+        if cents == 0.0 {
+            return;
+        }
+        painter.text(
+            key_rect.center_top() + Vec2::new(0.0, 4.0),
+            Align2::CENTER_TOP,
+            format!("{:+.0}", cents),
+            FontId::proportional(10.0),
+            color,
+        );
+    }
 
-        let white_key_width = octave_width / 7.0;
-        let black_key_width = white_key_width * 0.6;
-        let black_key_height = rect.height() * 0.6;
-
-        // Calculate the visual octave and key within the keyboard
-        let visual_octave = (x_in_keyboard / octave_width) as i32;
-        let x_in_octave = x_in_keyboard % octave_width;
-
-        // Check black keys first
-        for (i, &key_index) in BLACK_KEY_INDICES.iter().enumerate() {
-            let x = match i {
-                0 => white_key_width * 0.75,
-                1 => white_key_width * 1.75,
-                2 => white_key_width * 3.75,
-                3 => white_key_width * 4.75,
-                4 => white_key_width * 5.75,
-                _ => unreachable!(),
-            };
-            if x_in_octave >= x && x_in_octave < x + black_key_width && y < black_key_height {
-                return self.calculate_midi_note(visual_octave, key_index);
+    /// Offset of the last played key in cents, shared by every part that has
+    /// key tuning switched on.
+    fn draw_key_tuning_editor(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let note = self.tuning_key;
+            ui.label(format!("Key tuning: {}", note_name(note)))
+                .on_hover_text("Play a key to select it, then nudge it from equal temperament");
+            let mut cents = self.key_tuning.offset(note);
+            let drag = egui::DragValue::new(&mut cents)
+                .clamp_range(-MAX_KEY_OFFSET..=MAX_KEY_OFFSET)
+                .speed(0.1)
+                .suffix(" ct");
+            if ui.add(drag).changed() {
+                self.set_key_offset(note, cents);
             }
-        }
+            if ui.button("Reset Key").clicked() {
+                self.set_key_offset(note, 0.0);
+            }
+            if ui.button("Reset All").clicked() {
+                let retuned: Vec<u8> = self.key_tuning.retuned_keys().map(|(note, _)| note).collect();
+                for note in retuned {
+                    self.set_key_offset(note, 0.0);
+                }
+            }
+            if ui.button("Save").on_hover_text(format!("Write the offsets to {}", key_tuning::KEY_TUNING_FILE)).clicked() {
+                self.key_tuning_status = match self.key_tuning.save(Path::new(key_tuning::KEY_TUNING_FILE)) {
+                    Ok(()) => format!("Saved to {}", key_tuning::KEY_TUNING_FILE),
+                    Err(err) => format!("Error: {}", err),
+                };
+            }
+            ui.label(&self.key_tuning_status);
+        });
+    }
 
-        // If not a black key, it must be a white key
-        let white_key_index = (x_in_octave / white_key_width) as usize;
-        if white_key_index < WHITE_KEY_INDICES.len() {
-            let key_index = WHITE_KEY_INDICES[white_key_index];
-            return self.calculate_midi_note(visual_octave, key_index);
+    fn set_key_offset(&mut self, note: u8, cents: f32) {
+        if send_event(&self.events, EngineEvent::SetKeyOffset { note, cents }) {
+            self.key_tuning.set_offset(note, cents);
+            self.key_tuning_status.clear();
         }
-
-        None
     }
 
+    fn draw_key_label(painter: &egui::Painter, key_rect: Rect, note: u8, color: Color32) {
+        painter.text(
+            key_rect.center_bottom() - Vec2::new(0.0, 4.0),
+            Align2::CENTER_BOTTOM,
+            note_name(note),
+            FontId::proportional(11.0),
+            color,
+        );
+    }
 
 
 
@@ -388,32 +1811,69 @@ impl SynthUI {
             Key::Q, Key::Num2, Key::W, Key::Num3, Key::E, Key::R, Key::Num5, Key::T, Key::Num6, Key::Y, Key::Num7, Key::U,
         ];
 
+        let velocity = ctx.input(|i| {
+            if i.modifiers.shift {
+                VELOCITY_ACCENT
+            } else if i.modifiers.ctrl {
+                VELOCITY_SOFT
+            } else {
+                VELOCITY_NORMAL
+            }
+        });
+
         for &key in KEYS.iter() {
-            if ctx.input(|i| i.key_pressed(key)) && !self.pressed_keys.contains(&key) {
+            if ctx.input(|i| i.key_pressed(key)) && !self.pressed_keys.contains_key(&key) {
                 if let Some(note) = self.key_to_note(key) {
-                    self.play_note(note);
-                    self.pressed_keys.insert(key);
+                    self.play_note(note, velocity);
+                    self.pressed_keys.insert(key, note);
                 }
             }
             if ctx.input(|i| i.key_released(key)) {
-                if let Some(note) = self.key_to_note(key) {
+                if let Some(note) = self.pressed_keys.remove(&key) {
                     self.stop_note(note);
-                    self.pressed_keys.remove(&key);
                 }
             }
         }
+
+        self.update_key_pressure(ctx);
+    }
+
+    /// Builds a simulated aftertouch value while any computer key is held and
+    /// sends it to the selected part. Pressure follows a smoothstep curve over
+    /// the rise time so swells start gently, and drops to zero on release.
+    fn update_key_pressure(&mut self, ctx: &egui::Context) {
+        let target = if self.key_pressure_enabled && !self.pressed_keys.is_empty() {
+            self.key_hold_time += ctx.input(|i| i.stable_dt);
+            let t = (self.key_hold_time / self.key_pressure_rise).min(1.0);
+            t * t * (3.0 - 2.0 * t)
+        } else {
+            self.key_hold_time = 0.0;
+            0.0
+        };
+
+        if ((target - self.key_pressure).abs() >= PRESSURE_STEP || (target == 0.0 && self.key_pressure != 0.0))
+            && send_event(&self.events, EngineEvent::PartPressure { part: self.selected_part, pressure: target })
+        {
+            self.key_pressure = target;
+        }
+
+        if self.key_pressure_enabled && !self.pressed_keys.is_empty() && target < 1.0 {
+            ctx.request_repaint();
+        }
     }
 
     fn handle_mouse_input(&mut self, ui: &egui::Ui, rect: Rect, response: &egui::Response) {
         if let Some(pos) = ui.input(|i| i.pointer.interact_pos()) {
-            if let Some(note) = self.get_note_from_pointer(pos, rect) {
-                if response.clicked() || (response.dragged() && Some(note) != self.active_mouse_note) {
+            let previous = self.active_mouse_note.and_then(|note| self.note_key(note));
+            if let Some(note) = key_at(rect, pos, KEYBOARD_KEYS, previous).and_then(|key| self.key_note(key)) {
+                let changed = Some(note) != self.active_mouse_note;
+                if response.clicked() || (response.dragged() && changed) {
                     // Stop the previous note if there was one
                     if let Some(old_note) = self.active_mouse_note.take() {
                         self.stop_note(old_note);
                     }
                     // Play the new note
-                    self.play_note(note);
+                    self.play_note(note, VELOCITY_NORMAL);
                     self.active_mouse_note = Some(note);
                 }
             }
@@ -441,6 +1901,17 @@ impl SynthUI {
         self.calculate_midi_note(octave_offset, note_index.try_into().unwrap())
     }
 
+    /// MIDI note of on-screen key `key` (0 is the lowest C) at the current octave.
+    fn key_note(&self, key: usize) -> Option<u8> {
+        self.calculate_midi_note((key / 12) as i32, key % 12)
+    }
+
+    /// On-screen key playing `note` at the current octave.
+    fn note_key(&self, note: u8) -> Option<usize> {
+        let key = note as i32 - self.current_octave * 12;
+        (0..KEYBOARD_KEYS as i32).contains(&key).then_some(key as usize)
+    }
+
     fn calculate_midi_note(&self, visual_octave: i32, key_index: usize) -> Option<u8> {
         let base_note = (self.current_octave + visual_octave) * 12 + key_index as i32;
         if base_note >= 0 && base_note <= 127 {
@@ -450,15 +1921,70 @@ impl SynthUI {
         }
     }
 
-    fn play_note(&mut self, note: u8) {
-        self.voice_manager.lock().note_on(note);
-        self.key_states[note as usize] = true;
-        println!("Playing note: {} ({:.2} Hz)", note, Oscillator::note_to_frequency(note));
+    fn play_note(&mut self, note: u8, velocity: u8) {
+        if send_event(&self.events, EngineEvent::PartNoteOn { part: self.selected_part, note, velocity }) {
+            self.key_states[note as usize] = true;
+            self.tuning_key = note;
+        }
     }
 
+    /// Releases the key even if the note-off could not be queued, since the
+    /// key itself is up; the failure is reported by `send_event`.
     fn stop_note(&mut self, note: u8) {
-        self.voice_manager.lock().note_off(note);
+        send_event(&self.events, EngineEvent::PartNoteOff { part: self.selected_part, note });
         self.key_states[note as usize] = false;
-        println!("Stopping note: {}", note);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use eframe::egui::{pos2, vec2};
+
+    /// Three octaves 420 wide, so white keys are 20 wide and black keys
+    /// cover the top 60
+    fn keyboard() -> Rect {
+        Rect::from_min_size(pos2(0.0, 0.0), vec2(420.0, 100.0))
+    }
+
+    #[test]
+    fn clamps_at_the_left_edge() {
+        assert_eq!(key_at(keyboard(), pos2(0.0, 80.0), 36, None), Some(0));
+        assert_eq!(key_at(keyboard(), pos2(-2.0, 80.0), 36, None), Some(0));
+    }
+
+    #[test]
+    fn clamps_at_the_right_edge() {
+        assert_eq!(key_at(keyboard(), pos2(420.0, 80.0), 36, None), Some(35));
+        assert_eq!(key_at(keyboard(), pos2(422.0, 80.0), 36, None), Some(35));
+    }
+
+    #[test]
+    fn outside_the_rect_is_none() {
+        assert_eq!(key_at(keyboard(), pos2(-50.0, 50.0), 36, None), None);
+        assert_eq!(key_at(keyboard(), pos2(470.0, 50.0), 36, None), None);
+        assert_eq!(key_at(keyboard(), pos2(200.0, -10.0), 36, None), None);
+        assert_eq!(key_at(keyboard(), pos2(200.0, 110.0), 36, Some(20)), None);
+        assert_eq!(key_at(Rect::NOTHING, pos2(0.0, 0.0), 36, None), None);
+        assert_eq!(key_at(keyboard(), pos2(200.0, 50.0), 0, None), None);
+    }
+
+    #[test]
+    fn last_keys_are_reachable() {
+        // Top B, and the black A# just below it
+        assert_eq!(key_at(keyboard(), pos2(419.9, 80.0), 36, None), Some(35));
+        assert_eq!(key_at(keyboard(), pos2(400.0, 30.0), 36, None), Some(34));
+    }
+
+    #[test]
+    fn hysteresis_holds_the_previous_key_near_a_boundary() {
+        // C and D meet at x = 20 below the black keys
+        assert_eq!(key_at(keyboard(), pos2(21.0, 80.0), 36, None), Some(2));
+        assert_eq!(key_at(keyboard(), pos2(21.0, 80.0), 36, Some(0)), Some(0));
+        assert_eq!(key_at(keyboard(), pos2(19.0, 80.0), 36, Some(2)), Some(2));
+        // Past the margin the new key wins
+        assert_eq!(key_at(keyboard(), pos2(25.0, 80.0), 36, Some(0)), Some(2));
+        // A distant previous key is not held
+        assert_eq!(key_at(keyboard(), pos2(21.0, 80.0), 36, Some(30)), Some(2));
+    }
+}
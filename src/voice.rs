@@ -1,12 +1,54 @@
 use crate::oscillator::Oscillator;
 use crate::envelope::Envelope;
-use crate::filter::LadderFilter;
+use crate::comb::CombFilter;
+use crate::filter::{FilterType, LadderFilter};
+use crate::lfo::Lfo;
+use crate::modulation::{ModMatrix, ModSource, ModSources};
+use crate::wavefolder::Wavefolder;
 
 pub struct Voice {
     pub oscillator: Oscillator,
     pub envelope: Envelope,
     pub filter: LadderFilter,
+    pub comb: CombFilter,
+    /// Which of the two filters the voice plays through
+    filter_type: FilterType,
+    /// Shapes the oscillator before the envelope and filter
+    pub wavefolder: Wavefolder,
+    /// Sweeps the filter cutoff, restarted with every note
+    pub cutoff_lfo: Lfo,
+    /// How far the LFO moves the cutoff either way, in octaves
+    cutoff_lfo_depth: f32,
+    /// The voice's own modulation LFO (LFO 2), restarted with every note
+    pub lfo: Lfo,
+    /// Modulation source values as this voice sees them: the LFOs as of the
+    /// last sample rendered, its note's velocity and random value, and the
+    /// part's controllers
+    pub sources: ModSources,
+    /// Pan offset from the modulation matrix, as of the last sample rendered
+    mod_pan: f32,
     pub note: Option<u8>,
+    /// The note most recently triggered, kept through the release stage
+    pub last_note: Option<u8>,
+    /// Trigger order stamp from the voice manager, higher is newer
+    pub triggered_at: u64,
+    /// Gain from the note-on velocity (0.0-1.0). A triggered voice gets it
+    /// through its envelope's peak, a gated one in the paraphonic mix
+    pub velocity: f32,
+    /// Position within a unison stack, 0 when unison is off
+    pub unison_index: usize,
+    /// Unison detune in cents
+    detune: f32,
+    /// Oscillator tuning from the octave, semitone and fine controls, in cents
+    tune: f32,
+    /// Offset of the current key from equal temperament, in cents
+    key_offset: f32,
+    /// -1.0 (left) to 1.0 (right)
+    pan: f32,
+    /// Random offsets drawn for the current note: pitch in cents, pan, and gain
+    humanize_detune: f32,
+    humanize_pan: f32,
+    humanize_gain: f32,
 }
 
 impl Voice {
@@ -15,34 +57,228 @@ impl Voice {
             oscillator: Oscillator::new(sample_rate, 440.0),
             envelope: Envelope::new(sample_rate),
             filter: LadderFilter::new(sample_rate),
+            comb: CombFilter::new(sample_rate),
+            filter_type: FilterType::Ladder,
+            wavefolder: Wavefolder::new(),
+            cutoff_lfo: Lfo::new(sample_rate),
+            cutoff_lfo_depth: 0.0,
+            lfo: Lfo::new(sample_rate),
+            sources: ModSources::default(),
+            mod_pan: 0.0,
             note: None,
+            last_note: None,
+            triggered_at: 0,
+            velocity: 1.0,
+            unison_index: 0,
+            detune: 0.0,
+            tune: 0.0,
+            key_offset: 0.0,
+            pan: 0.0,
+            humanize_detune: 0.0,
+            humanize_pan: 0.0,
+            humanize_gain: 1.0,
         }
     }
 
-    pub fn trigger(&mut self, note: u8) {
-        let frequency = Oscillator::note_to_frequency(note);
-        self.oscillator.set_frequency(frequency);
-        self.envelope.note_on();
+    pub fn trigger(&mut self, note: u8, velocity: u8) {
+        self.oscillator.set_frequency(self.note_frequency(note));
+        self.oscillator.restart_sample();
+        self.cutoff_lfo.reset();
+        self.lfo.note_on();
+        self.sources.velocity = velocity as f32 / 127.0;
+        self.comb.set_note(note);
+        self.envelope.note_on(velocity as f32 / 127.0);
         self.note = Some(note);
+        self.last_note = Some(note);
+        self.velocity = self.envelope.velocity_gain(velocity as f32 / 127.0);
+    }
+
+    /// Starts the oscillator on a note without its own envelope, for paraphonic
+    /// play where the voice manager's shared envelope shapes every note.
+    pub fn gate(&mut self, note: u8, velocity: u8) {
+        self.oscillator.set_frequency(self.note_frequency(note));
+        self.oscillator.restart_sample();
+        self.lfo.note_on();
+        self.sources.velocity = velocity as f32 / 127.0;
+        self.note = Some(note);
+        self.last_note = Some(note);
+        self.velocity = self.envelope.velocity_gain(velocity as f32 / 127.0);
+    }
+
+    /// Moves the voice to a new note without restarting its envelope, for legato.
+    pub fn set_note(&mut self, note: u8) {
+        self.oscillator.set_frequency(self.note_frequency(note));
+        self.comb.set_note(note);
+        self.note = Some(note);
+        self.last_note = Some(note);
+    }
+
+    /// Sets the voice's unison detune and pan, retuning it if a note is sounding.
+    pub fn set_unison(&mut self, detune: f32, pan: f32) {
+        self.detune = detune;
+        self.pan = pan.clamp(-1.0, 1.0);
+        if let Some(note) = self.last_note {
+            self.oscillator.set_frequency(self.note_frequency(note));
+        }
+    }
+
+    /// Sets the oscillator tuning in cents, retuning the voice if a note is sounding.
+    pub fn set_tune(&mut self, cents: f32) {
+        self.tune = cents;
+        if let Some(note) = self.last_note {
+            self.oscillator.set_frequency(self.note_frequency(note));
+        }
+    }
+
+    /// Sets the key's microtuning offset in cents, retuning the voice if a note is sounding.
+    pub fn set_key_offset(&mut self, cents: f32) {
+        self.key_offset = cents;
+        if let Some(note) = self.last_note {
+            self.oscillator.set_frequency(self.note_frequency(note));
+        }
+    }
+
+    /// Sets the random offsets for the next note, before it is triggered.
+    pub fn set_humanize(&mut self, cents: f32, pan: f32, gain: f32) {
+        self.humanize_detune = cents;
+        self.humanize_pan = pan;
+        self.humanize_gain = gain;
+    }
+
+    fn note_frequency(&self, note: u8) -> f32 {
+        let cents = self.detune + self.tune + self.key_offset + self.humanize_detune;
+        Oscillator::note_to_frequency(note) * 2.0_f32.powf(cents / 1200.0)
+    }
+
+    /// Balance-style pan gains, unity on both sides when centered.
+    pub fn pan_gains(&self) -> (f32, f32) {
+        let pan = (self.pan + self.humanize_pan + self.mod_pan).clamp(-1.0, 1.0);
+        ((1.0 - pan).min(1.0), (1.0 + pan).min(1.0))
     }
 
     pub fn release(&mut self) {
-        self.envelope.note_off();
+        // A gated paraphonic voice has no envelope of its own to release
+        if !self.envelope.is_idle() {
+            self.envelope.note_off();
+        }
         self.note = None;
     }
 
+    /// Whether the note has been released and the envelope is still fading out.
+    pub fn is_releasing(&self) -> bool {
+        self.note.is_none() && !self.envelope.is_idle()
+    }
+
     pub fn is_active(&self) -> bool {
         self.note.is_some() || !self.envelope.is_idle()
     }
 
-    pub fn render_next(&mut self) -> f32 {
-        let osc_sample = self.oscillator.next_sample();
+    /// Renders the next sample. `lfo1` is the part's shared LFO at this sample.
+    pub fn render_next(&mut self, matrix: &ModMatrix, lfo1: f32) -> f32 {
+        let (gain, cutoff) = self.modulate(matrix, lfo1);
+        let raw = self.render_pre_filter() * gain;
+        self.modulate_cutoff(cutoff);
+        self.apply_filter(raw)
+    }
+
+    /// Applies the modulation matrix for the next sample, setting the pitch,
+    /// pulse width and pan offsets. Returns the gain and cutoff offset in
+    /// octaves for the caller to apply.
+    fn modulate(&mut self, matrix: &ModMatrix, lfo1: f32) -> (f32, f32) {
+        if !matrix.is_active() {
+            return (1.0, 0.0);
+        }
+        self.sources.lfo1 = lfo1;
+        self.sources.lfo2 = if matrix.uses(ModSource::Lfo2) { self.lfo.next() } else { 0.0 };
+        let offsets = matrix.offsets(&self.sources);
+        self.oscillator.set_pitch_modulation(offsets.pitch);
+        self.oscillator.set_width_modulation(offsets.pulse_width);
+        self.mod_pan = offsets.pan;
+        ((1.0 + offsets.level).max(0.0), offsets.cutoff)
+    }
+
+    /// Drops any offsets left by the modulation matrix, for when its routings change.
+    pub fn clear_modulation(&mut self) {
+        self.oscillator.set_pitch_modulation(0.0);
+        self.oscillator.set_width_modulation(0.0);
+        self.mod_pan = 0.0;
+        // With the cutoff LFO running, its next sample replaces the offset anyway
+        if self.cutoff_lfo_depth == 0.0 {
+            self.filter.set_cutoff_modulation(0.0);
+            self.comb.set_modulation(0.0);
+        }
+    }
+
+    /// Moves the filter cutoff by the LFO and `octaves` from the modulation
+    /// matrix for the next sample.
+    fn modulate_cutoff(&mut self, octaves: f32) {
+        if self.cutoff_lfo_depth > 0.0 || octaves != 0.0 {
+            let octaves = octaves + if self.cutoff_lfo_depth > 0.0 { self.cutoff_lfo.next() * self.cutoff_lfo_depth } else { 0.0 };
+            match self.filter_type {
+                FilterType::Ladder => self.filter.set_cutoff_modulation(octaves),
+                FilterType::Comb => self.comb.set_modulation(octaves),
+            }
+        }
+    }
+
+    fn apply_filter(&mut self, input: f32) -> f32 {
+        match self.filter_type {
+            FilterType::Ladder => self.filter.process(input),
+            FilterType::Comb => self.comb.process(input),
+        }
+    }
+
+    /// Oscillator through the wavefolder and envelope, before the filter
+    fn render_pre_filter(&mut self) -> f32 {
+        let osc_sample = self.wavefolder.process(self.oscillator.next_sample());
         let env_sample = self.envelope.next_sample();
-        self.filter.process(osc_sample * env_sample)
+        osc_sample * env_sample * self.humanize_gain
+    }
+
+    /// Adds the folded oscillator, scaled by velocity, to `output`. Paraphonic
+    /// voices are mixed like this ahead of the shared envelope and filter, so
+    /// cutoff and pan routings have nothing of the voice's own to move.
+    pub fn render_oscillator_block(&mut self, output: &mut [f32], matrix: &ModMatrix, lfo1: &[f32]) {
+        for (sample, &lfo1) in output.iter_mut().zip(lfo1) {
+            let (gain, _) = self.modulate(matrix, lfo1);
+            *sample += self.wavefolder.process(self.oscillator.next_sample()) * self.velocity * self.humanize_gain * gain;
+        }
+    }
+
+    /// Renders `output.len()` samples and adds them to `output`. `lfo1` holds
+    /// the part's shared LFO for each of them.
+    pub fn render_block(&mut self, output: &mut [f32], matrix: &ModMatrix, lfo1: &[f32]) {
+        for (sample, &lfo1) in output.iter_mut().zip(lfo1) {
+            *sample += self.render_next(matrix, lfo1);
+        }
+    }
+
+    /// Like `render_block`, also adding the unfiltered signal to `pre_filter`.
+    pub fn render_block_tapped(&mut self, output: &mut [f32], pre_filter: &mut [f32], matrix: &ModMatrix, lfo1: &[f32]) {
+        for ((sample, tap), &lfo1) in output.iter_mut().zip(pre_filter.iter_mut()).zip(lfo1) {
+            let (gain, cutoff) = self.modulate(matrix, lfo1);
+            let raw = self.render_pre_filter() * gain;
+            *tap += raw;
+            self.modulate_cutoff(cutoff);
+            *sample += self.apply_filter(raw);
+        }
     }
 
     pub fn set_filter_cutoff(&mut self, cutoff: f32) {
         self.filter.set_cutoff(cutoff);
+        self.comb.set_frequency(cutoff);
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        self.filter_type = filter_type;
+    }
+
+    pub fn set_cutoff_lfo_depth(&mut self, octaves: f32) {
+        self.cutoff_lfo_depth = octaves.max(0.0);
+        if self.cutoff_lfo_depth == 0.0 {
+            self.filter.set_cutoff_modulation(0.0);
+            self.comb.set_modulation(0.0);
+        }
     }
 
     pub fn set_filter_resonance(&mut self, resonance: f32) {
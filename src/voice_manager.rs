@@ -1,134 +1,1370 @@
+use crate::advanced::FilterTuning;
+use crate::envelope::{Envelope, EnvelopeCurve, EnvelopeStage, ReleaseMode, RetriggerMode};
+use crate::comb::CombFilter;
+use crate::filter::{FilterType, LadderFilter, Oversampling};
+use crate::key_tuning::KeyTuning;
+use crate::lfo::{Lfo, LfoShape, LfoTrigger};
+use crate::modulation::{ModDestination, ModMatrix, ModSource, ModSources};
 use crate::voice::Voice;
-use crate::reverb::Reverb;
-use crate::chorus::{Chorus, ChorusMode};
+use crate::oscillator::Waveform;
+use crate::sampler::SampleBuffer;
+use crate::tempo::NoteDivision;
+use crate::wavetable::Wavetable;
+use std::sync::Arc;
+use crate::multi_engine::MAX_BLOCK_SIZE;
+
+/// How far full aftertouch pressure opens the filter, in octaves above the cutoff
+const PRESSURE_CUTOFF_OCTAVES: f32 = 2.0;
+/// How far a fully pushed pitch bend wheel moves the pitch, in semitones
+const PITCH_BEND_RANGE: f32 = 2.0;
+/// Number of MIDI notes
+const NUM_KEYS: usize = 128;
+/// Largest number of voices one note can stack in unison
+pub const MAX_UNISON_VOICES: usize = 8;
+
+/// How notes are assigned to voices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoiceMode {
+    Poly,
+    /// One voice, last-note priority, envelope retriggered on every note
+    Mono,
+    /// One voice, last-note priority, overlapping notes change pitch without retriggering
+    Legato,
+    /// An oscillator per note, mixed into one shared envelope and filter like
+    /// a vintage string machine
+    Paraphonic,
+}
+
+impl VoiceMode {
+    pub const ALL: [VoiceMode; 4] = [VoiceMode::Poly, VoiceMode::Mono, VoiceMode::Legato, VoiceMode::Paraphonic];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VoiceMode::Poly => "Poly",
+            VoiceMode::Mono => "Mono",
+            VoiceMode::Legato => "Legato",
+            VoiceMode::Paraphonic => "Paraphonic",
+        }
+    }
+
+    /// Whether only one note sounds at a time, chosen by the note priority.
+    pub fn is_mono(self) -> bool {
+        matches!(self, VoiceMode::Mono | VoiceMode::Legato)
+    }
+}
+
+/// Where the oscillator cycle starts when a voice is triggered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseMode {
+    /// Keep running from wherever the oscillator was
+    Free,
+    /// Restart from the start phase, for a consistent attack
+    Reset,
+    /// Start from a random phase on every note
+    Random,
+}
+
+impl PhaseMode {
+    pub const ALL: [PhaseMode; 3] = [PhaseMode::Free, PhaseMode::Reset, PhaseMode::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PhaseMode::Free => "Free",
+            PhaseMode::Reset => "Reset",
+            PhaseMode::Random => "Random",
+        }
+    }
+}
+
+/// Which held key sounds in the mono modes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotePriority {
+    Last,
+    Low,
+    High,
+}
+
+impl NotePriority {
+    pub const ALL: [NotePriority; 3] = [NotePriority::Last, NotePriority::Low, NotePriority::High];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotePriority::Last => "Last",
+            NotePriority::Low => "Low",
+            NotePriority::High => "High",
+        }
+    }
+}
+
+/// How unison voices are placed in the stereo field.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnisonStereo {
+    /// Voices alternate sides, with free-running phases
+    Alternating,
+    /// Voices are spread from left to right in order of detune and every copy
+    /// starts at phase zero, so a new note folds down to mono without loss.
+    /// The detune still drifts the copies apart, so this holds for a fraction
+    /// of the beat between the outer copies. After that the fold averages out
+    /// to what the pan spread costs, about three quarters of the energy for
+    /// four copies at full spread against half with alternating sides.
+    PhaseLocked,
+}
+
+impl UnisonStereo {
+    pub const ALL: [UnisonStereo; 2] = [UnisonStereo::Alternating, UnisonStereo::PhaseLocked];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UnisonStereo::Alternating => "Alternating",
+            UnisonStereo::PhaseLocked => "Phase Locked",
+        }
+    }
+}
+
+/// Which voice to take over when a note arrives and every voice is busy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StealPolicy {
+    Oldest,
+    /// The voice with the lowest envelope level
+    Quietest,
+    LowestNote,
+    HighestNote,
+    /// Retrigger a voice already playing or releasing the same note, otherwise the oldest
+    SameNoteFirst,
+}
+
+impl StealPolicy {
+    pub const ALL: [StealPolicy; 5] = [
+        StealPolicy::Oldest,
+        StealPolicy::Quietest,
+        StealPolicy::LowestNote,
+        StealPolicy::HighestNote,
+        StealPolicy::SameNoteFirst,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StealPolicy::Oldest => "Oldest",
+            StealPolicy::Quietest => "Quietest",
+            StealPolicy::LowestNote => "Lowest Note",
+            StealPolicy::HighestNote => "Highest Note",
+            StealPolicy::SameNoteFirst => "Same Note First",
+        }
+    }
+}
+
+/// What happens when a note arrives while the same note is still sounding.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameNotePolicy {
+    /// Keep the sounding voice and drop the repeated note-on, so duplicate
+    /// note-ons from a controller don't stack up voices
+    Ignore,
+    /// Restart the voice already playing the note
+    Retrigger,
+    /// Start a new voice and let the old one carry on
+    Stack,
+    /// Release the old voice and start a new one
+    Cut,
+}
+
+impl SameNotePolicy {
+    pub const ALL: [SameNotePolicy; 4] = [
+        SameNotePolicy::Ignore,
+        SameNotePolicy::Retrigger,
+        SameNotePolicy::Stack,
+        SameNotePolicy::Cut,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SameNotePolicy::Ignore => "Ignore",
+            SameNotePolicy::Retrigger => "Retrigger",
+            SameNotePolicy::Stack => "Stack",
+            SameNotePolicy::Cut => "Cut",
+        }
+    }
+}
 
 pub struct VoiceManager {
     pub voices: Vec<Voice>,
-    reverb: Reverb,
-    chorus: Chorus,
-    active_notes: std::collections::HashSet<u8>,
+    /// How many of `voices` new notes may use, set by the polyphony limit
+    voice_limit: usize,
+    /// Which MIDI keys are physically held, independent of the voices playing them
+    held_keys: [bool; NUM_KEYS],
+    /// Held keys in the order they were pressed, most recent last
+    key_order: Vec<u8>,
+    /// Latched keys ignore their note-off and are released by pressing them again
+    latch: bool,
+    /// Filter cutoff set by the patch, before pressure is applied
+    filter_cutoff: f32,
+    /// Channel aftertouch pressure (0.0-1.0)
+    pressure: f32,
+    steal_policy: StealPolicy,
+    same_note_policy: SameNotePolicy,
+    voice_mode: VoiceMode,
+    note_priority: NotePriority,
+    /// Velocity of the latest mono note, reused when falling back to an older held note
+    mono_velocity: u8,
+    /// Oscillator transposition in whole octaves and semitones, plus fine tuning in cents
+    octave: f32,
+    semitone: f32,
+    fine_tune: f32,
+    /// Per-key microtuning, applied while `key_tuning_enabled` is set
+    key_tuning: KeyTuning,
+    key_tuning_enabled: bool,
+    /// Voices started by each note, spread by `unison_detune` and `unison_spread`
+    unison_voices: usize,
+    /// Detune between the outermost unison voices and the note, in cents
+    unison_detune: f32,
+    /// How far unison voices are panned apart (0.0-1.0)
+    unison_spread: f32,
+    unison_stereo: UnisonStereo,
+    phase_mode: PhaseMode,
+    /// Largest random pitch (cents), pan and level (dB) offsets for each note
+    humanize_pitch: f32,
+    humanize_pan: f32,
+    humanize_level: f32,
+    /// xorshift32 state for random start phases and humanization
+    phase_rng: u32,
+    /// Number of notes triggered so far, used to stamp voices with their age
+    trigger_count: u64,
+    /// Envelope and filter shared by every note in paraphonic mode
+    para_envelope: Envelope,
+    para_filter: LadderFilter,
+    para_comb: CombFilter,
+    filter_type: FilterType,
+    /// Cutoff LFO of the shared filter, free-running across notes
+    para_cutoff_lfo: Lfo,
+    /// Depth of the cutoff LFOs in octaves, kept for the shared filter
+    cutoff_lfo_depth: f32,
+    /// The part's shared modulation LFO (LFO 1), free-running across notes
+    lfo1: Lfo,
+    /// LFO 1 for each sample of the block being rendered
+    lfo1_buffer: [f32; MAX_BLOCK_SIZE],
+    mod_matrix: ModMatrix,
+    /// Part-wide modulation sources, for the shared paraphonic filter and the
+    /// fixed pitch bend
+    mod_sources: ModSources,
+    /// Voices whose notes were released last and still ring through the
+    /// shared envelope's release stage
+    para_ringing: Vec<bool>,
+    /// Whether to also render the pre-filter signal, for the oscilloscope
+    pre_filter_tap: bool,
+    pre_filter: [f32; MAX_BLOCK_SIZE],
+    /// One voice's output, before it is panned into the stereo sum
+    voice_buffer: [f32; MAX_BLOCK_SIZE],
 }
 
 impl VoiceManager {
     pub fn new(sample_rate: f32, num_voices: usize) -> Self {
+        // Each paraphonic note is mixed in at its own velocity, so the shared
+        // envelope always peaks at full level
+        let mut para_envelope = Envelope::new(sample_rate);
+        para_envelope.set_velocity_to_level(0.0);
+        // LFO 1 is shared, so by default it runs on through new notes
+        let mut lfo1 = Lfo::new(sample_rate);
+        lfo1.set_trigger(LfoTrigger::Free);
         Self {
-            voices: (0..num_voices).map(|_| Voice::new(sample_rate)).collect(),
-            reverb: Reverb::new(sample_rate),
-            chorus: Chorus::new(sample_rate),
-            active_notes: std::collections::HashSet::new(),
+            voices: (0..num_voices)
+                .map(|i| {
+                    let mut voice = Voice::new(sample_rate);
+                    voice.oscillator.seed_drift(i as u32 + 1);
+                    voice.cutoff_lfo.seed(i as u32 + 1);
+                    voice.lfo.seed(i as u32 + 1 + num_voices as u32);
+                    voice
+                })
+                .collect(),
+            voice_limit: num_voices,
+            held_keys: [false; NUM_KEYS],
+            key_order: Vec::with_capacity(NUM_KEYS),
+            latch: false,
+            filter_cutoff: 15000.0,
+            pressure: 0.0,
+            steal_policy: StealPolicy::Oldest,
+            same_note_policy: SameNotePolicy::Ignore,
+            voice_mode: VoiceMode::Poly,
+            note_priority: NotePriority::Last,
+            mono_velocity: 0,
+            unison_voices: 1,
+            unison_detune: 0.0,
+            octave: 0.0,
+            semitone: 0.0,
+            fine_tune: 0.0,
+            key_tuning: KeyTuning::new(),
+            key_tuning_enabled: true,
+            unison_spread: 0.0,
+            unison_stereo: UnisonStereo::Alternating,
+            phase_mode: PhaseMode::Free,
+            humanize_pitch: 0.0,
+            humanize_pan: 0.0,
+            humanize_level: 0.0,
+            phase_rng: 0x2545_f491,
+            trigger_count: 0,
+            para_envelope,
+            para_filter: LadderFilter::new(sample_rate),
+            para_comb: CombFilter::new(sample_rate),
+            filter_type: FilterType::Ladder,
+            para_cutoff_lfo: Lfo::new(sample_rate),
+            cutoff_lfo_depth: 0.0,
+            lfo1,
+            lfo1_buffer: [0.0; MAX_BLOCK_SIZE],
+            mod_matrix: ModMatrix::new(),
+            mod_sources: ModSources::default(),
+            para_ringing: vec![false; num_voices],
+            pre_filter_tap: false,
+            pre_filter: [0.0; MAX_BLOCK_SIZE],
+            voice_buffer: [0.0; MAX_BLOCK_SIZE],
         }
     }
 
-    pub fn note_on(&mut self, note: u8) {
-        if self.active_notes.insert(note) {
-            if let Some(inactive_voice) = self.voices.iter_mut().find(|v| !v.is_active()) {
-                inactive_voice.trigger(note);
-            } else if let Some(oldest_voice) = self.find_oldest_voice() {
-                oldest_voice.trigger(note);
-            }
+    pub fn note_on(&mut self, note: u8, velocity: u8) {
+        if note as usize >= NUM_KEYS {
+            return;
+        }
+        if self.latch && self.held_keys[note as usize] {
+            self.release_note(note);
+            return;
+        }
+        let repeated = self.held_keys[note as usize];
+        if repeated && !self.voice_mode.is_mono() && self.same_note_policy == SameNotePolicy::Ignore {
+            return;
+        }
+        self.press_key(note);
+        // A free-running shared LFO only fades in again with each phrase
+        if self.lfo1.retriggers() || self.voices.iter().all(|v| !v.is_active()) {
+            self.lfo1.note_on();
         }
-    }
 
-    pub fn note_off(&mut self, note: u8) {
-        if self.active_notes.remove(&note) {
-            for voice in self.voices.iter_mut() {
+        if self.voice_mode.is_mono() {
+            self.mono_note_on(note, velocity);
+            return;
+        }
+
+        if self.voice_mode == VoiceMode::Paraphonic && self.voices.iter().all(|v| v.note.is_none()) {
+            // The first key of a phrase retriggers the shared envelope and cuts the old chord's tail
+            self.para_ringing.fill(false);
+            self.para_envelope.note_on(velocity as f32 / 127.0);
+        }
+        if self.voice_mode == VoiceMode::Paraphonic {
+            // The shared comb tracks the newest note
+            self.para_comb.set_note(note);
+        }
+
+        self.trigger_count += 1;
+        let stamp = self.trigger_count;
+
+        if self.same_note_policy == SameNotePolicy::Cut {
+            for voice in &mut self.voices {
                 if voice.note == Some(note) {
                     voice.release();
                 }
             }
         }
+
+        for unison_index in 0..self.unison_count() {
+            if let Some(index) = self.allocate_voice(note, stamp) {
+                self.start_voice(index, unison_index, note, velocity);
+                self.voices[index].triggered_at = stamp;
+            }
+        }
+    }
+
+    /// Picks a voice for one copy of `note`, never one already started for the
+    /// current note-on (stamped `stamp`).
+    fn allocate_voice(&self, note: u8, stamp: u64) -> Option<usize> {
+        let reuse_same_note = self.same_note_policy == SameNotePolicy::Retrigger
+            || self.steal_policy == StealPolicy::SameNoteFirst;
+        let same_note_voice = if reuse_same_note {
+            self.find_same_note_voice(note, stamp)
+        } else {
+            None
+        };
+        same_note_voice
+            .or_else(|| self.playable_voices().iter().position(|v| !v.is_active()))
+            .or_else(|| self.find_voice_to_steal(stamp))
+    }
+
+    fn start_voice(&mut self, index: usize, unison_index: usize, note: u8, velocity: u8) {
+        let (detune, pan) = self.unison_offset(unison_index);
+        // Phase-locked unison needs every copy to start together, whatever the phase mode
+        let phase_mode = if self.unison_stereo == UnisonStereo::PhaseLocked {
+            PhaseMode::Reset
+        } else {
+            self.phase_mode
+        };
+        let random_phase = if phase_mode == PhaseMode::Random { self.next_random_phase() } else { 0.0 };
+        let key_offset = self.key_offset(note);
+        let humanize_detune = self.next_random_offset() * self.humanize_pitch;
+        let humanize_pan = self.next_random_offset() * self.humanize_pan;
+        let humanize_gain = 10.0_f32.powf(self.next_random_offset() * self.humanize_level / 20.0);
+        let random = self.next_random_offset();
+
+        let voice = &mut self.voices[index];
+        voice.unison_index = unison_index;
+        voice.sources.random = random;
+        voice.set_humanize(humanize_detune, humanize_pan, humanize_gain);
+        voice.set_unison(detune, pan);
+        voice.set_key_offset(key_offset);
+        match phase_mode {
+            PhaseMode::Free => {}
+            PhaseMode::Reset => voice.oscillator.reset_phase(),
+            PhaseMode::Random => voice.oscillator.set_phase(random_phase),
+        }
+        if self.voice_mode == VoiceMode::Paraphonic {
+            voice.gate(note, velocity);
+        } else {
+            voice.trigger(note, velocity);
+        }
+    }
+
+    /// A random phase in 0.0-1.0 from xorshift32.
+    fn next_random_phase(&mut self) -> f32 {
+        self.phase_rng ^= self.phase_rng << 13;
+        self.phase_rng ^= self.phase_rng >> 17;
+        self.phase_rng ^= self.phase_rng << 5;
+        self.phase_rng as f32 / u32::MAX as f32
+    }
+
+    /// A random offset in -1.0-1.0, scaled by the humanize amounts.
+    fn next_random_offset(&mut self) -> f32 {
+        self.next_random_phase() * 2.0 - 1.0
+    }
+
+    pub fn set_humanize_pitch(&mut self, cents: f32) {
+        self.humanize_pitch = cents.max(0.0);
+    }
+
+    pub fn set_humanize_pan(&mut self, amount: f32) {
+        self.humanize_pan = amount.clamp(0.0, 1.0);
+    }
+
+    pub fn set_humanize_level(&mut self, db: f32) {
+        self.humanize_level = db.max(0.0);
+    }
+
+    pub fn set_phase_mode(&mut self, mode: PhaseMode) {
+        self.phase_mode = mode;
+    }
+
+    pub fn set_start_phase(&mut self, degrees: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_start_phase(degrees);
+        }
+    }
+
+    /// Number of voices each note starts, limited by the voices available.
+    fn unison_count(&self) -> usize {
+        self.unison_voices.min(self.voice_limit)
+    }
+
+    /// The voices new notes may be given, the first `voice_limit` of them.
+    fn playable_voices(&self) -> &[Voice] {
+        &self.voices[..self.voice_limit]
+    }
+
+    /// Caps how many voices notes may use. Voices beyond a lowered limit are
+    /// released and left to finish their tails.
+    pub fn set_voice_limit(&mut self, limit: usize) {
+        let limit = limit.clamp(1, self.voices.len());
+        if limit < self.voice_limit {
+            if self.voice_mode == VoiceMode::Paraphonic {
+                self.release_paraphonic(|index, _| index >= limit);
+            } else {
+                for voice in &mut self.voices[limit..] {
+                    if voice.note.is_some() {
+                        voice.release();
+                    }
+                }
+            }
+        }
+        self.voice_limit = limit;
+    }
+
+    /// Whether every voice notes may use is sounding, so the next note steals
+    /// one. Never true in the mono modes, which don't allocate voices.
+    pub fn is_saturated(&self) -> bool {
+        !self.voice_mode.is_mono() && self.playable_voices().iter().all(|v| v.is_active())
+    }
+
+    /// Where the envelope of the most recently triggered voice is, or the
+    /// shared envelope in paraphonic mode. `None` when nothing is sounding.
+    pub fn envelope_position(&self) -> Option<(EnvelopeStage, f32)> {
+        if self.voice_mode == VoiceMode::Paraphonic {
+            return self.para_envelope.position();
+        }
+        self.voices
+            .iter()
+            .filter(|v| v.is_active())
+            .max_by_key(|v| v.triggered_at)
+            .and_then(|v| v.envelope.position())
+    }
+
+    /// Detune in cents and pan for a voice's position in the unison stack.
+    /// Detune is spread evenly between the outer voices. Pan alternates sides,
+    /// or in phase-locked mode follows the detune so the stack is symmetric.
+    fn unison_offset(&self, unison_index: usize) -> (f32, f32) {
+        let count = self.unison_count();
+        if count < 2 {
+            return (0.0, 0.0);
+        }
+        let position = unison_index as f32 / (count - 1) as f32 * 2.0 - 1.0;
+        let pan = match self.unison_stereo {
+            UnisonStereo::Alternating => if unison_index.is_multiple_of(2) { -1.0 } else { 1.0 },
+            UnisonStereo::PhaseLocked => position,
+        };
+        (position * self.unison_detune, pan * self.unison_spread)
+    }
+
+    fn press_key(&mut self, note: u8) {
+        self.held_keys[note as usize] = true;
+        self.key_order.retain(|&n| n != note);
+        self.key_order.push(note);
+    }
+
+    /// Marks a key as released, returning whether it was held.
+    fn release_key(&mut self, note: u8) -> bool {
+        let was_held = std::mem::replace(&mut self.held_keys[note as usize], false);
+        self.key_order.retain(|&n| n != note);
+        was_held
+    }
+
+    fn mono_note_on(&mut self, note: u8, velocity: u8) {
+        self.mono_velocity = velocity;
+
+        // A new key that doesn't win priority leaves the sounding note alone
+        let sounding = self.voices.first().and_then(|v| v.note);
+        match self.priority_note() {
+            Some(target) if target != note && sounding == Some(target) => {}
+            Some(target) => self.play_mono(target),
+            None => {}
+        }
+    }
+
+    fn mono_note_off(&mut self, note: u8) {
+        // Releasing a key that isn't sounding leaves the current note alone
+        if self.voices.first().is_none_or(|v| v.note != Some(note)) {
+            return;
+        }
+        // Return to whichever key still held has priority
+        match self.priority_note() {
+            Some(target) => self.play_mono(target),
+            None => {
+                for unison_index in 0..self.unison_count() {
+                    self.voices[unison_index].release();
+                }
+            }
+        }
+    }
+
+    /// The held key that should sound under the note priority.
+    fn priority_note(&self) -> Option<u8> {
+        match self.note_priority {
+            NotePriority::Last => self.key_order.last().copied(),
+            NotePriority::Low => self.key_order.iter().min().copied(),
+            NotePriority::High => self.key_order.iter().max().copied(),
+        }
+    }
+
+    /// Plays `note` on the first voices, one per unison copy. Legato glides
+    /// the pitch of a sounding note, otherwise the envelope restarts.
+    fn play_mono(&mut self, note: u8) {
+        let count = self.unison_count();
+        let legato = self.voice_mode == VoiceMode::Legato && self.voices[..count].iter().any(|v| v.note.is_some());
+        for unison_index in 0..count {
+            if legato {
+                let key_offset = self.key_offset(note);
+                self.voices[unison_index].set_key_offset(key_offset);
+                self.voices[unison_index].set_note(note);
+            } else {
+                self.start_voice(unison_index, unison_index, note, self.mono_velocity);
+            }
+        }
+    }
+
+    pub fn set_note_priority(&mut self, priority: NotePriority) {
+        self.note_priority = priority;
+    }
+
+    /// Switches between polyphonic, mono and paraphonic play, releasing
+    /// whatever is sounding.
+    pub fn set_voice_mode(&mut self, mode: VoiceMode) {
+        if mode == self.voice_mode {
+            return;
+        }
+        let paraphonic_changed = (mode == VoiceMode::Paraphonic) != (self.voice_mode == VoiceMode::Paraphonic);
+        self.voice_mode = mode;
+        self.release_all();
+        if paraphonic_changed {
+            // The voice envelopes and the shared one are only advanced by their own
+            // topology, so tails from the other one would never finish
+            for voice in &mut self.voices {
+                voice.envelope.reset();
+            }
+            self.para_envelope.reset();
+            self.para_ringing.fill(false);
+        }
+    }
+
+    /// Sets how many voices each note stacks. Sounding notes are released,
+    /// since their voices were allocated for the previous count.
+    pub fn set_unison_voices(&mut self, count: usize) {
+        let count = count.clamp(1, MAX_UNISON_VOICES);
+        if count == self.unison_voices {
+            return;
+        }
+        self.unison_voices = count;
+        self.release_all();
+    }
+
+    pub fn set_octave(&mut self, octave: f32) {
+        self.octave = octave.round().clamp(-3.0, 3.0);
+        self.update_tune();
+    }
+
+    pub fn set_semitone(&mut self, semitone: f32) {
+        self.semitone = semitone.round().clamp(-12.0, 12.0);
+        self.update_tune();
+    }
+
+    pub fn set_fine_tune(&mut self, cents: f32) {
+        self.fine_tune = cents.clamp(-100.0, 100.0);
+        self.update_tune();
+    }
+
+    /// Replaces the whole key tuning table, retuning sounding voices.
+    pub fn set_key_tuning(&mut self, tuning: &KeyTuning) {
+        self.key_tuning = *tuning;
+        self.update_key_offsets();
+    }
+
+    /// Nudges one key of the table, retuning voices playing it.
+    pub fn set_key_offset(&mut self, note: u8, cents: f32) {
+        self.key_tuning.set_offset(note, cents);
+        self.update_key_offsets();
+    }
+
+    /// Switches the part between the key tuning table and equal temperament.
+    pub fn set_key_tuning_enabled(&mut self, enabled: bool) {
+        self.key_tuning_enabled = enabled;
+        self.update_key_offsets();
+    }
+
+    /// Offset in cents a voice playing `note` gets from the key tuning.
+    fn key_offset(&self, note: u8) -> f32 {
+        if self.key_tuning_enabled {
+            self.key_tuning.offset(note)
+        } else {
+            0.0
+        }
+    }
+
+    fn update_key_offsets(&mut self) {
+        for index in 0..self.voices.len() {
+            if let Some(note) = self.voices[index].last_note {
+                let key_offset = self.key_offset(note);
+                self.voices[index].set_key_offset(key_offset);
+            }
+        }
+    }
+
+    /// Retunes every voice, sounding ones included, to the combined tuning controls.
+    fn update_tune(&mut self) {
+        let bend = self.mod_sources.pitch_bend * PITCH_BEND_RANGE * 100.0;
+        let cents = self.octave * 1200.0 + self.semitone * 100.0 + self.fine_tune + bend;
+        for voice in &mut self.voices {
+            voice.set_tune(cents);
+        }
+    }
+
+    pub fn set_unison_detune(&mut self, cents: f32) {
+        self.unison_detune = cents;
+        self.update_unison();
+    }
+
+    pub fn set_unison_spread(&mut self, spread: f32) {
+        self.unison_spread = spread.clamp(0.0, 1.0);
+        self.update_unison();
+    }
+
+    pub fn set_unison_stereo(&mut self, mode: UnisonStereo) {
+        self.unison_stereo = mode;
+        self.update_unison();
+    }
+
+    /// Applies the current detune and spread to every voice, including sounding ones.
+    fn update_unison(&mut self) {
+        for index in 0..self.voices.len() {
+            let (detune, pan) = self.unison_offset(self.voices[index].unison_index);
+            self.voices[index].set_unison(detune, pan);
+        }
+    }
+
+    /// Turns latch mode on or off. Turning it off releases the latched notes.
+    pub fn set_latch(&mut self, enabled: bool) {
+        if self.latch && !enabled {
+            self.all_notes_off();
+        }
+        self.latch = enabled;
+    }
+
+    /// Releases every voice and forgets all held and latched keys.
+    pub fn all_notes_off(&mut self) {
+        self.release_all();
+        self.held_keys = [false; NUM_KEYS];
+        self.key_order.clear();
+    }
+
+    /// Releases every sounding voice. Keys stay marked as held until their note-off.
+    fn release_all(&mut self) {
+        if self.voice_mode == VoiceMode::Paraphonic {
+            self.release_paraphonic(|_, _| true);
+            return;
+        }
+        for voice in &mut self.voices {
+            if voice.note.is_some() {
+                voice.release();
+            }
+        }
+    }
+
+    pub fn set_pre_filter_tap(&mut self, enabled: bool) {
+        self.pre_filter_tap = enabled;
+        self.pre_filter = [0.0; MAX_BLOCK_SIZE];
+    }
+
+    /// The unfiltered voice sum of the last block, when the pre-filter tap is enabled.
+    /// Only the final MAX_BLOCK_SIZE chunk of a longer block is kept.
+    pub fn pre_filter(&self, len: usize) -> &[f32] {
+        &self.pre_filter[..len]
+    }
+
+    pub fn set_steal_policy(&mut self, policy: StealPolicy) {
+        self.steal_policy = policy;
+    }
+
+    pub fn set_same_note_policy(&mut self, policy: SameNotePolicy) {
+        self.same_note_policy = policy;
+    }
+
+    /// Index of the voice to take over under the steal policy, skipping voices
+    /// already started for the note-on stamped `stamp`.
+    fn find_voice_to_steal(&self, stamp: u64) -> Option<usize> {
+        let candidates = self.playable_voices()
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.triggered_at != stamp);
+        let voice = match self.steal_policy {
+            StealPolicy::Oldest | StealPolicy::SameNoteFirst => {
+                // Prefer voices that are already releasing so held notes are
+                // only stolen as a last resort
+                candidates.min_by_key(|(_, v)| (!v.is_releasing(), v.triggered_at))
+            }
+            StealPolicy::Quietest => candidates
+                .min_by(|(_, a), (_, b)| a.envelope.level().total_cmp(&b.envelope.level())),
+            StealPolicy::LowestNote => candidates.min_by_key(|(_, v)| v.last_note),
+            StealPolicy::HighestNote => candidates.max_by_key(|(_, v)| v.last_note),
+        };
+        voice.map(|(index, _)| index)
+    }
+
+    fn find_same_note_voice(&self, note: u8, stamp: u64) -> Option<usize> {
+        self.playable_voices()
+            .iter()
+            .position(|v| v.triggered_at != stamp && v.is_active() && v.last_note == Some(note))
+    }
+
+    pub fn note_off(&mut self, note: u8) {
+        if !self.latch {
+            self.release_note(note);
+        }
+    }
+
+    fn release_note(&mut self, note: u8) {
+        if note as usize >= NUM_KEYS || !self.release_key(note) {
+            return;
+        }
+
+        if self.voice_mode.is_mono() {
+            self.mono_note_off(note);
+            return;
+        }
+
+        if self.voice_mode == VoiceMode::Paraphonic {
+            self.release_paraphonic(|_, v| v.note == Some(note));
+            return;
+        }
+
+        for voice in self.voices.iter_mut() {
+            if voice.note == Some(note) {
+                voice.release();
+            }
+        }
+    }
+
+    /// Releases the sounding paraphonic voices picked by `released`, which is
+    /// given each voice's index. Notes let
+    /// go while others are held stop at once, like a key lifted on an organ,
+    /// while the last ones keep ringing through the shared envelope's release.
+    fn release_paraphonic(&mut self, released: impl Fn(usize, &Voice) -> bool) {
+        let last = self.voices.iter().enumerate().all(|(index, v)| v.note.is_none() || released(index, v));
+        for (index, (voice, ringing)) in self.voices.iter_mut().zip(self.para_ringing.iter_mut()).enumerate() {
+            if voice.note.is_some() && released(index, voice) {
+                voice.release();
+                *ringing = last;
+            }
+        }
+        if last && !self.para_envelope.is_idle() {
+            self.para_envelope.note_off();
+        }
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_volume(volume);
+        }
+    }
+
+    pub fn set_drift(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_drift(amount);
+        }
+    }
+
+    pub fn set_pulse_width(&mut self, width: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_pulse_width(width);
+        }
+    }
+
+    pub fn set_pwm_amount(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_pwm_amount(amount);
+        }
+    }
+
+    pub fn set_pwm_rate(&mut self, rate: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_pwm_rate(rate);
+        }
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_waveform(waveform);
+        }
+    }
+
+    pub fn set_wavetable(&mut self, wavetable: Arc<Wavetable>) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_wavetable(Some(Arc::clone(&wavetable)));
+        }
+    }
+
+    pub fn set_wave_position(&mut self, position: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_wave_position(position);
+        }
+    }
+
+    pub fn set_supersaw_detune(&mut self, detune: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_supersaw_detune(detune);
+        }
+    }
+
+    pub fn set_supersaw_mix(&mut self, mix: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_supersaw_mix(mix);
+        }
+    }
+
+    pub fn set_sample(&mut self, sample: Arc<SampleBuffer>) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_sample(Some(Arc::clone(&sample)));
+        }
+    }
+
+    pub fn set_root_note(&mut self, note: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_root_note(note);
+        }
+    }
+
+    pub fn set_sample_start(&mut self, start: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_sample_start(start);
+        }
+    }
+
+    pub fn set_loop_start(&mut self, start: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_loop_start(start);
+        }
+    }
+
+    pub fn set_loop_end(&mut self, end: f32) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_loop_end(end);
+        }
+    }
+
+    pub fn set_sample_loop(&mut self, enabled: bool) {
+        for voice in &mut self.voices {
+            voice.oscillator.set_sample_loop(enabled);
+        }
+    }
+
+    pub fn set_fold_amount(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.wavefolder.set_amount(amount);
+        }
+    }
+
+    pub fn set_fold_symmetry(&mut self, symmetry: f32) {
+        for voice in &mut self.voices {
+            voice.wavefolder.set_symmetry(symmetry);
+        }
+    }
+
+    pub fn set_envelope_delay(&mut self, delay: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_delay(delay);
+        }
+        self.para_envelope.set_delay(delay);
+    }
+
+    pub fn set_hold(&mut self, hold: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_hold(hold);
+        }
+        self.para_envelope.set_hold(hold);
+    }
+
+    pub fn set_attack(&mut self, attack: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_attack(attack);
+        }
+        self.para_envelope.set_attack(attack);
+    }
+
+    pub fn set_decay(&mut self, decay: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_decay(decay);
+        }
+        self.para_envelope.set_decay(decay);
+    }
+
+    pub fn set_sustain(&mut self, sustain: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_sustain(sustain);
+        }
+        self.para_envelope.set_sustain(sustain);
+    }
+
+    pub fn set_release(&mut self, release: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_release(release);
+        }
+        self.para_envelope.set_release(release);
+    }
+
+    pub fn set_release_mode(&mut self, mode: ReleaseMode) {
+        for voice in &mut self.voices {
+            voice.envelope.set_release_mode(mode);
+        }
+        self.para_envelope.set_release_mode(mode);
     }
 
-    fn find_oldest_voice(&mut self) -> Option<&mut Voice> {
-        self.voices.iter_mut().min_by_key(|v| v.note)
+    pub fn set_retrigger_mode(&mut self, mode: RetriggerMode) {
+        for voice in &mut self.voices {
+            voice.envelope.set_retrigger_mode(mode);
+        }
+        self.para_envelope.set_retrigger_mode(mode);
+    }
+
+    pub fn set_velocity_to_level(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_velocity_to_level(amount);
+        }
+    }
+
+    pub fn set_velocity_to_attack(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.envelope.set_velocity_to_attack(amount);
+        }
+        self.para_envelope.set_velocity_to_attack(amount);
+    }
+
+    pub fn set_envelope_curve(&mut self, stage: EnvelopeStage, curve: EnvelopeCurve) {
+        for voice in &mut self.voices {
+            voice.envelope.set_curve(stage, curve);
+        }
+        self.para_envelope.set_curve(stage, curve);
     }
 
     pub fn set_filter_cutoff(&mut self, cutoff: f32) {
+        self.filter_cutoff = cutoff;
+        self.update_filter_cutoff();
+    }
+
+    /// Sets the aftertouch pressure, which opens the filter above the patch
+    /// cutoff and is the aftertouch modulation source.
+    pub fn set_pressure(&mut self, pressure: f32) {
+        self.pressure = pressure.clamp(0.0, 1.0);
+        self.update_filter_cutoff();
+        let pressure = self.pressure;
+        self.update_sources(|sources| sources.aftertouch = pressure);
+    }
+
+    /// Sets the mod wheel position (0.0-1.0).
+    pub fn set_mod_wheel(&mut self, value: f32) {
+        let value = value.clamp(0.0, 1.0);
+        self.update_sources(|sources| sources.mod_wheel = value);
+    }
+
+    /// Sets the pitch bend position (-1.0 to 1.0). Bend always moves the
+    /// pitch by up to `PITCH_BEND_RANGE`, and matrix routings add to that.
+    pub fn set_pitch_bend(&mut self, bend: f32) {
+        let bend = bend.clamp(-1.0, 1.0);
+        self.update_sources(|sources| sources.pitch_bend = bend);
+        self.update_tune();
+    }
+
+    /// Applies `update` to the modulation sources every voice sees, and the
+    /// shared filter's.
+    fn update_sources(&mut self, update: impl Fn(&mut ModSources)) {
+        for voice in &mut self.voices {
+            update(&mut voice.sources);
+        }
+        update(&mut self.mod_sources);
+    }
+
+    fn update_filter_cutoff(&mut self) {
+        let cutoff = self.filter_cutoff * 2.0_f32.powf(self.pressure * PRESSURE_CUTOFF_OCTAVES);
         for voice in &mut self.voices {
             voice.set_filter_cutoff(cutoff);
         }
+        self.para_filter.set_cutoff(cutoff);
+        self.para_comb.set_frequency(cutoff);
+    }
+
+    pub fn set_filter_type(&mut self, filter_type: FilterType) {
+        for voice in &mut self.voices {
+            voice.set_filter_type(filter_type);
+        }
+        self.filter_type = filter_type;
+    }
+
+    pub fn set_comb_feedback(&mut self, feedback: f32) {
+        for voice in &mut self.voices {
+            voice.comb.set_feedback(feedback);
+        }
+        self.para_comb.set_feedback(feedback);
+    }
+
+    pub fn set_comb_key_tracking(&mut self, amount: f32) {
+        for voice in &mut self.voices {
+            voice.comb.set_key_tracking(amount);
+        }
+        self.para_comb.set_key_tracking(amount);
+    }
+
+    pub fn set_cutoff_lfo_rate(&mut self, rate: f32) {
+        for voice in &mut self.voices {
+            voice.cutoff_lfo.set_rate(rate);
+        }
+        self.para_cutoff_lfo.set_rate(rate);
+    }
+
+    pub fn set_cutoff_lfo_depth(&mut self, octaves: f32) {
+        for voice in &mut self.voices {
+            voice.set_cutoff_lfo_depth(octaves);
+        }
+        self.cutoff_lfo_depth = octaves.max(0.0);
+        if self.cutoff_lfo_depth == 0.0 {
+            self.para_filter.set_cutoff_modulation(0.0);
+            self.para_comb.set_modulation(0.0);
+        }
+    }
+
+    pub fn set_cutoff_lfo_shape(&mut self, shape: LfoShape) {
+        for voice in &mut self.voices {
+            voice.cutoff_lfo.set_shape(shape);
+        }
+        self.para_cutoff_lfo.set_shape(shape);
+    }
+
+    /// Applies `update` to LFO 1, or to every voice's LFO 2.
+    fn update_lfo(&mut self, source: ModSource, update: impl Fn(&mut Lfo)) {
+        match source {
+            ModSource::Lfo1 => update(&mut self.lfo1),
+            ModSource::Lfo2 => {
+                for voice in &mut self.voices {
+                    update(&mut voice.lfo);
+                }
+            }
+            // Performance controls have no settings
+            ModSource::Velocity | ModSource::ModWheel | ModSource::Aftertouch | ModSource::PitchBend | ModSource::Random => {}
+        }
+    }
+
+    pub fn set_lfo_rate(&mut self, source: ModSource, rate: f32) {
+        self.update_lfo(source, |lfo| lfo.set_rate(rate));
+    }
+
+    pub fn set_lfo_depth(&mut self, source: ModSource, depth: f32) {
+        self.update_lfo(source, |lfo| lfo.set_depth(depth));
+    }
+
+    pub fn set_lfo_phase(&mut self, source: ModSource, degrees: f32) {
+        self.update_lfo(source, |lfo| lfo.set_phase(degrees));
+    }
+
+    pub fn set_lfo_fade_in(&mut self, source: ModSource, seconds: f32) {
+        self.update_lfo(source, |lfo| lfo.set_fade_in(seconds));
+    }
+
+    pub fn set_lfo_shape(&mut self, source: ModSource, shape: LfoShape) {
+        self.update_lfo(source, |lfo| lfo.set_shape(shape));
+    }
+
+    pub fn set_lfo_trigger(&mut self, source: ModSource, trigger: LfoTrigger) {
+        self.update_lfo(source, |lfo| lfo.set_trigger(trigger));
+    }
+
+    pub fn set_lfo_sync(&mut self, source: ModSource, sync: Option<NoteDivision>) {
+        self.update_lfo(source, |lfo| lfo.set_sync(sync));
+    }
+
+    /// Sets the tempo both LFOs follow when synced.
+    pub fn set_tempo(&mut self, bpm: f32) {
+        for source in ModSource::LFOS {
+            self.update_lfo(source, |lfo| lfo.set_tempo(bpm));
+        }
+    }
+
+    pub fn set_mod_route(&mut self, slot: usize, source: ModSource, destination: ModDestination) {
+        self.mod_matrix.set_route(slot, source, destination);
+        self.clear_modulation();
+    }
+
+    pub fn set_mod_amount(&mut self, slot: usize, amount: f32) {
+        self.mod_matrix.set_amount(slot, amount);
+        if !self.mod_matrix.is_active() {
+            self.clear_modulation();
+        }
+    }
+
+    /// Drops offsets from routings that no longer exist. Those that do are
+    /// applied again from the next sample.
+    fn clear_modulation(&mut self) {
+        for voice in &mut self.voices {
+            voice.clear_modulation();
+        }
+        if self.cutoff_lfo_depth == 0.0 {
+            self.para_filter.set_cutoff_modulation(0.0);
+            self.para_comb.set_modulation(0.0);
+        }
     }
 
     pub fn set_filter_resonance(&mut self, resonance: f32) {
         for voice in &mut self.voices {
             voice.set_filter_resonance(resonance);
         }
+        self.para_filter.set_resonance(resonance);
     }
 
     pub fn set_filter_drive(&mut self, drive: f32) {
         for voice in &mut self.voices {
             voice.filter.set_drive(drive);
         }
+        self.para_filter.set_drive(drive);
     }
 
     pub fn set_filter_saturation(&mut self, saturation: f32) {
         for voice in &mut self.voices {
             voice.filter.set_saturation(saturation);
         }
+        self.para_filter.set_saturation(saturation);
     }
 
+    pub fn set_filter_auto_gain(&mut self, enabled: bool) {
+        for voice in &mut self.voices {
+            voice.filter.set_auto_gain(enabled);
+        }
+        self.para_filter.set_auto_gain(enabled);
+    }
 
-    pub fn render_next(&mut self) -> (f32, f32) {
-        let mut left_output = 0.0;
-        let mut right_output = 0.0;
+    pub fn set_filter_oversampling(&mut self, oversampling: Oversampling) {
+        for voice in &mut self.voices {
+            voice.filter.set_oversampling(oversampling);
+        }
+        self.para_filter.set_oversampling(oversampling);
+    }
+
+    pub fn set_filter_tuning(&mut self, tuning: &FilterTuning) {
+        for voice in &mut self.voices {
+            voice.filter.set_tuning(tuning);
+        }
+        self.para_filter.set_tuning(tuning);
+    }
+
+    /// Renders a block of the summed voices into `left` and `right`, which must
+    /// have the same length. Voices are rendered a chunk of up to MAX_BLOCK_SIZE
+    /// frames at a time, and the set of active voices is taken at the start of each chunk.
+    pub fn render_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        for (left, right) in left.chunks_mut(MAX_BLOCK_SIZE).zip(right.chunks_mut(MAX_BLOCK_SIZE)) {
+            self.render_chunk(left, right);
+        }
+    }
+
+    fn render_chunk(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let len = left.len();
+        if self.mod_matrix.uses(ModSource::Lfo1) {
+            self.lfo1_buffer[..len].fill_with(|| self.lfo1.next());
+        } else {
+            self.lfo1_buffer[..len].fill(0.0);
+        }
+        if self.voice_mode == VoiceMode::Paraphonic {
+            self.render_paraphonic_chunk(left, right);
+            return;
+        }
+        left.fill(0.0);
+        right.fill(0.0);
+        let pre_filter = &mut self.pre_filter[..left.len()];
+        if self.pre_filter_tap {
+            pre_filter.fill(0.0);
+        }
+        let voice_buffer = &mut self.voice_buffer[..len];
+        let lfo1 = &self.lfo1_buffer[..len];
 
         let mut active_voices = 0;
         for voice in &mut self.voices {
             if voice.is_active() {
-                let voice_output = voice.render_next();
-                left_output += voice_output;
-                right_output += voice_output;
+                voice_buffer.fill(0.0);
+                if self.pre_filter_tap {
+                    voice.render_block_tapped(voice_buffer, pre_filter, &self.mod_matrix, lfo1);
+                } else {
+                    voice.render_block(voice_buffer, &self.mod_matrix, lfo1);
+                }
+
+                let (gain_left, gain_right) = voice.pan_gains();
+                for ((l, r), sample) in left.iter_mut().zip(right.iter_mut()).zip(voice_buffer.iter()) {
+                    *l += sample * gain_left;
+                    *r += sample * gain_right;
+                }
                 active_voices += 1;
             }
         }
-    
+
         if active_voices > 0 {
             let normalization_factor = 1.0 / (active_voices as f32).sqrt();
-            left_output *= normalization_factor;
-            right_output *= normalization_factor;
+            for sample in left.iter_mut().chain(right.iter_mut()) {
+                *sample *= normalization_factor;
+            }
+            if self.pre_filter_tap {
+                for sample in pre_filter.iter_mut() {
+                    *sample *= normalization_factor;
+                }
+            }
         }
-    
-        // Apply reverb
-        let (reverb_left, reverb_right) = self.reverb.process(left_output, right_output);
-
-        // Mix dry and reverb signals
-        let wet_amount = self.reverb.get_wet();
-
-
+    }
 
-        let left = left_output * (1.0 - wet_amount) + reverb_left * wet_amount;
-        let right = right_output * (1.0 - wet_amount) + reverb_right * wet_amount;
+    /// Paraphonic rendering: the oscillators of every held note are mixed first,
+    /// then shaped by the one shared envelope and filter. The result is mono,
+    /// so unison spread has no effect here.
+    fn render_paraphonic_chunk(&mut self, left: &mut [f32], right: &mut [f32]) {
+        let len = left.len();
+        let mix = &mut self.voice_buffer[..len];
+        mix.fill(0.0);
 
-        // Apply chorus to the reverb output
-        let (chorus_left, chorus_right) = self.chorus.process(left, right);
+        let mut sounding = 0;
+        for (voice, &ringing) in self.voices.iter_mut().zip(self.para_ringing.iter()) {
+            if voice.note.is_some() || ringing {
+                voice.render_oscillator_block(mix, &self.mod_matrix, &self.lfo1_buffer[..len]);
+                sounding += 1;
+            }
+        }
+        let normalization_factor = if sounding > 0 { 1.0 / (sounding as f32).sqrt() } else { 0.0 };
+        let modulate_cutoff = self.mod_matrix.targets(ModDestination::Cutoff);
 
-        // Mix reverb and chorus
-        let chorus_mix = 0.8;
-        let left = left * (1.0 - chorus_mix) + chorus_left * chorus_mix;
-        let right = right * (1.0 - chorus_mix) + chorus_right * chorus_mix;
+        for i in 0..len {
+            let raw = mix[i] * normalization_factor * self.para_envelope.next_sample();
+            if self.pre_filter_tap {
+                self.pre_filter[i] = raw;
+            }
+            // Only part-wide sources can move the shared filter, the per-voice
+            // ones have no single value
+            let routed = if modulate_cutoff {
+                self.mod_matrix.offsets(&ModSources { lfo1: self.lfo1_buffer[i], ..self.mod_sources }).cutoff
+            } else {
+                0.0
+            };
+            if self.cutoff_lfo_depth > 0.0 || modulate_cutoff {
+                let octaves = routed + if self.cutoff_lfo_depth > 0.0 { self.para_cutoff_lfo.next() * self.cutoff_lfo_depth } else { 0.0 };
+                self.para_filter.set_cutoff_modulation(octaves);
+                self.para_comb.set_modulation(octaves);
+            }
+            // The filter keeps running on silence so its resonance can ring out
+            let sample = match self.filter_type {
+                FilterType::Ladder => self.para_filter.process(raw),
+                FilterType::Comb => self.para_comb.process(raw),
+            };
+            left[i] = sample;
+            right[i] = sample;
+        }
 
-        (left, right)
+        if self.para_envelope.is_idle() {
+            self.para_ringing.fill(false);
+        }
     }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    const SAMPLE_RATE: f32 = 48000.0;
 
-    pub fn set_reverb_decay(&mut self, decay: f32) {
-        self.reverb.set_decay(decay.clamp(0.0, 0.99));
-    }
-
-    pub fn set_reverb_wet(&mut self, wet: f32) {
-        self.reverb.set_wet(wet.clamp(0.0, 1.0));
+    /// Share of the stereo energy a mono fold-down keeps: 1.0 when the
+    /// channels match, 0.0 when they cancel completely.
+    fn mono_fold(left: &[f32], right: &[f32]) -> f32 {
+        let mono: f32 = left.iter().zip(right).map(|(l, r)| ((l + r) * 0.5).powi(2)).sum();
+        let stereo: f32 = left.iter().zip(right).map(|(l, r)| (l * l + r * r) * 0.5).sum();
+        mono / stereo
     }
 
-    pub fn set_chorus_mode(&mut self, mode: ChorusMode) {
-        self.chorus.set_mode(mode);
+    fn render_chord(mode: UnisonStereo, length: usize) -> (Vec<f32>, Vec<f32>) {
+        let mut voices = VoiceManager::new(SAMPLE_RATE, 12);
+        voices.set_unison_voices(4);
+        voices.set_unison_spread(1.0);
+        voices.set_unison_detune(10.0);
+        voices.set_unison_stereo(mode);
+        for note in [60, 64, 67] {
+            voices.note_on(note, 100);
+        }
+        let mut left = vec![0.0; length];
+        let mut right = vec![0.0; length];
+        voices.render_block(&mut left, &mut right);
+        (left, right)
     }
 
-    pub fn set_chorus_rate(&mut self, rate: f32) {
-        self.chorus.set_rate(rate);
+    #[test]
+    fn phase_locked_unison_folds_to_mono_at_note_on() {
+        // 20 ms is a small part of the ~330 ms beat between the outer copies of C4
+        let (left, right) = render_chord(UnisonStereo::PhaseLocked, SAMPLE_RATE as usize / 50);
+        let fold = mono_fold(&left, &right);
+        assert!(fold > 0.95, "mono fold-down kept {:.3} of the energy", fold);
     }
 
-    pub fn set_chorus_depth(&mut self, depth: f32) {
-        self.chorus.set_depth(depth);
+    #[test]
+    fn phase_locked_unison_keeps_the_pan_law_share_once_drifted() {
+        let length = 2 * SAMPLE_RATE as usize;
+        let (left, right) = render_chord(UnisonStereo::PhaseLocked, length);
+        let locked = mono_fold(&left, &right);
+        let (left, right) = render_chord(UnisonStereo::Alternating, length);
+        let alternating = mono_fold(&left, &right);
+        assert!(locked > 0.7, "mono fold-down kept {:.3} of the energy", locked);
+        assert!(locked > alternating, "phase-locked {:.3} against alternating {:.3}", locked, alternating);
     }
-}
\ No newline at end of file
+}
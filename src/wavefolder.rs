@@ -0,0 +1,47 @@
+use std::f32::consts::FRAC_PI_2;
+
+/// Extra gain into the folder at full amount. Each step of about 2 adds
+/// another fold to a full-scale wave.
+const MAX_FOLD_GAIN: f32 = 7.0;
+
+/// West-Coast-style wavefolder. Instead of clipping, peaks that pass the
+/// threshold are reflected back down, which adds bright, shifting harmonics
+/// as the drive rises. A sine transfer curve keeps the folds rounded.
+pub struct Wavefolder {
+    /// How hard the signal is driven into the folds (0.0-1.0), off at 0
+    amount: f32,
+    /// Offset added before folding (-1.0-1.0), making the folds lopsided for
+    /// even harmonics
+    symmetry: f32,
+}
+
+impl Wavefolder {
+    pub fn new() -> Self {
+        Self {
+            amount: 0.0,
+            symmetry: 0.0,
+        }
+    }
+
+    pub fn set_amount(&mut self, amount: f32) {
+        self.amount = amount.clamp(0.0, 1.0);
+    }
+
+    pub fn set_symmetry(&mut self, symmetry: f32) {
+        self.symmetry = symmetry.clamp(-1.0, 1.0);
+    }
+
+    pub fn process(&self, input: f32) -> f32 {
+        if self.amount == 0.0 {
+            return input;
+        }
+        let gain = 1.0 + self.amount * MAX_FOLD_GAIN;
+        // The offset's own output is taken back out so silence stays at zero,
+        // and the result scaled back to full scale
+        let offset = (FRAC_PI_2 * gain * self.symmetry).sin();
+        let folded = ((FRAC_PI_2 * gain * (input + self.symmetry)).sin() - offset) / (1.0 + offset.abs());
+        // Faded in over the first quarter of the control, so it starts from the dry wave
+        let mix = (self.amount * 4.0).min(1.0);
+        input + (folded - input) * mix
+    }
+}
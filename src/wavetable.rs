@@ -0,0 +1,322 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, RustWaveError};
+
+/// Samples in one single-cycle frame of a wavetable.
+pub const FRAME_SIZE: usize = 2048;
+/// Band-limited copies kept of every frame, one per octave. Level 0 holds the
+/// frames as loaded and each level above keeps half the harmonics of the last,
+/// down to the fundamental alone.
+const MIP_LEVELS: usize = 11;
+/// Folder for user wavetables, relative to the working directory.
+pub const WAVETABLE_DIR: &str = "wavetables";
+/// Most frames extracted from one audio snippet
+const MAX_EXTRACTED_FRAMES: usize = 64;
+/// Pitch range searched when extracting cycles, in Hz
+const MIN_PITCH: f32 = 30.0;
+const MAX_PITCH: f32 = 2000.0;
+/// YIN threshold on the normalized difference; lower demands a cleaner period
+const PITCH_THRESHOLD: f32 = 0.15;
+/// Above this normalized difference the snippet is treated as unpitched
+const MAX_APERIODICITY: f32 = 0.5;
+
+/// Reads `--make-wavetable=<file.wav>` from the command line.
+pub fn snippet_from_args() -> Option<PathBuf> {
+    std::env::args().find_map(|arg| arg.strip_prefix("--make-wavetable=").map(PathBuf::from))
+}
+
+/// Resolves a wavetable name typed by the user: paths that exist are used as
+/// they are, anything else is looked up in the user wavetable folder.
+pub fn resolve_path(name: &str) -> PathBuf {
+    let path = PathBuf::from(name);
+    if path.exists() {
+        return path;
+    }
+    let in_folder = Path::new(WAVETABLE_DIR).join(&path);
+    if in_folder.extension().is_none() {
+        in_folder.with_extension("wav")
+    } else {
+        in_folder
+    }
+}
+
+/// A stack of single-cycle frames, scanned through by the position parameter.
+pub struct Wavetable {
+    /// For each mip level, the frames one after another, each FRAME_SIZE samples long
+    mips: Vec<Vec<f32>>,
+    frames: usize,
+}
+
+impl Wavetable {
+    /// Loads a table from a .wav file holding consecutive 2048-sample frames.
+    /// Multichannel files are mixed to mono, a trailing partial frame is
+    /// dropped and the table is normalized to full scale.
+    pub fn load(path: &Path) -> Result<Self> {
+        let (samples, _) = read_mono(path)?;
+        Self::from_samples(samples)
+    }
+
+    /// Builds a table from a short recording of a pitched sound: the period is
+    /// detected, and up to 64 cycles spread evenly over the snippet are each
+    /// resampled to one frame, so the position control scans through how the
+    /// sound evolves. Returns the table and the detected pitch in Hz.
+    pub fn extract(path: &Path) -> Result<(Self, f32)> {
+        let (audio, sample_rate) = read_mono(path)?;
+        let period = detect_period(&audio, sample_rate as f32)
+            .ok_or_else(|| RustWaveError::NoPitch(path.display().to_string()))?;
+
+        let cycles = ((audio.len() - 1) as f32 / period) as usize - 1;
+        let frames = cycles.min(MAX_EXTRACTED_FRAMES);
+        let mut samples = Vec::with_capacity(frames * FRAME_SIZE);
+        for frame in 0..frames {
+            // Spread the chosen cycles evenly from the first to the last
+            let cycle = if frames > 1 { frame * (cycles - 1) / (frames - 1) } else { 0 };
+            extract_cycle(&audio, cycle as f32 * period, period, &mut samples);
+        }
+        Ok((Self::from_samples(samples)?, sample_rate as f32 / period))
+    }
+
+    /// Writes the table as a mono 32-bit float .wav that `load` reads back.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for &sample in &self.mips[0] {
+            writer.write_sample(sample)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    fn from_samples(mut samples: Vec<f32>) -> Result<Self> {
+        let frames = samples.len() / FRAME_SIZE;
+        if frames == 0 {
+            return Err(RustWaveError::WavetableTooShort(samples.len()));
+        }
+        samples.truncate(frames * FRAME_SIZE);
+
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        if peak > 0.0 {
+            for sample in &mut samples {
+                *sample /= peak;
+            }
+        }
+
+        let mut mips = vec![samples.clone(); MIP_LEVELS];
+        for (frame, original) in samples.chunks(FRAME_SIZE).enumerate() {
+            let mut spectrum_re = original.to_vec();
+            let mut spectrum_im = vec![0.0; FRAME_SIZE];
+            fft(&mut spectrum_re, &mut spectrum_im, false);
+
+            for (level, mip) in mips.iter_mut().enumerate().skip(1) {
+                let mut re = spectrum_re.clone();
+                let mut im = spectrum_im.clone();
+                // Clear every harmonic above the level's limit, on both halves of the spectrum
+                let harmonics = (FRAME_SIZE / 2) >> level;
+                for bin in harmonics + 1..FRAME_SIZE - harmonics {
+                    re[bin] = 0.0;
+                    im[bin] = 0.0;
+                }
+                fft(&mut re, &mut im, true);
+                mip[frame * FRAME_SIZE..(frame + 1) * FRAME_SIZE].copy_from_slice(&re);
+            }
+        }
+        Ok(Self { mips, frames })
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Reads the table at `phase` (0.0-1.0 through the cycle) and `position`
+    /// (0.0-1.0 from the first frame to the last), interpolating linearly
+    /// within and between frames so position sweeps are smooth. `increment` is
+    /// the phase step per sample; it picks the mip level with the most
+    /// harmonics that all stay below Nyquist, so high notes don't alias.
+    pub fn sample(&self, position: f32, phase: f32, increment: f32) -> f32 {
+        let max_harmonics = 0.5 / increment.abs().max(f32::EPSILON);
+        let level = (0..MIP_LEVELS)
+            .find(|&level| ((FRAME_SIZE / 2) >> level) as f32 <= max_harmonics)
+            .unwrap_or(MIP_LEVELS - 1);
+        let samples = &self.mips[level];
+
+        let frame = position.clamp(0.0, 1.0) * (self.frames - 1) as f32;
+        let frame_index = (frame as usize).min(self.frames - 1);
+        let next_frame = (frame_index + 1).min(self.frames - 1);
+        let frame_mix = frame - frame_index as f32;
+
+        let index = phase.rem_euclid(1.0) * FRAME_SIZE as f32;
+        let i0 = (index as usize) % FRAME_SIZE;
+        let i1 = (i0 + 1) % FRAME_SIZE;
+        let mix = index - index.floor();
+
+        let read = |frame: usize| {
+            let base = frame * FRAME_SIZE;
+            samples[base + i0] + (samples[base + i1] - samples[base + i0]) * mix
+        };
+        let current = read(frame_index);
+        if frame_mix > 0.0 {
+            current + (read(next_frame) - current) * frame_mix
+        } else {
+            current
+        }
+    }
+}
+
+/// Reads a .wav file of any sample format, mixed down to mono, along with its
+/// sample rate.
+pub fn read_mono(path: &Path) -> Result<(Vec<f32>, u32)> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let raw: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<std::result::Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<std::result::Result<_, _>>()?
+        }
+    };
+
+    let channels = spec.channels.max(1) as usize;
+    let mono = raw.chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+/// Finds the period of `audio` in samples with the YIN method, measured on a
+/// window from the middle of the snippet to skip the attack. Returns None if
+/// the snippet is too short or has no clear pitch.
+fn detect_period(audio: &[f32], sample_rate: f32) -> Option<f32> {
+    let min_lag = (sample_rate / MAX_PITCH) as usize;
+    let max_lag = (sample_rate / MIN_PITCH) as usize;
+    let window = max_lag;
+    if audio.len() < window + max_lag + 2 {
+        return None;
+    }
+    let start = (audio.len() - window - max_lag - 2) / 2;
+    let audio = &audio[start..];
+
+    // Cumulative mean normalized difference for every lag
+    let mut difference = vec![1.0f32; max_lag + 2];
+    let mut running_sum = 0.0;
+    for lag in 1..difference.len() {
+        let sum: f32 = (0..window).map(|i| (audio[i] - audio[i + lag]).powi(2)).sum();
+        running_sum += sum;
+        difference[lag] = if running_sum > 0.0 { sum * lag as f32 / running_sum } else { 1.0 };
+    }
+
+    // First dip under the threshold, followed down to its minimum, or else
+    // the deepest dip overall
+    let search = min_lag.max(1)..=max_lag;
+    let lag = match search.clone().find(|&lag| difference[lag] < PITCH_THRESHOLD) {
+        Some(mut lag) => {
+            while lag < max_lag && difference[lag + 1] < difference[lag] {
+                lag += 1;
+            }
+            lag
+        }
+        None => search.min_by(|&a, &b| difference[a].total_cmp(&difference[b]))?,
+    };
+    if difference[lag] > MAX_APERIODICITY {
+        return None;
+    }
+
+    // Parabolic interpolation around the dip for a fractional period
+    let (before, at, after) = (difference[lag - 1], difference[lag], difference[lag + 1]);
+    let curvature = before - 2.0 * at + after;
+    let offset = if curvature > 0.0 { 0.5 * (before - after) / curvature } else { 0.0 };
+    Some(lag as f32 + offset.clamp(-0.5, 0.5))
+}
+
+/// Resamples one cycle of `period` samples starting at `start` to a frame,
+/// appending it to `output`. The DC offset is removed and any step between the
+/// cycle's end and its start is spread across the frame so it loops cleanly.
+fn extract_cycle(audio: &[f32], start: f32, period: f32, output: &mut Vec<f32>) {
+    let read = |position: f32| {
+        let index = position as usize;
+        let next = (index + 1).min(audio.len() - 1);
+        let mix = position - index as f32;
+        audio[index] + (audio[next] - audio[index]) * mix
+    };
+    let step = read(start + period) - read(start);
+    let frame_start = output.len();
+    for i in 0..FRAME_SIZE {
+        let progress = i as f32 / FRAME_SIZE as f32;
+        output.push(read(start + progress * period) - step * progress);
+    }
+    let frame = &mut output[frame_start..];
+    let mean = frame.iter().sum::<f32>() / FRAME_SIZE as f32;
+    for sample in frame {
+        *sample -= mean;
+    }
+}
+
+/// In-place radix-2 FFT of a complex signal whose length is a power of two.
+/// The inverse transform is scaled, so a round trip gives back the input.
+fn fft(re: &mut [f32], im: &mut [f32], inverse: bool) {
+    let n = re.len();
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    // Twiddle factors for the full length, strided through by the shorter stages
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let twiddles: Vec<(f32, f32)> = (0..n / 2)
+        .map(|k| {
+            let (sin, cos) = (sign * 2.0 * std::f64::consts::PI * k as f64 / n as f64).sin_cos();
+            (cos as f32, sin as f32)
+        })
+        .collect();
+
+    let mut len = 2;
+    while len <= n {
+        let stride = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_re, w_im) = twiddles[k * stride];
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * w_re - im[b] * w_im;
+                let t_im = re[b] * w_im + im[b] * w_re;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+
+    if inverse {
+        for value in re.iter_mut().chain(im.iter_mut()) {
+            *value /= n as f32;
+        }
+    }
+}
+
+impl fmt::Debug for Wavetable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wavetable").field("frames", &self.frames).finish()
+    }
+}
@@ -0,0 +1,75 @@
+use std::f32::consts::PI;
+
+/// Delay of the side signal behind the dry sound, inside the Haas window so
+/// it widens the image instead of being heard as an echo
+const DELAY_MS: f32 = 11.0;
+/// Below this frequency nothing is widened, so bass stays centered and solid
+const HIGHPASS_HZ: f32 = 250.0;
+
+/// Haas-style stereo widener for mono sources.
+///
+/// A delayed, high-passed copy of the mid signal is added to the left channel
+/// and subtracted from the right. The two copies cancel when the output is
+/// summed to mono, so unlike a plain inter-channel delay it never comb-filters
+/// on mono playback.
+pub struct Widener {
+    /// Mid signal history, long enough for the delay
+    buffer: Vec<f32>,
+    position: usize,
+    /// Side signal level (0.0-1.0), 0.0 bypasses the widener
+    width: f32,
+    /// One-pole high-pass on the delayed signal
+    highpass_coefficient: f32,
+    highpass_input: f32,
+    highpass_output: f32,
+}
+
+impl Widener {
+    pub fn new(sample_rate: f32) -> Self {
+        let delay = (DELAY_MS * 0.001 * sample_rate).round().max(1.0) as usize;
+        Self {
+            buffer: vec![0.0; delay],
+            position: 0,
+            width: 0.0,
+            highpass_coefficient: 1.0 / (1.0 + 2.0 * PI * HIGHPASS_HZ / sample_rate),
+            highpass_input: 0.0,
+            highpass_output: 0.0,
+        }
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        // The history isn't kept while bypassed, so start over from silence
+        if self.width == 0.0 {
+            self.buffer.fill(0.0);
+            self.highpass_input = 0.0;
+            self.highpass_output = 0.0;
+        }
+        self.width = width.clamp(0.0, 1.0);
+    }
+
+    pub fn process(&mut self, left: f32, right: f32) -> (f32, f32) {
+        if self.width == 0.0 {
+            return (left, right);
+        }
+        let mid = (left + right) * 0.5;
+        let delayed = self.buffer[self.position];
+        self.buffer[self.position] = mid;
+        self.position = (self.position + 1) % self.buffer.len();
+
+        self.highpass_output = self.highpass_coefficient * (self.highpass_output + delayed - self.highpass_input);
+        self.highpass_input = delayed;
+
+        let side = self.highpass_output * self.width;
+        (left + side, right - side)
+    }
+
+    /// Widens a block in place.
+    pub fn process_block(&mut self, left: &mut [f32], right: &mut [f32]) {
+        if self.width == 0.0 {
+            return;
+        }
+        for (left, right) in left.iter_mut().zip(right.iter_mut()) {
+            (*left, *right) = self.process(*left, *right);
+        }
+    }
+}